@@ -20,9 +20,9 @@ pub(crate) fn main() {
         vec![]
     };
     let name = &args[1];
-    match lookup_container_pid(&name, &types) {
-        Ok(pid) => {
-            println!("{}", pid);
+    match lookup_container_pid(name, &types) {
+        Ok(process) => {
+            println!("{}", process.pid);
         }
         Err(e) => {
             eprintln!("{}", e);
@@ -1,18 +1,47 @@
-use anyhow::bail;
 use libc::c_char;
 use std::env;
 use std::ffi::CStr;
+use std::fmt::Debug;
+use std::io;
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
 use std::ptr;
 
-use crate::result::Result;
+use crate::result::{Error, Result};
+use crate::LookupOptions;
 
-fn access<P: AsRef<Path>>(path: &P, amode: libc::c_int) -> Result<()> {
+/// Spawns the command line tools the backends fall back to and finds them in `$PATH`.
+///
+/// `SystemRunner` runs the real tools, `ReplayRunner` serves recorded output instead.
+pub trait CommandRunner: Debug + Send + Sync {
+    /// Runs `program` with `args` to completion and returns what it printed and its exit status.
+    fn output(&self, program: &str, args: &[&str]) -> io::Result<Output>;
+    /// Full path of the executable `program`, `None` if it is not installed.
+    fn which(&self, program: &str) -> Option<PathBuf>;
+}
+
+/// Runs the tools installed on this machine.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn output(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        Command::new(program).args(args).output()
+    }
+    fn which(&self, program: &str) -> Option<PathBuf> {
+        find_executable(program)
+    }
+}
+
+fn access<P: AsRef<Path>>(path: &P, amode: libc::c_int) -> io::Result<()> {
     let mut buf = [0u8; libc::PATH_MAX as usize];
     let path = path.as_ref().as_os_str().as_bytes();
     if path.len() >= libc::PATH_MAX as usize {
-        bail!("path too long (exceeds PATH_MAX)");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "path too long (exceeds PATH_MAX)",
+        ));
     }
 
     let cstr = unsafe {
@@ -22,12 +51,12 @@ fn access<P: AsRef<Path>>(path: &P, amode: libc::c_int) -> Result<()> {
 
     let res = unsafe { libc::access(cstr.as_ptr(), amode) };
     if res < 0 {
-        bail!("access check failed with code {}", res)
+        return Err(io::Error::last_os_error());
     }
     Ok(())
 }
 
-pub(crate) fn which<P>(exe_name: P) -> Option<PathBuf>
+fn find_executable<P>(exe_name: P) -> Option<PathBuf>
 where
     P: AsRef<Path>,
 {
@@ -45,3 +74,131 @@ where
             .next()
     })
 }
+
+/// Finds `program` with the runner of `options`.
+pub(crate) fn which(options: &LookupOptions, program: &str) -> Option<PathBuf> {
+    options.runner.which(program)
+}
+
+/// Runs `program` with the runner of `options` and returns its stdout. If the command fails, the
+/// error is classified based on what the tool printed to stderr.
+pub(crate) fn run(options: &LookupOptions, program: &str, args: &[&str]) -> Result<Vec<u8>> {
+    let cmd_str = std::iter::once(program)
+        .chain(args.iter().copied())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let output = options.runner.output(program, args).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            Error::ToolUnavailable(format!("'{}' command is not available", program))
+        } else {
+            Error::io(format!("failed to execute '{}'", cmd_str), e)
+        }
+    })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(classify_failure(&cmd_str, output.status, stderr.trim()));
+    }
+    Ok(output.stdout)
+}
+
+fn classify_failure(cmd_str: &str, status: ExitStatus, stderr: &str) -> Error {
+    let message = format!("'{}' failed ({}): {}", cmd_str, status, stderr);
+    let stderr = stderr.to_lowercase();
+    let contains_any = |patterns: &[&str]| patterns.iter().any(|p| stderr.contains(p));
+
+    // order matters: "permission denied while trying to connect to the docker daemon" is a
+    // permission problem and not an unreachable daemon.
+    if contains_any(&[
+        "permission denied",
+        "access denied",
+        "operation not permitted",
+    ]) {
+        Error::PermissionDenied(message)
+    } else if contains_any(&[
+        "cannot connect",
+        "connection refused",
+        "failed to connect",
+        "unable to connect",
+        "failed to dial",
+        "is the docker daemon running",
+        "socket not accessible",
+    ]) {
+        Error::DaemonUnreachable(message)
+    } else if contains_any(&["command not found", "executable file not found"]) {
+        // checked before "not found", a tool that runs another one may be missing it
+        Error::ToolUnavailable(message)
+    } else if contains_any(&["multiple ids found", "more than one"]) {
+        Error::Ambiguous {
+            candidates: candidate_ids(&stderr),
+            message,
+        }
+    } else if contains_any(&[
+        "no such",
+        "not found",
+        "does not exist",
+        "doesn't exist",
+        "no machine",
+    ]) {
+        Error::NotFound(message)
+    } else {
+        Error::CommandFailed(message)
+    }
+}
+
+/// The full container IDs a tool names in its message about an ambiguous prefix, in the order
+/// they are printed. Tools that only repeat the prefix yield no candidates.
+fn candidate_ids(stderr: &str) -> Vec<String> {
+    let mut ids: Vec<String> = vec![];
+    let words = stderr.split(|c: char| !c.is_ascii_hexdigit());
+    for word in words.filter(|w| w.len() >= 12) {
+        if !ids.iter().any(|id| id == word) {
+            ids.push(word.to_string());
+        }
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn classify(stderr: &str) -> Error {
+        classify_failure("tool", ExitStatus::from_raw(1 << 8), stderr)
+    }
+
+    #[test]
+    fn missing_tool_is_not_a_missing_container() {
+        let err = classify("sh: 1: runc: command not found");
+        assert!(matches!(err, Error::ToolUnavailable(_)), "{:?}", err);
+        let err = classify("Error: No such container: web");
+        assert!(matches!(err, Error::NotFound(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn ambiguous_candidates() {
+        let err = classify(
+            "multiple IDs found with provided prefix: 8d\n\
+             8d043ca8a27eef30f2f28a611bca0e08ce6cb2f7eb5a2028574f724ccb740126\n\
+             8d416d82ef8710d51cd76c7f3cbbf8cee04075fc2f7649aeeeaf1425b063b331",
+        );
+        match err {
+            Error::Ambiguous { candidates, .. } => assert_eq!(
+                candidates,
+                vec![
+                    "8d043ca8a27eef30f2f28a611bca0e08ce6cb2f7eb5a2028574f724ccb740126",
+                    "8d416d82ef8710d51cd76c7f3cbbf8cee04075fc2f7649aeeeaf1425b063b331",
+                ]
+            ),
+            err => panic!("{:?}", err),
+        }
+        let err =
+            classify("Error response from daemon: multiple IDs found with provided prefix: 8d");
+        assert!(
+            matches!(&err, Error::Ambiguous { candidates, .. } if candidates.is_empty()),
+            "{:?}",
+            err
+        );
+    }
+}
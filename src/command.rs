@@ -1,23 +1,76 @@
-use anyhow::{bail, Context};
+use libc::pid_t;
 use std::fs;
+use std::path::Path;
 
-use crate::result::Result;
-use crate::Container;
+use crate::result::{Error, Result};
+use crate::{Container, ContainerProcess, LookupOptions};
+
+/// Our own PID as seen in `proc_root`, which may belong to another PID namespace.
+fn own_pid(proc_root: &Path) -> pid_t {
+    fs::read_link(proc_root.join("self"))
+        .ok()
+        .and_then(|pid| pid.to_str()?.parse().ok())
+        .unwrap_or(std::process::id() as pid_t)
+}
+
+/// Parent of `pid` from `<proc_root>/<pid>/stat`, `None` once the process is gone.
+fn parent_pid(proc_root: &Path, pid: pid_t) -> Option<pid_t> {
+    let stat = fs::read_to_string(proc_root.join(pid.to_string()).join("stat")).ok()?;
+    // the process name may contain spaces and parentheses, the state and ppid follow it
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    fields.nth(1)?.parse().ok()
+}
+
+/// PIDs of the ancestors of `pid`, closest first.
+fn ancestors(proc_root: &Path, mut pid: pid_t) -> Vec<pid_t> {
+    let mut ancestors = vec![];
+    while let Some(ppid) = parent_pid(proc_root, pid) {
+        if ppid <= 0 || ancestors.contains(&ppid) {
+            break;
+        }
+        ancestors.push(ppid);
+        pid = ppid;
+    }
+    ancestors
+}
 
 #[derive(Clone, Debug)]
 pub(crate) struct Command {}
 
 impl Container for Command {
-    fn lookup(&self, container_id: &str) -> Result<libc::pid_t> {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        // blanks would match every command line with more than one argument
+        if container_id.trim().is_empty() {
+            return Err(Error::NotFound(String::from(
+                "an empty command line query matches no process",
+            )));
+        }
         let needle = container_id.as_bytes();
-        let dir =
-            fs::read_dir("/proc").context("failed to read /proc directory - is procfs mounted?")?;
-        let own_pid = std::process::id() as libc::pid_t;
+        let proc_root = &options.proc_root;
+        let dir = fs::read_dir(proc_root).map_err(|e| {
+            Error::io(
+                format!(
+                    "failed to read {} directory - is procfs mounted?",
+                    proc_root.display()
+                ),
+                e,
+            )
+        })?;
+        let own_pid = own_pid(proc_root);
+        let mut matches = vec![];
 
         for entry in dir {
-            let entry = entry.context("failed to read entry in /proc")?;
+            let entry = entry.map_err(|e| {
+                Error::io(
+                    format!("failed to read entry in {}", proc_root.display()),
+                    e,
+                )
+            })?;
             let cmdline = entry.path().join("cmdline");
-            let pid = match entry.file_name().to_string_lossy().parse::<libc::pid_t>() {
+            let pid = match entry.file_name().to_string_lossy().parse::<pid_t>() {
                 Ok(pid) => pid,
                 _ => {
                     continue;
@@ -39,17 +92,56 @@ impl Container for Command {
                     .windows(needle.len())
                     .any(|window| window == needle)
                 {
-                    return Ok(pid);
+                    let cmdline = String::from_utf8_lossy(&arguments);
+                    matches.push((pid, cmdline.trim_end().to_string()));
                 }
             }
         }
 
-        bail!(
-            "no process found with command line matching '{}'",
-            container_id
-        )
+        // whoever started us, e.g. sudo or a shell, has the query in its command line as well
+        let own_ancestors = ancestors(proc_root, own_pid);
+        matches.retain(|(pid, _)| !own_ancestors.contains(pid));
+        // workers of a matching supervisor match too, the topmost process wins
+        let pids = matches.iter().map(|(pid, _)| *pid).collect::<Vec<_>>();
+        matches.retain(|(pid, _)| {
+            !ancestors(proc_root, *pid)
+                .iter()
+                .any(|ancestor| pids.contains(ancestor))
+        });
+        matches.sort();
+
+        if matches.len() > 1 {
+            return Err(Error::Ambiguous {
+                message: format!(
+                    "several unrelated processes have a command line matching '{}'",
+                    container_id
+                ),
+                candidates: matches
+                    .iter()
+                    .map(|(pid, cmdline)| format!("{} (PID {})", cmdline, pid))
+                    .collect(),
+            });
+        }
+        match matches.pop() {
+            Some((pid, cmdline)) => Ok(ContainerProcess::new(
+                self.name(),
+                pid.to_string(),
+                cmdline,
+                pid,
+                container_id,
+                proc_root,
+            )),
+            None => Err(Error::NotFound(format!(
+                "no process found with command line matching '{}'",
+                container_id
+            ))),
+        }
+    }
+    fn list(&self, _options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        // arbitrary processes are not containers, so there is nothing to enumerate
+        Ok(vec![])
     }
-    fn check_required_tools(&self) -> Result<()> {
+    fn check_required_tools(&self, _options: &LookupOptions) -> Result<()> {
         Ok(())
     }
 }
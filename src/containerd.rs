@@ -1,66 +1,395 @@
-use anyhow::{bail, Context};
 use libc::pid_t;
-use std::process::Command;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
 use crate::cmd;
-use crate::result::Result;
-use crate::Container;
+use crate::result::{Error, Result};
+use crate::{Container, ContainerProcess, LookupOptions};
 
 #[derive(Clone, Debug)]
 pub(crate) struct Containerd {}
 
-impl Container for Containerd {
-    fn lookup(&self, container_id: &str) -> Result<pid_t> {
-        let output = Command::new("ctr")
-            .args(&["task", "list"])
-            .output()
-            .context("failed to execute 'ctr task list'")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!(
-                "containerd task list failed (exit status {}): {}",
-                output.status,
-                stderr.trim_end()
-            );
+/// Per-task state written by the runtime v2 shims, one directory per namespace and task.
+const TASK_STATE_DIR: &str = "/run/containerd/io.containerd.runtime.v2.task";
+
+/// Annotations that carry a human readable container name.
+const NAME_ANNOTATIONS: &[&str] = &["io.kubernetes.cri.container-name", "nerdctl/name"];
+
+/// The namespace set for `ctr`, all namespaces are searched if there is none.
+fn env_namespace() -> Option<String> {
+    env::var("CONTAINERD_NAMESPACE")
+        .ok()
+        .filter(|namespace| !namespace.is_empty())
+}
+
+fn state_dir(options: &LookupOptions) -> Option<PathBuf> {
+    Some(options.state_path(TASK_STATE_DIR)).filter(|dir| dir.is_dir())
+}
+
+/// Namespace and task IDs are used as directory names and never contain slashes.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+/// Namespaces that have tasks in the state directory or that containerd knows about, sorted.
+fn all_namespaces(options: &LookupOptions, state_dir: Option<&Path>) -> Result<Vec<String>> {
+    let mut namespaces = vec![];
+    if let Some(state_dir) = state_dir {
+        let entries = fs::read_dir(state_dir)
+            .map_err(|e| Error::io(format!("failed to read '{}'", state_dir.display()), e))?;
+        for entry in entries {
+            let entry = entry
+                .map_err(|e| Error::io(format!("failed to read '{}'", state_dir.display()), e))?;
+            namespaces.push(entry.file_name().to_string_lossy().into_owned());
         }
+    }
+    if cmd::which(options, "ctr").is_some() {
+        // $ ctr namespaces list -q
+        // default
+        // k8s.io
+        let stdout = cmd::run(options, "ctr", &["namespaces", "list", "-q"])?;
+        let stdout = String::from_utf8_lossy(&stdout);
+        namespaces.extend(
+            stdout
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from),
+        );
+    }
+    namespaces.sort();
+    namespaces.dedup();
+    Ok(namespaces)
+}
+
+/// Converts `linux.cgroupsPath` from an OCI config to the name of the cgroup directory.
+/// The systemd cgroup driver uses `slice:prefix:name`, which ends up as `prefix-name.scope`.
+fn cgroup_name(cgroups_path: &str) -> Option<String> {
+    let fields: Vec<&str> = cgroups_path.split(':').collect();
+    match fields.as_slice() {
+        [_slice, prefix, name] if !prefix.is_empty() => Some(format!("{}-{}.scope", prefix, name)),
+        [_slice, _, name] => Some(format!("{}.scope", name)),
+        _ => cgroups_path
+            .rsplit('/')
+            .find(|part| !part.is_empty())
+            .map(String::from),
+    }
+}
 
-        // $ ctr task list
-        // TASK    PID      STATUS
-        // v2      17515    RUNNING
-        // v1      14602    RUNNING
-        let mut lines = output.stdout.split(|&c| c == b'\n');
-        lines.next(); // skip header
-        let pid_str = lines.find_map(|line| {
-            let line_str = String::from_utf8_lossy(&line);
+/// Checks that `pid` is still the init process of the task, its PID might have been reused.
+fn check_task_pid(proc_root: &Path, pid: pid_t, config: &Value, task_id: &str) -> Result<()> {
+    let proc_dir = proc_root.join(pid.to_string());
+    let cgroups = match fs::read_to_string(proc_dir.join("cgroup")) {
+        Ok(cgroups) => cgroups,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(Error::NotRunning(format!(
+                "containerd task '{}' is not running (process {} has exited)",
+                task_id, pid
+            )))
+        }
+        Err(e) => {
+            return Err(Error::io(
+                format!("failed to read cgroup of process {}", pid),
+                e,
+            ))
+        }
+    };
+    let expected = match config["linux"]["cgroupsPath"]
+        .as_str()
+        .and_then(cgroup_name)
+    {
+        Some(name) => name,
+        // nothing to compare against
+        None => return Ok(()),
+    };
+    // 0::/system.slice/cri-containerd-<id>.scope
+    let matches = cgroups.lines().any(|line| {
+        line.rsplit(':')
+            .next()
+            .is_some_and(|path| path.split('/').any(|part| part == expected))
+    });
+    if matches {
+        Ok(())
+    } else {
+        Err(Error::NotRunning(format!(
+            "containerd task '{}' is not running (PID {} was reused)",
+            task_id, pid
+        )))
+    }
+}
+
+/// Resolves a task from the `init.pid` and `config.json` files in its bundle directory.
+fn lookup_task_state(
+    proc_root: &Path,
+    task_dir: &Path,
+    task_id: &str,
+    query: &str,
+) -> Result<ContainerProcess> {
+    let pid_file = task_dir.join("init.pid");
+    let pid = match fs::read_to_string(&pid_file) {
+        Ok(pid) => pid,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let err = if task_dir.exists() {
+                // the shim writes init.pid once the task was created
+                Error::NotRunning(format!("containerd task '{}' is not running", task_id))
+            } else {
+                Error::NotFound(format!("no containerd task found with id '{}'", task_id))
+            };
+            return Err(err);
+        }
+        Err(e) => {
+            return Err(Error::io(
+                format!("failed to read '{}'", pid_file.display()),
+                e,
+            ))
+        }
+    };
+    let pid = pid.trim().parse::<pid_t>().map_err(|e| {
+        Error::ParseError(format!(
+            "invalid PID '{}' in '{}': {}",
+            pid.trim(),
+            pid_file.display(),
+            e
+        ))
+    })?;
+
+    let config_file = task_dir.join("config.json");
+    let config = match fs::read(&config_file) {
+        Ok(config) => serde_json::from_slice(&config).map_err(|e| {
+            Error::ParseError(format!(
+                "invalid JSON in '{}': {}",
+                config_file.display(),
+                e
+            ))
+        })?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Value::Null,
+        Err(e) => {
+            return Err(Error::io(
+                format!("failed to read '{}'", config_file.display()),
+                e,
+            ))
+        }
+    };
+    check_task_pid(proc_root, pid, &config, task_id)?;
+
+    let name = NAME_ANNOTATIONS
+        .iter()
+        .find_map(|key| config["annotations"][*key].as_str())
+        .unwrap_or(task_id);
+    Ok(ContainerProcess::new(
+        "containerd",
+        task_id,
+        name,
+        pid,
+        query,
+        proc_root,
+    ))
+}
+
+fn list_task_state(
+    proc_root: &Path,
+    state_dir: &Path,
+    namespace: &str,
+) -> Result<Vec<ContainerProcess>> {
+    let dir = state_dir.join(namespace);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(Error::io(format!("failed to read '{}'", dir.display()), e)),
+    };
+    let mut processes = vec![];
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| Error::io(format!("failed to read '{}'", dir.display()), e))?;
+        let id = entry.file_name().to_string_lossy().into_owned();
+        let query = format!("{}/{}", namespace, id);
+        match lookup_task_state(proc_root, &entry.path(), &id, &query) {
+            Ok(process) => processes.push(process),
+            Err(Error::NotFound(_)) | Err(Error::NotRunning(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(processes)
+}
+
+struct Task {
+    id: String,
+    pid: String,
+    status: String,
+}
+
+impl Task {
+    fn into_process(self, query: &str, proc_root: &Path) -> Result<ContainerProcess> {
+        if self.status != "RUNNING" {
+            return Err(Error::NotRunning(format!(
+                "containerd task '{}' is not running (status: {})",
+                self.id, self.status
+            )));
+        }
+        let pid = self.pid.parse::<pid_t>().map_err(|e| {
+            Error::ParseError(format!(
+                "invalid PID '{}' from containerd for container '{}': {}",
+                self.pid, self.id, e
+            ))
+        })?;
+        // containerd has no separate notion of a container name
+        Ok(ContainerProcess::new(
+            "containerd",
+            self.id.clone(),
+            self.id,
+            pid,
+            query,
+            proc_root,
+        ))
+    }
+}
+
+fn list_tasks(options: &LookupOptions, namespace: &str) -> Result<Vec<Task>> {
+    let stdout = cmd::run(options, "ctr", &["-n", namespace, "task", "list"])?;
+
+    // $ ctr -n default task list
+    // TASK    PID      STATUS
+    // v2      17515    RUNNING
+    // v1      14602    RUNNING
+    let mut lines = stdout.split(|&c| c == b'\n');
+    lines.next(); // skip header
+    Ok(lines
+        .filter_map(|line| {
+            let line_str = String::from_utf8_lossy(line);
             let cols = line_str.split_whitespace().collect::<Vec<&str>>();
             if cols.len() != 3 {
                 return None;
             }
+            Some(Task {
+                id: String::from(cols[0]),
+                pid: String::from(cols[1]),
+                status: String::from(cols[2]),
+            })
+        })
+        .collect())
+}
 
-            if cols[0] == container_id {
-                Some(String::from(cols[1]))
-            } else {
-                None
+/// Resolves the task `id` of one namespace.
+fn lookup_namespace(
+    options: &LookupOptions,
+    state_dir: Option<&Path>,
+    namespace: &str,
+    id: &str,
+    query: &str,
+) -> Result<ContainerProcess> {
+    if let Some(state_dir) = state_dir {
+        let task_dir = state_dir.join(namespace).join(id);
+        match lookup_task_state(&options.proc_root, &task_dir, id, query) {
+            // tasks of the legacy v1 runtime are only known to containerd itself
+            Err(Error::NotFound(_)) if cmd::which(options, "ctr").is_some() => {}
+            res => return res,
+        }
+    }
+
+    match list_tasks(options, namespace)?
+        .into_iter()
+        .find(|t| t.id == id)
+    {
+        Some(task) => task.into_process(query, &options.proc_root),
+        None => Err(Error::NotFound(format!(
+            "no containerd task found with id '{}' in namespace '{}'",
+            id, namespace
+        ))),
+    }
+}
+
+impl Container for Containerd {
+    fn name(&self) -> &'static str {
+        "containerd"
+    }
+    /// Accepts `namespace/id`, or just the task ID to search the namespace from
+    /// `$CONTAINERD_NAMESPACE` or else all namespaces.
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        let (namespace, id) = match container_id.split_once('/') {
+            Some((namespace, id)) => (Some(namespace), id),
+            None => (None, container_id),
+        };
+        // don't let the ID escape the state directory
+        if !valid_name(id) || !namespace.is_none_or(valid_name) {
+            return Err(Error::NotFound(format!(
+                "invalid containerd task id '{}'",
+                container_id
+            )));
+        }
+
+        let state_dir = state_dir(options);
+        let namespaces = match namespace.map(String::from).or_else(env_namespace) {
+            Some(namespace) => vec![namespace],
+            None => all_namespaces(options, state_dir.as_deref())?,
+        };
+        let mut found = vec![];
+        let mut not_running = None;
+        for namespace in &namespaces {
+            match lookup_namespace(options, state_dir.as_deref(), namespace, id, container_id) {
+                Ok(process) => found.push((namespace, process)),
+                Err(Error::NotFound(_)) if namespaces.len() > 1 => {}
+                Err(e @ Error::NotRunning(_)) if namespaces.len() > 1 => {
+                    not_running.get_or_insert(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if found.len() > 1 {
+            return Err(Error::Ambiguous {
+                message: format!(
+                    "containerd task '{}' exists in several namespaces",
+                    container_id
+                ),
+                candidates: found
+                    .iter()
+                    .map(|(namespace, process)| format!("{}/{}", namespace, process.id))
+                    .collect(),
+            });
+        }
+        match found.pop() {
+            Some((_, process)) => Ok(process),
+            None => Err(not_running.unwrap_or_else(|| {
+                Error::NotFound(format!(
+                    "no containerd task found with id '{}' in any namespace",
+                    container_id
+                ))
+            })),
+        }
+    }
+    fn list(&self, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        let state_dir = state_dir(options);
+        let namespaces = match env_namespace() {
+            Some(namespace) => vec![namespace],
+            None => all_namespaces(options, state_dir.as_deref())?,
+        };
+        let mut processes = vec![];
+        for namespace in &namespaces {
+            if let Some(state_dir) = &state_dir {
+                if cmd::which(options, "ctr").is_none() {
+                    processes.extend(list_task_state(&options.proc_root, state_dir, namespace)?);
+                    continue;
+                }
             }
-        });
-        match pid_str {
-            Some(pid_str) => pid_str.parse::<pid_t>().with_context(|| {
-                format!(
-                    "invalid PID '{}' from containerd for container '{}'",
-                    pid_str, container_id
-                )
-            }),
-            None => {
-                bail!("no containerd task found with id '{}'", container_id)
+            for task in list_tasks(options, namespace)? {
+                if task.status == "RUNNING" {
+                    let query = format!("{}/{}", namespace, task.id);
+                    processes.push(task.into_process(&query, &options.proc_root)?);
+                }
             }
         }
+        Ok(processes)
     }
-    fn check_required_tools(&self) -> Result<()> {
-        if cmd::which("ctr").is_some() {
+    fn check_required_tools(&self, options: &LookupOptions) -> Result<()> {
+        if state_dir(options).is_some() || cmd::which(options, "ctr").is_some() {
             Ok(())
         } else {
-            bail!("containerd runtime not found: 'ctr' command is not available")
+            Err(Error::ToolUnavailable(String::from(
+                "containerd runtime not found: no task state directory and 'ctr' command is not available",
+            )))
         }
     }
 }
@@ -0,0 +1,540 @@
+//! Resolves containers through the Container Runtime Interface (CRI) that containerd and CRI-O
+//! serve to the kubelet.
+//!
+//! Accepts container IDs, pod sandbox IDs and `pod/container` or `namespace/pod/container` names.
+//! The runtime socket can be set in `CONTAINER_RUNTIME_ENDPOINT`, like for `crictl`.
+//!
+//! The runtime socket is queried over gRPC directly. Built without the default `cri-grpc` feature,
+//! `crictl` is run instead.
+
+use libc::pid_t;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[cfg(not(feature = "cri-grpc"))]
+use crate::cmd;
+#[cfg(feature = "cri-grpc")]
+use crate::cri_grpc as runtime;
+#[cfg(not(feature = "cri-grpc"))]
+use crate::crictl as runtime;
+use crate::result::{Error, Result};
+use crate::{Container, ContainerProcess, LookupOptions};
+
+#[derive(Clone, Debug)]
+pub(crate) struct Cri {}
+
+const DEFAULT_ENDPOINTS: &[&str] = &[
+    "/run/containerd/containerd.sock",
+    "/var/run/crio/crio.sock",
+    "/var/run/cri-dockerd.sock",
+];
+
+const POD_NAMESPACE_LABEL: &str = "io.kubernetes.pod.namespace";
+const POD_NAME_LABEL: &str = "io.kubernetes.pod.name";
+const CONTAINER_NAME_LABEL: &str = "io.kubernetes.container.name";
+const POD_UID_LABEL: &str = "io.kubernetes.pod.uid";
+
+/// Status of a container or pod sandbox, with the verbose info of the runtime.
+pub(crate) struct Status {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    /// Namespace of a pod sandbox, empty for containers.
+    pub(crate) namespace: String,
+    /// Whether the container is running or the sandbox is ready.
+    pub(crate) running: bool,
+    pub(crate) labels: HashMap<String, String>,
+    pub(crate) info: Value,
+}
+
+/// A ready pod sandbox.
+pub(crate) struct Sandbox {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) namespace: String,
+}
+
+/// A container as listed by `ListContainers`.
+pub(crate) struct Listed {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) running: bool,
+    pub(crate) labels: HashMap<String, String>,
+}
+
+/// Selects the containers of `ListContainers`.
+#[derive(Default)]
+pub(crate) struct Filter<'a> {
+    pub(crate) running_only: bool,
+    pub(crate) sandbox_id: Option<&'a str>,
+    pub(crate) labels: Vec<(&'a str, &'a str)>,
+}
+
+pub(crate) fn endpoints(options: &LookupOptions) -> Vec<PathBuf> {
+    let mut candidates = vec![];
+    if let Ok(endpoint) = env::var("CONTAINER_RUNTIME_ENDPOINT") {
+        let path = endpoint.strip_prefix("unix://").unwrap_or(&endpoint);
+        candidates.push(PathBuf::from(path));
+    }
+    candidates.extend(
+        DEFAULT_ENDPOINTS
+            .iter()
+            .map(|socket| options.state_path(socket)),
+    );
+
+    let mut sockets: Vec<PathBuf> = vec![];
+    for path in candidates {
+        if path.exists() && !sockets.contains(&path) {
+            sockets.push(path);
+        }
+    }
+    sockets
+}
+
+/// Whether the runtime behind a socket can't answer at all, because it is not running or doesn't
+/// serve CRI, e.g. containerd with the CRI plugin disabled, or because `crictl` is missing. Such
+/// sockets are skipped.
+pub(crate) fn runtime_unavailable(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::DaemonUnreachable(_) | Error::Unsupported(_) | Error::ToolUnavailable(_)
+    )
+}
+
+/// The most relevant of the errors of several sockets: a missing container first, then a runtime
+/// that is not running, then a runtime without CRI.
+fn most_relevant(errors: Vec<Error>) -> Error {
+    errors
+        .into_iter()
+        .rev()
+        .max_by_key(|e| match e {
+            Error::NotFound(_) => 2,
+            Error::DaemonUnreachable(_) => 1,
+            _ => 0,
+        })
+        .unwrap_or_else(|| Error::ToolUnavailable(String::from("no CRI runtime socket found")))
+}
+
+/// Collects the results of all runtime sockets, skipping unavailable runtimes. Fails only if
+/// there are sockets and none of them answered.
+fn each_socket<T>(
+    options: &LookupOptions,
+    mut f: impl FnMut(&Path) -> Result<Vec<T>>,
+) -> Result<Vec<T>> {
+    let mut items = vec![];
+    let mut answered = false;
+    let mut errors = vec![];
+    for socket in endpoints(options) {
+        match f(&socket) {
+            Ok(mut found) => {
+                answered = true;
+                items.append(&mut found);
+            }
+            Err(e) if runtime_unavailable(&e) => errors.push(e),
+            Err(e) => return Err(e),
+        }
+    }
+    if answered || errors.is_empty() {
+        Ok(items)
+    } else {
+        Err(most_relevant(errors))
+    }
+}
+
+/// `namespace/pod/container` from the labels the kubelet sets, the CRI name otherwise.
+fn display_name(labels: &HashMap<String, String>, fallback: String) -> String {
+    let parts: Vec<&str> = [POD_NAMESPACE_LABEL, POD_NAME_LABEL, CONTAINER_NAME_LABEL]
+        .iter()
+        .filter_map(|label| labels.get(*label).map(String::as_str))
+        .collect();
+    if parts.len() < 2 {
+        fallback
+    } else {
+        parts.join("/")
+    }
+}
+
+/// Reads the PID from the verbose info of a status response.
+fn info_pid(info: &Value, what: &str) -> Result<pid_t> {
+    info["pid"]
+        .as_i64()
+        .map(|pid| pid as pid_t)
+        .ok_or_else(|| Error::ParseError(format!("runtime reported no PID for {}", what)))
+}
+
+fn container_status(
+    socket: &Path,
+    container_id: &str,
+    query: &str,
+    options: &LookupOptions,
+) -> Result<ContainerProcess> {
+    let status = runtime::container_status(socket, container_id, options)?;
+    let name = display_name(&status.labels, status.name);
+    if !status.running {
+        return Err(Error::NotRunning(format!(
+            "CRI container '{}' is not running",
+            name
+        )));
+    }
+    let pid = info_pid(&status.info, &format!("container '{}'", name))?;
+    if pid <= 0 {
+        return Err(Error::NotRunning(format!(
+            "CRI container '{}' is not running",
+            name
+        )));
+    }
+    Ok(ContainerProcess::new(
+        "cri",
+        status.id,
+        name,
+        pid,
+        query,
+        &options.proc_root,
+    ))
+}
+
+/// Resolves a pod sandbox to the process holding its namespaces, i.e. the pause container.
+fn sandbox_status(
+    socket: &Path,
+    sandbox_id: &str,
+    options: &LookupOptions,
+) -> Result<ContainerProcess> {
+    let status = runtime::sandbox_status(socket, sandbox_id, options)?;
+    let name = format!("{}/{}", status.namespace, status.name);
+    if !status.running {
+        return Err(Error::NotRunning(format!(
+            "CRI pod sandbox '{}' is not ready",
+            name
+        )));
+    }
+    let pid = info_pid(&status.info, &format!("pod sandbox '{}'", name))?;
+    Ok(ContainerProcess::new(
+        "cri",
+        status.id,
+        name,
+        pid,
+        sandbox_id,
+        &options.proc_root,
+    ))
+}
+
+/// A running container the kubelet created, identified by the labels it puts on containers.
+#[derive(Debug)]
+pub(crate) struct KubernetesContainer {
+    pub(crate) id: String,
+    /// The name the runtime reports, which the kubelet uses as the scheme of container IDs in
+    /// the pod status, e.g. `containerd` or `cri-o`.
+    pub(crate) runtime: String,
+    pub(crate) namespace: String,
+    pub(crate) pod: String,
+    pub(crate) pod_uid: String,
+    pub(crate) container: String,
+}
+
+/// Running Kubernetes containers of all runtimes that answer, optionally only those of one pod.
+pub(crate) fn kubernetes_containers(
+    pod: Option<(&str, &str)>,
+    options: &LookupOptions,
+) -> Result<Vec<KubernetesContainer>> {
+    let mut filter = Filter {
+        running_only: true,
+        ..Filter::default()
+    };
+    if let Some((namespace, name)) = pod {
+        filter.labels = vec![(POD_NAMESPACE_LABEL, namespace), (POD_NAME_LABEL, name)];
+    }
+
+    each_socket(options, |socket| {
+        let runtime = runtime::runtime_name(socket, options)?;
+        let mut containers = vec![];
+        for item in runtime::list_containers(socket, &filter, options)? {
+            let labels = item.labels;
+            let label = |name: &str| labels.get(name).cloned().unwrap_or_default();
+            // containers not created by the kubelet have no pod
+            if !labels.contains_key(POD_UID_LABEL) {
+                continue;
+            }
+            containers.push(KubernetesContainer {
+                id: item.id,
+                runtime: runtime.clone(),
+                namespace: label(POD_NAMESPACE_LABEL),
+                pod: label(POD_NAME_LABEL),
+                pod_uid: label(POD_UID_LABEL),
+                container: label(CONTAINER_NAME_LABEL),
+            });
+        }
+        Ok(containers)
+    })
+}
+
+/// Resolves `pod/container` or `namespace/pod/container`.
+fn lookup_name(socket: &Path, query: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+    let parts: Vec<&str> = query.split('/').collect();
+    let (namespace, pod, container) = match parts.as_slice() {
+        [pod, container] => (None, *pod, *container),
+        [namespace, pod, container] => (Some(*namespace), *pod, *container),
+        _ => {
+            return Err(Error::NotFound(format!(
+                "'{}' is not a CRI container name, expected [namespace/]pod/container",
+                query
+            )))
+        }
+    };
+
+    let sandboxes = runtime::list_sandboxes(socket, options)?;
+    let sandboxes = sandboxes
+        .iter()
+        .filter(|s| s.name == pod && namespace.is_none_or(|ns| ns == s.namespace));
+    let mut running = vec![];
+    let mut exists = false;
+    for sandbox in sandboxes {
+        let filter = Filter {
+            sandbox_id: Some(&sandbox.id),
+            ..Filter::default()
+        };
+        for listed in runtime::list_containers(socket, &filter, options)? {
+            if listed.name != container {
+                continue;
+            }
+            exists = true;
+            if listed.running {
+                running.push((sandbox, listed.id));
+            }
+        }
+    }
+
+    if running.len() > 1 {
+        return Err(Error::Ambiguous {
+            message: format!("'{}' matches containers in several pods", query),
+            candidates: running
+                .iter()
+                .map(|(sandbox, id)| {
+                    format!("{}/{}/{} ({})", sandbox.namespace, pod, container, id)
+                })
+                .collect(),
+        });
+    }
+    match running.pop() {
+        Some((_, id)) => container_status(socket, &id, query, options),
+        None if exists => Err(Error::NotRunning(format!(
+            "CRI container '{}' is not running",
+            query
+        ))),
+        None => Err(Error::NotFound(format!(
+            "no CRI container '{}' found via '{}'",
+            query,
+            socket.display()
+        ))),
+    }
+}
+
+fn lookup_id(socket: &Path, id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+    match container_status(socket, id, id, options) {
+        Err(Error::NotFound(_)) => sandbox_status(socket, id, options).map_err(|e| match e {
+            Error::NotFound(_) => Error::NotFound(format!(
+                "no CRI container or pod sandbox '{}' found via '{}'",
+                id,
+                socket.display()
+            )),
+            e => e,
+        }),
+        res => res,
+    }
+}
+
+fn list_socket(socket: &Path, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+    let filter = Filter {
+        running_only: true,
+        ..Filter::default()
+    };
+    let mut processes = vec![];
+    for listed in runtime::list_containers(socket, &filter, options)? {
+        match container_status(socket, &listed.id, &listed.id, options) {
+            Ok(process) => processes.push(process),
+            // the container stopped in the meantime
+            Err(Error::NotFound(_)) | Err(Error::NotRunning(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(processes)
+}
+
+impl Container for Cri {
+    fn name(&self) -> &'static str {
+        "cri"
+    }
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        let mut errors = vec![];
+        for socket in endpoints(options) {
+            let res = if container_id.contains('/') {
+                lookup_name(&socket, container_id, options)
+            } else {
+                lookup_id(&socket, container_id, options)
+            };
+            match res {
+                Err(e) if matches!(e, Error::NotFound(_)) || runtime_unavailable(&e) => {
+                    errors.push(e)
+                }
+                res => return res,
+            }
+        }
+        Err(most_relevant(errors))
+    }
+    fn list(&self, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        each_socket(options, |socket| list_socket(socket, options))
+    }
+    fn check_required_tools(&self, options: &LookupOptions) -> Result<()> {
+        if endpoints(options).is_empty() {
+            return Err(Error::ToolUnavailable(String::from(
+                "CRI runtime not found: no containerd, CRI-O or cri-dockerd socket exists",
+            )));
+        }
+        #[cfg(not(feature = "cri-grpc"))]
+        {
+            if cmd::which(options, "crictl").is_none() {
+                return Err(Error::ToolUnavailable(String::from(
+                    "CRI runtime not found: 'crictl' command is not available",
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "cri-grpc"))]
+mod tests {
+    use super::*;
+    use crate::cri_grpc::{CONTAINER_RUNNING, KUBELET_API_VERSION};
+    use crate::grpc::tests::{serve, Handler, Reply};
+    use crate::protobuf::{Encoder, Message};
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+
+    const CONTAINER_ID: &str = "5e0c5c3a2f8b";
+
+    fn labels(mut message: Encoder, field: u32) -> Encoder {
+        for (key, value) in &[
+            (POD_NAMESPACE_LABEL, "default"),
+            (POD_NAME_LABEL, "web"),
+            (CONTAINER_NAME_LABEL, "nginx"),
+            (POD_UID_LABEL, "8d7c6b5a"),
+        ] {
+            message = message.message(field, Encoder::new().string(1, key).string(2, value));
+        }
+        message
+    }
+
+    /// A CRI-O with one running container of a pod.
+    fn crio(method: &str, request: &[u8]) -> Reply {
+        let request = Message::decode(request).unwrap();
+        let response = match method {
+            "/runtime.v1.RuntimeService/Version" => Encoder::new()
+                .string(1, KUBELET_API_VERSION)
+                .string(2, "cri-o")
+                .string(3, "1.30.4"),
+            "/runtime.v1.RuntimeService/ListContainers" => {
+                let container = Encoder::new()
+                    .string(1, CONTAINER_ID)
+                    .message(3, Encoder::new().string(1, "nginx"))
+                    .varint(6, CONTAINER_RUNNING);
+                Encoder::new().message(1, labels(container, 8))
+            }
+            "/runtime.v1.RuntimeService/ContainerStatus" => {
+                if request.string(1).unwrap() != CONTAINER_ID {
+                    return Err((5, "container not found"));
+                }
+                let status = Encoder::new()
+                    .string(1, CONTAINER_ID)
+                    .message(2, Encoder::new().string(1, "nginx"))
+                    .varint(3, CONTAINER_RUNNING);
+                let info = Encoder::new()
+                    .string(1, "info")
+                    .string(2, r#"{"pid": 4242}"#);
+                Encoder::new()
+                    .message(1, labels(status, 12))
+                    .message(2, info)
+            }
+            "/runtime.v1.RuntimeService/PodSandboxStatus" => {
+                return Err((5, "pod sandbox not found"))
+            }
+            _ => return Err((12, "unknown method")),
+        };
+        Ok(response.finish())
+    }
+
+    /// containerd with the CRI plugin disabled.
+    fn without_cri(_: &str, _: &[u8]) -> Reply {
+        Err((12, "unknown service"))
+    }
+
+    /// A host whose runtimes are served by `handlers`, in the order of the default endpoints. A
+    /// runtime without handler left a stale socket behind.
+    fn host(name: &str, handlers: &[Option<Handler>]) -> LookupOptions {
+        let root =
+            env::temp_dir().join(format!("container-pid-cri-{}-{}", std::process::id(), name));
+        for (endpoint, handler) in DEFAULT_ENDPOINTS.iter().zip(handlers) {
+            let socket = root.join(endpoint.trim_start_matches('/'));
+            fs::create_dir_all(socket.parent().unwrap()).unwrap();
+            match handler {
+                Some(handler) => serve(&socket, *handler),
+                None => {
+                    let _ = fs::remove_file(&socket);
+                    drop(UnixListener::bind(&socket).unwrap());
+                }
+            }
+        }
+        LookupOptions {
+            proc_root: root.join("proc"),
+            state_root: root,
+            ..LookupOptions::default()
+        }
+    }
+
+    #[test]
+    fn list_skips_unavailable_runtimes() {
+        let options = host("list", &[Some(without_cri), Some(crio), None]);
+        let processes = Cri {}.list(&options).unwrap();
+        let processes: Vec<_> = processes.iter().map(|p| (p.id.as_str(), p.pid)).collect();
+        assert_eq!(processes, vec![(CONTAINER_ID, 4242)]);
+    }
+
+    #[test]
+    fn list_without_answering_runtime() {
+        let options = host("unavailable", &[Some(without_cri), None]);
+        let res = Cri {}.list(&options);
+        assert!(matches!(res, Err(Error::DaemonUnreachable(_))), "{:?}", res);
+        let res = kubernetes_containers(None, &options);
+        assert!(matches!(res, Err(Error::DaemonUnreachable(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn lookup_skips_unavailable_runtimes() {
+        let options = host("lookup", &[Some(without_cri), Some(crio), None]);
+        let process = Cri {}.lookup(CONTAINER_ID, &options).unwrap();
+        assert_eq!(process.pid, 4242);
+        assert_eq!(process.name, "default/web/nginx");
+
+        let res = Cri {}.lookup("0123456789ab", &options);
+        assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn kubernetes_containers_runtime_name() {
+        let options = host("kubernetes", &[Some(without_cri), Some(crio)]);
+        let containers = kubernetes_containers(Some(("default", "web")), &options).unwrap();
+        assert_eq!(containers.len(), 1);
+        let container = &containers[0];
+        assert_eq!(container.id, CONTAINER_ID);
+        assert_eq!(container.runtime, "cri-o");
+        assert_eq!(
+            (
+                container.namespace.as_str(),
+                container.pod.as_str(),
+                container.container.as_str()
+            ),
+            ("default", "web", "nginx")
+        );
+        assert_eq!(container.pod_uid, "8d7c6b5a");
+    }
+}
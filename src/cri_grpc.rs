@@ -0,0 +1,127 @@
+//! Talks to the CRI runtime service over gRPC directly, the default unless the `cri-grpc` feature
+//! is disabled.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::cri::{Filter, Listed, Sandbox, Status};
+use crate::grpc;
+use crate::protobuf::{Encoder, Message};
+use crate::result::{Error, Result};
+use crate::LookupOptions;
+
+/// Versions of the runtime service, runtimes older than Kubernetes 1.23 only serve `v1alpha2`.
+const SERVICES: &[&str] = &[
+    "runtime.v1.RuntimeService",
+    "runtime.v1alpha2.RuntimeService",
+];
+
+/// Version of the runtime API the kubelet sends in `Version` requests.
+pub(crate) const KUBELET_API_VERSION: &str = "0.1.0";
+
+pub(crate) const CONTAINER_RUNNING: u64 = 1;
+const SANDBOX_READY: u64 = 0;
+
+/// Calls `method` of the runtime service, falling back to older API versions.
+fn call(socket: &Path, method: &str, request: Encoder) -> Result<Vec<u8>> {
+    let request = request.finish();
+    let mut result = Err(Error::Unsupported(String::new()));
+    for service in SERVICES {
+        result = grpc::call(socket, &format!("/{}/{}", service, method), &request);
+        match result {
+            Err(Error::Unsupported(_)) => {}
+            _ => break,
+        }
+    }
+    result
+}
+
+/// The JSON blob in the verbose info of a status response.
+fn info(info: &HashMap<String, String>) -> Value {
+    info.get("info")
+        .and_then(|info| serde_json::from_str(info).ok())
+        .unwrap_or_default()
+}
+
+pub(crate) fn container_status(socket: &Path, id: &str, _: &LookupOptions) -> Result<Status> {
+    let request = Encoder::new().string(1, id).bool(2, true);
+    let response = call(socket, "ContainerStatus", request)?;
+    let response = Message::decode(&response)?;
+    let status = response.message(1)?;
+    Ok(Status {
+        id: status.string(1)?,
+        name: status.message(2)?.string(1)?,
+        namespace: String::new(),
+        running: status.varint(3)? == CONTAINER_RUNNING,
+        labels: status.string_map(12)?,
+        info: info(&response.string_map(2)?),
+    })
+}
+
+pub(crate) fn sandbox_status(socket: &Path, id: &str, _: &LookupOptions) -> Result<Status> {
+    let request = Encoder::new().string(1, id).bool(2, true);
+    let response = call(socket, "PodSandboxStatus", request)?;
+    let response = Message::decode(&response)?;
+    let status = response.message(1)?;
+    let metadata = status.message(2)?;
+    Ok(Status {
+        id: status.string(1)?,
+        name: metadata.string(1)?,
+        namespace: metadata.string(3)?,
+        running: status.varint(3)? == SANDBOX_READY,
+        labels: status.string_map(7)?,
+        info: info(&response.string_map(2)?),
+    })
+}
+
+pub(crate) fn list_sandboxes(socket: &Path, _: &LookupOptions) -> Result<Vec<Sandbox>> {
+    let response = call(socket, "ListPodSandbox", Encoder::new())?;
+    let mut sandboxes = vec![];
+    for item in Message::decode(&response)?.messages(1)? {
+        if item.varint(3)? != SANDBOX_READY {
+            continue;
+        }
+        let metadata = item.message(2)?;
+        sandboxes.push(Sandbox {
+            id: item.string(1)?,
+            name: metadata.string(1)?,
+            namespace: metadata.string(3)?,
+        });
+    }
+    Ok(sandboxes)
+}
+
+pub(crate) fn list_containers(
+    socket: &Path,
+    filter: &Filter<'_>,
+    _: &LookupOptions,
+) -> Result<Vec<Listed>> {
+    let mut request = Encoder::new();
+    if filter.running_only {
+        request = request.message(2, Encoder::new().varint(1, CONTAINER_RUNNING));
+    }
+    if let Some(sandbox_id) = filter.sandbox_id {
+        request = request.string(3, sandbox_id);
+    }
+    for (label, value) in &filter.labels {
+        request = request.message(4, Encoder::new().string(1, label).string(2, value));
+    }
+    let response = call(socket, "ListContainers", Encoder::new().message(1, request))?;
+    let mut containers = vec![];
+    for item in Message::decode(&response)?.messages(1)? {
+        containers.push(Listed {
+            id: item.string(1)?,
+            name: item.message(3)?.string(1)?,
+            running: item.varint(6)? == CONTAINER_RUNNING,
+            labels: item.string_map(8)?,
+        });
+    }
+    Ok(containers)
+}
+
+pub(crate) fn runtime_name(socket: &Path, _: &LookupOptions) -> Result<String> {
+    let request = Encoder::new().string(1, KUBELET_API_VERSION);
+    let response = call(socket, "Version", request)?;
+    Message::decode(&response)?.string(2)
+}
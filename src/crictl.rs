@@ -0,0 +1,167 @@
+//! Talks to the CRI runtime service through `crictl`, pointed at each runtime socket in turn. Used
+//! instead of the gRPC client if the `cri-grpc` feature is disabled.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::cmd;
+use crate::cri::{Filter, Listed, Sandbox, Status};
+use crate::result::{Error, Result};
+use crate::LookupOptions;
+
+/// Runs `crictl` against the runtime behind `socket`. crictl reports failed calls with their
+/// gRPC status code, which is more precise than what it prints around it.
+fn crictl(socket: &Path, options: &LookupOptions, args: &[&str]) -> Result<Vec<u8>> {
+    let endpoint = format!("unix://{}", socket.display());
+    let mut command = vec!["--runtime-endpoint", &endpoint];
+    command.extend_from_slice(args);
+    cmd::run(options, "crictl", &command).map_err(|e| {
+        let message = e.to_string();
+        // $ crictl inspect 0123456789ab
+        // FATA[0000] getting the status of the container "0123456789ab": rpc error: code = NotFound desc = ...
+        let code = message
+            .split("code = ")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next());
+        match code {
+            Some("NotFound") => Error::NotFound(message),
+            Some("Unavailable") | Some("DeadlineExceeded") => Error::DaemonUnreachable(message),
+            Some("PermissionDenied") | Some("Unauthenticated") => Error::PermissionDenied(message),
+            // the runtime does not serve CRI, e.g. containerd with the CRI plugin disabled
+            Some("Unimplemented") => Error::Unsupported(message),
+            _ => e,
+        }
+    })
+}
+
+fn json(socket: &Path, options: &LookupOptions, args: &[&str]) -> Result<Value> {
+    let stdout = crictl(socket, options, args)?;
+    serde_json::from_slice(&stdout).map_err(|e| {
+        Error::ParseError(format!(
+            "invalid JSON from 'crictl {}': {}",
+            args.join(" "),
+            e
+        ))
+    })
+}
+
+fn string(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+fn labels(value: &Value) -> HashMap<String, String> {
+    value
+        .as_object()
+        .map(|labels| {
+            labels
+                .iter()
+                .map(|(key, value)| (key.clone(), string(value)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn container_status(socket: &Path, id: &str, options: &LookupOptions) -> Result<Status> {
+    // {"status": {"id": ..., "metadata": {"name": ...}, "state": "CONTAINER_RUNNING", ...},
+    //  "info": {"pid": 4242, ...}}
+    let inspect = json(socket, options, &["inspect", "--output", "json", id])?;
+    let status = &inspect["status"];
+    Ok(Status {
+        id: string(&status["id"]),
+        name: string(&status["metadata"]["name"]),
+        namespace: String::new(),
+        running: status["state"] == "CONTAINER_RUNNING",
+        labels: labels(&status["labels"]),
+        info: inspect["info"].clone(),
+    })
+}
+
+pub(crate) fn sandbox_status(socket: &Path, id: &str, options: &LookupOptions) -> Result<Status> {
+    let inspect = json(socket, options, &["inspectp", "--output", "json", id])?;
+    let status = &inspect["status"];
+    Ok(Status {
+        id: string(&status["id"]),
+        name: string(&status["metadata"]["name"]),
+        namespace: string(&status["metadata"]["namespace"]),
+        running: status["state"] == "SANDBOX_READY",
+        labels: labels(&status["labels"]),
+        info: inspect["info"].clone(),
+    })
+}
+
+pub(crate) fn list_sandboxes(socket: &Path, options: &LookupOptions) -> Result<Vec<Sandbox>> {
+    let pods = json(
+        socket,
+        options,
+        &["pods", "--state", "ready", "--output", "json"],
+    )?;
+    Ok(pods["items"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|pod| Sandbox {
+            id: string(&pod["id"]),
+            name: string(&pod["metadata"]["name"]),
+            namespace: string(&pod["metadata"]["namespace"]),
+        })
+        .collect())
+}
+
+pub(crate) fn list_containers(
+    socket: &Path,
+    filter: &Filter<'_>,
+    options: &LookupOptions,
+) -> Result<Vec<Listed>> {
+    let mut args = vec![String::from("ps")];
+    if filter.running_only {
+        args.extend(["--state", "running"].iter().map(|s| s.to_string()));
+    } else {
+        args.push(String::from("--all"));
+    }
+    if let Some(sandbox_id) = filter.sandbox_id {
+        args.push(String::from("--pod"));
+        args.push(sandbox_id.to_string());
+    }
+    for (label, value) in &filter.labels {
+        args.push(String::from("--label"));
+        args.push(format!("{}={}", label, value));
+    }
+    args.push(String::from("--output"));
+    args.push(String::from("json"));
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let ps = json(socket, options, &args)?;
+    Ok(ps["containers"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|container| Listed {
+            id: string(&container["id"]),
+            name: string(&container["metadata"]["name"]),
+            running: container["state"] == "CONTAINER_RUNNING",
+            labels: labels(&container["labels"]),
+        })
+        .collect())
+}
+
+pub(crate) fn runtime_name(socket: &Path, options: &LookupOptions) -> Result<String> {
+    // $ crictl version
+    // Version:  0.1.0
+    // RuntimeName:  cri-o
+    // RuntimeVersion:  1.30.4
+    // RuntimeApiVersion:  v1
+    let stdout = crictl(socket, options, &["version"])?;
+    String::from_utf8_lossy(&stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("RuntimeName:"))
+        .map(|name| name.trim().to_string())
+        .ok_or_else(|| {
+            Error::ParseError(format!(
+                "no runtime name in 'crictl version' for '{}'",
+                socket.display()
+            ))
+        })
+}
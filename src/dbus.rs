@@ -0,0 +1,1088 @@
+//! Minimal D-Bus client, just enough to call methods with string arguments on the system bus.
+//!
+//! Implements the EXTERNAL authentication mechanism and the wire format described in
+//! <https://dbus.freedesktop.org/doc/dbus-specification.html>.
+
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::result::{Error, Result};
+use crate::LookupOptions;
+
+const DEFAULT_SYSTEM_BUS: &str = "/var/run/dbus/system_bus_socket";
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Limits of the specification: the length of a message, the nesting of arrays and of structs in
+/// a signature, and the nesting of all containers including variants in a message.
+const MAX_MESSAGE_LEN: usize = 1 << 27;
+const MAX_SIGNATURE_DEPTH: usize = 32;
+const MAX_DEPTH: usize = 64;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// A value decoded from a message body.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Byte(u8),
+    Bool(bool),
+    Int16(i16),
+    Uint16(u16),
+    Int32(i32),
+    Uint32(u32),
+    Int64(i64),
+    Uint64(u64),
+    Double(f64),
+    UnixFd(u32),
+    /// strings, object paths and signatures
+    Str(String),
+    Array(Vec<Value>),
+    Struct(Vec<Value>),
+    DictEntry(Box<Value>, Box<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            Value::Variant(v) => v.as_str(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::Uint32(n) => Some(*n),
+            Value::Variant(v) => v.as_u32(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) | Value::Struct(values) => Some(values),
+            Value::Variant(v) => v.as_array(),
+            _ => None,
+        }
+    }
+
+    /// Looks up `key` in a dictionary, i.e. the result of `org.freedesktop.DBus.Properties.GetAll`
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        self.as_array()?.iter().find_map(|entry| match entry {
+            Value::DictEntry(k, v) if k.as_str() == Some(key) => Some(v.as_ref()),
+            _ => None,
+        })
+    }
+}
+
+/// Address of the system bus, `None` if it is not a unix socket path.
+pub(crate) fn system_bus_path(options: &LookupOptions) -> Option<PathBuf> {
+    let address = match env::var("DBUS_SYSTEM_BUS_ADDRESS") {
+        Ok(address) => address,
+        Err(_) => return Some(options.state_path(DEFAULT_SYSTEM_BUS)),
+    };
+    // an address may list several transports separated by ';'
+    address
+        .split(';')
+        .filter_map(|transport| transport.strip_prefix("unix:"))
+        .flat_map(|params| params.split(','))
+        .find_map(|param| param.strip_prefix("path=").map(PathBuf::from))
+}
+
+pub(crate) struct Connection {
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Connection {
+    /// Connects and authenticates to the system bus.
+    pub(crate) fn system(options: &LookupOptions) -> Result<Connection> {
+        let path = system_bus_path(options).ok_or_else(|| {
+            Error::DaemonUnreachable(String::from("system bus address is not a unix socket path"))
+        })?;
+        let stream = UnixStream::connect(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+                Error::DaemonUnreachable(format!(
+                    "cannot connect to system bus at '{}': {}",
+                    path.display(),
+                    e
+                ))
+            }
+            _ => Error::io(
+                format!("cannot connect to system bus at '{}'", path.display()),
+                e,
+            ),
+        })?;
+        Connection::open(stream)
+    }
+
+    /// Authenticates and registers on a bus that `stream` is connected to.
+    fn open(stream: UnixStream) -> Result<Connection> {
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+            .map_err(|e| Error::io("failed to set socket timeout", e))?;
+
+        let mut conn = Connection { stream, serial: 0 };
+        conn.authenticate()?;
+        conn.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+            &[],
+        )?;
+        Ok(conn)
+    }
+
+    fn authenticate(&mut self) -> Result<()> {
+        let uid = unsafe { libc::geteuid() }.to_string();
+        let hex_uid: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+        let auth = format!("\0AUTH EXTERNAL {}\r\n", hex_uid);
+        self.stream
+            .write_all(auth.as_bytes())
+            .map_err(|e| Error::io("failed to authenticate to D-Bus", e))?;
+
+        // read byte-wise, the stream must not be consumed beyond the end of the line
+        let mut line = String::new();
+        BufReader::with_capacity(1, &self.stream)
+            .read_line(&mut line)
+            .map_err(|e| Error::io("failed to authenticate to D-Bus", e))?;
+        if !line.starts_with("OK ") {
+            return Err(Error::PermissionDenied(format!(
+                "D-Bus authentication was rejected: {}",
+                line.trim_end()
+            )));
+        }
+        self.stream
+            .write_all(b"BEGIN\r\n")
+            .map_err(|e| Error::io("failed to authenticate to D-Bus", e))
+    }
+
+    /// Calls a method that takes only string arguments and returns the values of the reply.
+    pub(crate) fn call(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[&str],
+    ) -> Result<Vec<Value>> {
+        self.serial += 1;
+        let serial = self.serial;
+        let message = encode_call(serial, destination, path, interface, member, args);
+        self.stream
+            .write_all(&message)
+            .map_err(|e| Error::io(format!("failed to call {}.{}", interface, member), e))?;
+
+        loop {
+            let reply = self
+                .read_message()
+                .map_err(|e| e.context(format!("failed to call {}.{}", interface, member)))?;
+            // skip signals such as NameAcquired
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            return match reply.message_type {
+                METHOD_RETURN => Ok(reply.body),
+                ERROR => {
+                    let name = reply.error_name.unwrap_or_default();
+                    let message = reply
+                        .body
+                        .first()
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string();
+                    Err(dbus_error(&name, &message))
+                }
+                other => Err(Error::ParseError(format!(
+                    "unexpected D-Bus message type {} in reply to {}.{}",
+                    other, interface, member
+                ))),
+            };
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Message> {
+        let mut fixed = [0u8; 16];
+        self.stream
+            .read_exact(&mut fixed)
+            .map_err(|e| Error::io("failed to read D-Bus message", e))?;
+        let big_endian = match fixed[0] {
+            b'l' => false,
+            b'B' => true,
+            other => {
+                return Err(Error::ParseError(format!(
+                    "invalid D-Bus endianness marker {:#x}",
+                    other
+                )))
+            }
+        };
+        let read_u32 = |bytes: &[u8]| {
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
+        };
+        let body_len = read_u32(&fixed[4..8]) as usize;
+        let fields_len = read_u32(&fixed[12..16]) as usize;
+        // the lengths come from the peer, they are checked before anything is allocated
+        if body_len > MAX_MESSAGE_LEN
+            || fields_len > MAX_MESSAGE_LEN
+            || align(16 + fields_len, 8) + body_len > MAX_MESSAGE_LEN
+        {
+            return Err(Error::ParseError(format!(
+                "D-Bus message with {} bytes of header fields and {} bytes of body exceeds the \
+                 maximum message length",
+                fields_len, body_len
+            )));
+        }
+        let header_len = align(16 + fields_len, 8);
+
+        let mut data = fixed.to_vec();
+        data.resize(header_len + body_len, 0);
+        self.stream
+            .read_exact(&mut data[16..])
+            .map_err(|e| Error::io("failed to read D-Bus message", e))?;
+
+        let mut reader = Reader {
+            data: &data,
+            pos: 12,
+            big_endian,
+        };
+        let mut message = Message {
+            message_type: fixed[1],
+            reply_serial: None,
+            error_name: None,
+            body: vec![],
+        };
+        let mut signature = String::new();
+        if let Value::Array(fields) = reader.read(b"a(yv)")? {
+            for field in fields {
+                let (code, value) = match field {
+                    Value::Struct(mut f) if f.len() == 2 => (f.remove(0), f.remove(0)),
+                    _ => continue,
+                };
+                match (code, value) {
+                    (Value::Byte(FIELD_REPLY_SERIAL), value) => {
+                        message.reply_serial = value.as_u32()
+                    }
+                    (Value::Byte(FIELD_ERROR_NAME), value) => {
+                        message.error_name = value.as_str().map(String::from)
+                    }
+                    (Value::Byte(FIELD_SIGNATURE), value) => {
+                        signature = value.as_str().unwrap_or_default().to_string()
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        reader.pos = header_len;
+        let mut sig = signature.as_bytes();
+        while !sig.is_empty() {
+            let len = single_type_len(sig)?;
+            message.body.push(reader.read(&sig[..len])?);
+            sig = &sig[len..];
+        }
+        Ok(message)
+    }
+}
+
+struct Message {
+    message_type: u8,
+    reply_serial: Option<u32>,
+    error_name: Option<String>,
+    body: Vec<Value>,
+}
+
+fn dbus_error(name: &str, message: &str) -> Error {
+    let text = format!("{}: {}", name, message);
+    match name {
+        "org.freedesktop.DBus.Error.AccessDenied"
+        | "org.freedesktop.DBus.Error.AuthFailed"
+        | "org.freedesktop.DBus.Error.InteractiveAuthorizationRequired" => {
+            Error::PermissionDenied(text)
+        }
+        // nothing owns the name and it cannot be activated, i.e. the service is not installed
+        "org.freedesktop.DBus.Error.ServiceUnknown" => Error::ToolUnavailable(text),
+        "org.freedesktop.DBus.Error.NameHasNoOwner"
+        | "org.freedesktop.DBus.Error.NoServer"
+        | "org.freedesktop.DBus.Error.Timeout" => Error::DaemonUnreachable(text),
+        "org.freedesktop.DBus.Error.UnknownObject" => Error::NotFound(text),
+        _ if name.contains(".NoSuch") => Error::NotFound(text),
+        _ => Error::CommandFailed(text),
+    }
+}
+
+fn align(pos: usize, alignment: usize) -> usize {
+    pos.div_ceil(alignment) * alignment
+}
+
+fn alignment_of(code: u8) -> usize {
+    match code {
+        b'y' | b'g' | b'v' => 1,
+        b'n' | b'q' => 2,
+        b'x' | b't' | b'd' | b'(' | b'{' => 8,
+        _ => 4,
+    }
+}
+
+/// Type codes that may be used as keys of dict entries.
+const BASIC_TYPES: &[u8] = b"ybnqiuxtdsogh";
+
+/// Length of the first single complete type in `sig`.
+fn single_type_len(sig: &[u8]) -> Result<usize> {
+    nested_type_len(sig, 0, 0).ok_or_else(|| {
+        Error::ParseError(format!(
+            "invalid D-Bus signature '{}'",
+            String::from_utf8_lossy(sig)
+        ))
+    })
+}
+
+/// Length of the first single complete type in `sig`, which is nested in `arrays` arrays and
+/// `structs` structs or dict entries.
+fn nested_type_len(sig: &[u8], arrays: usize, structs: usize) -> Option<usize> {
+    if arrays > MAX_SIGNATURE_DEPTH || structs > MAX_SIGNATURE_DEPTH {
+        return None;
+    }
+    match *sig.first()? {
+        b'a' => Some(1 + nested_type_len(&sig[1..], arrays + 1, structs)?),
+        // a struct has at least one field
+        b'(' if sig.get(1) == Some(&b')') => None,
+        b'(' => {
+            let mut len = 1;
+            while sig.get(len) != Some(&b')') {
+                if len >= sig.len() {
+                    return None;
+                }
+                len += nested_type_len(&sig[len..], arrays, structs + 1)?;
+            }
+            Some(len + 1)
+        }
+        // a dict entry has exactly one basic key type and one value type
+        b'{' => {
+            if !BASIC_TYPES.contains(sig.get(1)?) {
+                return None;
+            }
+            let value_len = nested_type_len(&sig[2..], arrays, structs + 1)?;
+            (sig.get(2 + value_len) == Some(&b'}')).then_some(3 + value_len)
+        }
+        code if BASIC_TYPES.contains(&code) || code == b'v' => Some(1),
+        _ => None,
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| Error::ParseError(String::from("truncated D-Bus message")))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.pos = align(self.pos, alignment);
+    }
+
+    fn fixed<const N: usize>(&mut self) -> Result<[u8; N]> {
+        self.align(N);
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.take(N)?);
+        if self.big_endian {
+            bytes.reverse();
+        }
+        // bytes are now little endian
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.fixed::<4>()?))
+    }
+
+    fn string(&mut self, len: usize) -> Result<String> {
+        let bytes = self.take(len)?;
+        self.take(1)?; // trailing nul byte
+        String::from_utf8(bytes.to_vec())
+            .map_err(|e| Error::ParseError(format!("invalid UTF-8 in D-Bus string: {}", e)))
+    }
+
+    /// Reads a value of the single complete type `sig`.
+    fn read(&mut self, sig: &[u8]) -> Result<Value> {
+        self.read_nested(sig, 0)
+    }
+
+    /// Reads a value of type `sig` that is nested in `depth` containers.
+    fn read_nested(&mut self, sig: &[u8], depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(Error::ParseError(format!(
+                "D-Bus value is nested deeper than {} containers",
+                MAX_DEPTH
+            )));
+        }
+        Ok(match sig[0] {
+            b'y' => Value::Byte(self.take(1)?[0]),
+            b'b' => Value::Bool(self.u32()? != 0),
+            b'n' => Value::Int16(i16::from_le_bytes(self.fixed::<2>()?)),
+            b'q' => Value::Uint16(u16::from_le_bytes(self.fixed::<2>()?)),
+            b'i' => Value::Int32(i32::from_le_bytes(self.fixed::<4>()?)),
+            b'u' => Value::Uint32(self.u32()?),
+            b'h' => Value::UnixFd(self.u32()?),
+            b'x' => Value::Int64(i64::from_le_bytes(self.fixed::<8>()?)),
+            b't' => Value::Uint64(u64::from_le_bytes(self.fixed::<8>()?)),
+            b'd' => Value::Double(f64::from_le_bytes(self.fixed::<8>()?)),
+            b's' | b'o' => {
+                let len = self.u32()? as usize;
+                Value::Str(self.string(len)?)
+            }
+            b'g' => {
+                let len = self.take(1)?[0] as usize;
+                Value::Str(self.string(len)?)
+            }
+            b'v' => {
+                let len = self.take(1)?[0] as usize;
+                let inner = self.string(len)?;
+                if inner.is_empty() || single_type_len(inner.as_bytes())? != inner.len() {
+                    return Err(Error::ParseError(format!(
+                        "invalid D-Bus variant signature '{}'",
+                        inner
+                    )));
+                }
+                Value::Variant(Box::new(self.read_nested(inner.as_bytes(), depth + 1)?))
+            }
+            b'a' => {
+                let len = self.u32()? as usize;
+                let element = &sig[1..1 + single_type_len(&sig[1..])?];
+                // padding to the first element is not part of the array length
+                self.align(alignment_of(element[0]));
+                let end = self.pos + len;
+                let mut values = vec![];
+                while self.pos < end {
+                    values.push(self.read_nested(element, depth + 1)?);
+                }
+                Value::Array(values)
+            }
+            b'(' => {
+                self.align(8);
+                let mut values = vec![];
+                let mut inner = &sig[1..sig.len() - 1];
+                while !inner.is_empty() {
+                    let len = single_type_len(inner)?;
+                    values.push(self.read_nested(&inner[..len], depth + 1)?);
+                    inner = &inner[len..];
+                }
+                Value::Struct(values)
+            }
+            b'{' => {
+                self.align(8);
+                let key_len = single_type_len(&sig[1..])?;
+                let key = self.read_nested(&sig[1..1 + key_len], depth + 1)?;
+                let value = self.read_nested(&sig[1 + key_len..sig.len() - 1], depth + 1)?;
+                Value::DictEntry(Box::new(key), Box::new(value))
+            }
+            other => {
+                return Err(Error::ParseError(format!(
+                    "unsupported D-Bus type '{}'",
+                    other as char
+                )))
+            }
+        })
+    }
+}
+
+/// Little endian message writer, alignment is relative to the start of the message.
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn pad(&mut self, alignment: usize) {
+        let len = align(self.buf.len(), alignment);
+        self.buf.resize(len, 0);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.pad(4);
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.buf.push(s.len() as u8);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// Writes a header field with a string-like value of type `sig` (`s`, `o` or `g`).
+    fn field(&mut self, code: u8, sig: &str, value: &str) {
+        self.pad(8);
+        self.buf.push(code);
+        self.signature(sig);
+        if sig == "g" {
+            self.signature(value);
+        } else {
+            self.string(value);
+        }
+    }
+}
+
+fn encode_call(
+    serial: u32,
+    destination: &str,
+    path: &str,
+    interface: &str,
+    member: &str,
+    args: &[&str],
+) -> Vec<u8> {
+    let mut body = Writer { buf: vec![] };
+    for arg in args {
+        body.string(arg);
+    }
+
+    let mut msg = Writer {
+        buf: vec![b'l', METHOD_CALL, 0, 1],
+    };
+    msg.u32(body.buf.len() as u32);
+    msg.u32(serial);
+
+    // header fields array, its length is patched in afterwards
+    msg.u32(0);
+    msg.pad(8);
+    let fields_start = msg.buf.len();
+    msg.field(FIELD_PATH, "o", path);
+    msg.field(FIELD_INTERFACE, "s", interface);
+    msg.field(FIELD_MEMBER, "s", member);
+    msg.field(FIELD_DESTINATION, "s", destination);
+    if !args.is_empty() {
+        msg.field(FIELD_SIGNATURE, "g", &"s".repeat(args.len()));
+    }
+    let fields_len = (msg.buf.len() - fields_start) as u32;
+    msg.buf[12..16].copy_from_slice(&fields_len.to_le_bytes());
+
+    msg.pad(8);
+    msg.buf.extend_from_slice(&body.buf);
+    msg.buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process::{Child, Command, Stdio};
+    use std::thread;
+
+    const SIGNAL: u8 = 4;
+    const MACHINE_INTERFACE: &str = "org.freedesktop.machine1.Machine";
+
+    fn le_u32(bytes: &[u8]) -> u32 {
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    /// Builds a message from the bus with a pre-encoded little endian `body` of type `sig`.
+    fn bus_message(
+        message_type: u8,
+        reply_serial: Option<u32>,
+        error_name: Option<&str>,
+        sig: &str,
+        body: &[u8],
+    ) -> Vec<u8> {
+        let mut msg = Writer {
+            buf: vec![b'l', message_type, 0, 1],
+        };
+        msg.u32(body.len() as u32);
+        msg.u32(1000);
+        msg.u32(0);
+        msg.pad(8);
+        let fields_start = msg.buf.len();
+        if let Some(serial) = reply_serial {
+            msg.pad(8);
+            msg.buf.push(FIELD_REPLY_SERIAL);
+            msg.signature("u");
+            msg.u32(serial);
+        }
+        if let Some(name) = error_name {
+            msg.field(FIELD_ERROR_NAME, "s", name);
+        }
+        if !sig.is_empty() {
+            msg.field(FIELD_SIGNATURE, "g", sig);
+        }
+        let fields_len = (msg.buf.len() - fields_start) as u32;
+        msg.buf[12..16].copy_from_slice(&fields_len.to_le_bytes());
+        msg.pad(8);
+        msg.buf.extend_from_slice(body);
+        msg.buf
+    }
+
+    fn string_body(s: &str) -> Vec<u8> {
+        let mut body = Writer { buf: vec![] };
+        body.string(s);
+        body.buf
+    }
+
+    /// `a{sv}` with string and uint32 values, as returned by `Properties.GetAll`.
+    fn properties_body(properties: &[(&str, Value)]) -> Vec<u8> {
+        let mut body = Writer { buf: vec![] };
+        body.u32(0);
+        body.pad(8);
+        let start = body.buf.len();
+        for (key, value) in properties {
+            body.pad(8);
+            body.string(key);
+            match value {
+                Value::Str(s) => {
+                    body.signature("s");
+                    body.string(s);
+                }
+                Value::Uint32(n) => {
+                    body.signature("u");
+                    body.u32(*n);
+                }
+                _ => unreachable!(),
+            }
+        }
+        let len = (body.buf.len() - start) as u32;
+        body.buf[0..4].copy_from_slice(&len.to_le_bytes());
+        body.buf
+    }
+
+    /// Decodes a method call: serial, member and arguments.
+    fn decode_call(data: &[u8]) -> (u32, String, Vec<Value>) {
+        let fields_len = le_u32(&data[12..16]) as usize;
+        let mut reader = Reader {
+            data,
+            pos: 12,
+            big_endian: false,
+        };
+        let mut member = String::new();
+        let mut signature = String::new();
+        for field in reader.read(b"a(yv)").unwrap().as_array().unwrap() {
+            let field = field.as_array().unwrap();
+            match field[0] {
+                Value::Byte(FIELD_MEMBER) => member = field[1].as_str().unwrap().to_string(),
+                Value::Byte(FIELD_SIGNATURE) => signature = field[1].as_str().unwrap().to_string(),
+                _ => {}
+            }
+        }
+        assert_eq!(reader.pos, 16 + fields_len);
+        reader.pos = align(reader.pos, 8);
+        let args = signature
+            .bytes()
+            .map(|code| reader.read(&[code]).unwrap())
+            .collect();
+        assert_eq!(reader.pos, data.len(), "body has trailing bytes");
+        let serial = le_u32(&data[8..12]);
+        (serial, member, args)
+    }
+
+    fn read_line(stream: &mut UnixStream) -> String {
+        let mut line = vec![];
+        let mut byte = [0];
+        while !line.ends_with(b"\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            line.push(byte[0]);
+        }
+        String::from_utf8(line).unwrap()
+    }
+
+    fn read_call(stream: &mut UnixStream) -> (u32, String, Vec<Value>) {
+        let mut data = vec![0; 16];
+        stream.read_exact(&mut data).unwrap();
+        let body_len = le_u32(&data[4..8]) as usize;
+        let fields_len = le_u32(&data[12..16]) as usize;
+        data.resize(align(16 + fields_len, 8) + body_len, 0);
+        stream.read_exact(&mut data[16..]).unwrap();
+        decode_call(&data)
+    }
+
+    /// Plays the bus side of the EXTERNAL handshake and answers `Hello`.
+    fn accept(stream: &mut UnixStream) {
+        let auth = read_line(stream);
+        let hex_uid = auth
+            .strip_prefix("\0AUTH EXTERNAL ")
+            .and_then(|rest| rest.strip_suffix("\r\n"))
+            .unwrap();
+        let uid = (0..hex_uid.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex_uid[i..i + 2], 16).unwrap() as char)
+            .collect::<String>();
+        assert_eq!(uid, unsafe { libc::geteuid() }.to_string());
+        stream
+            .write_all(b"OK 1234deadbeef1234deadbeef1234de\r\n")
+            .unwrap();
+        assert_eq!(read_line(stream), "BEGIN\r\n");
+
+        let (serial, member, args) = read_call(stream);
+        assert_eq!((member.as_str(), args.len()), ("Hello", 0));
+        let reply = bus_message(METHOD_RETURN, Some(serial), None, "s", &string_body(":1.7"));
+        stream.write_all(&reply).unwrap();
+    }
+
+    #[test]
+    fn encode_call_alignment() {
+        let msg = encode_call(
+            3,
+            "org.freedesktop.machine1",
+            "/org/freedesktop/machine1",
+            "org.freedesktop.machine1.Manager",
+            "GetMachine",
+            &["web", "x"],
+        );
+        assert_eq!(&msg[..4], &[b'l', METHOD_CALL, 0, 1]);
+        // fields array starts at 16, the body at the next multiple of 8 after it
+        let fields_len = le_u32(&msg[12..16]) as usize;
+        let body_start = align(16 + fields_len, 8);
+        let body_len = le_u32(&msg[4..8]) as usize;
+        assert_eq!(msg.len(), body_start + body_len);
+        assert!(msg[16 + fields_len..body_start].iter().all(|&b| b == 0));
+        // "web\0" padded to 4 bytes before the length of "x"
+        assert_eq!(&msg[body_start..], b"\x03\0\0\0web\0\x01\0\0\0x\0",);
+
+        let (serial, member, args) = decode_call(&msg);
+        assert_eq!(serial, 3);
+        assert_eq!(member, "GetMachine");
+        assert_eq!(
+            args,
+            vec![
+                Value::Str(String::from("web")),
+                Value::Str(String::from("x"))
+            ]
+        );
+    }
+
+    #[test]
+    fn read_aligned_values() {
+        // (yt) aligns the u64 to 8, a{sv} aligns each entry to 8 and the array length excludes
+        // the padding before the first entry
+        let data = b"\x07\0\0\0\0\0\0\0\x2a\0\0\0\0\0\0\0\
+                     \x10\0\0\0\0\0\0\0\
+                     \x01\0\0\0k\0\x01u\0\0\0\0\x05\0\0\0";
+        let mut reader = Reader {
+            data,
+            pos: 0,
+            big_endian: false,
+        };
+        assert_eq!(
+            reader.read(b"(yt)").unwrap(),
+            Value::Struct(vec![Value::Byte(7), Value::Uint64(42)])
+        );
+        let dict = reader.read(b"a{sv}").unwrap();
+        assert_eq!(dict.get("k").and_then(Value::as_u32), Some(5));
+        assert_eq!(reader.pos, data.len());
+    }
+
+    #[test]
+    fn read_big_endian() {
+        let data = b"\0\0\0\x02\xff\xfe\0\0\0\0\0\x03abc\0";
+        let mut reader = Reader {
+            data,
+            pos: 0,
+            big_endian: true,
+        };
+        assert_eq!(reader.read(b"u").unwrap(), Value::Uint32(2));
+        assert_eq!(reader.read(b"n").unwrap(), Value::Int16(-2));
+        assert_eq!(reader.read(b"s").unwrap(), Value::Str(String::from("abc")));
+    }
+
+    #[test]
+    fn read_truncated() {
+        let mut reader = Reader {
+            data: b"\x10\0\0\0ab",
+            pos: 0,
+            big_endian: false,
+        };
+        assert!(matches!(reader.read(b"s"), Err(Error::ParseError(_))));
+    }
+
+    #[test]
+    fn signature_lengths() {
+        assert_eq!(single_type_len(b"a{sv}s").unwrap(), 5);
+        assert_eq!(single_type_len(b"(ssso)").unwrap(), 6);
+        assert_eq!(single_type_len(b"aa(yv)u").unwrap(), 6);
+        assert!(single_type_len(b"(ss").is_err());
+        assert!(single_type_len(b"").is_err());
+    }
+
+    #[test]
+    fn error_names() {
+        let error = |name| dbus_error(name, "message");
+        assert!(matches!(
+            error("org.freedesktop.machine1.NoSuchMachine"),
+            Error::NotFound(_)
+        ));
+        assert!(matches!(
+            error("org.freedesktop.DBus.Error.AccessDenied"),
+            Error::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            error("org.freedesktop.DBus.Error.ServiceUnknown"),
+            Error::ToolUnavailable(_)
+        ));
+        assert!(matches!(
+            error("org.freedesktop.DBus.Error.NameHasNoOwner"),
+            Error::DaemonUnreachable(_)
+        ));
+        assert!(matches!(
+            error("org.freedesktop.DBus.Error.Failed"),
+            Error::CommandFailed(_)
+        ));
+    }
+
+    #[test]
+    fn dict_entry_without_value() {
+        let data = [4, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0];
+        let mut reader = Reader {
+            data: &data,
+            pos: 0,
+            big_endian: false,
+        };
+        let res = reader.read(b"a{s}");
+        assert!(matches!(res, Err(Error::ParseError(_))), "{:?}", res);
+        assert!(single_type_len(b"{sv}").is_ok());
+        assert!(single_type_len(b"{ss").is_err());
+        assert!(single_type_len(b"{svs}").is_err());
+        assert!(single_type_len(b"{vs}").is_err());
+    }
+
+    #[test]
+    fn empty_struct() {
+        assert!(single_type_len(b"()").is_err());
+        assert!(single_type_len(b"a()").is_err());
+        // an array of empty structs in a variant would never advance
+        let data = b"\x03a()\0\x08\0\0\0\0\0\0\0";
+        let mut reader = Reader {
+            data,
+            pos: 0,
+            big_endian: false,
+        };
+        let res = reader.read(b"v");
+        assert!(matches!(res, Err(Error::ParseError(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn nesting_limits() {
+        let arrays = |n| format!("{}y", "a".repeat(n));
+        assert!(single_type_len(arrays(32).as_bytes()).is_ok());
+        assert!(single_type_len(arrays(33).as_bytes()).is_err());
+        let structs = |n| format!("{}y{}", "(".repeat(n), ")".repeat(n));
+        assert!(single_type_len(structs(32).as_bytes()).is_ok());
+        assert!(single_type_len(structs(33).as_bytes()).is_err());
+
+        // variants in variants have a valid signature on every level
+        let mut data = b"\x01v\0".repeat(MAX_DEPTH + 1);
+        data.extend_from_slice(b"\x01y\0\x07");
+        let mut reader = Reader {
+            data: &data,
+            pos: 0,
+            big_endian: false,
+        };
+        let res = reader.read(b"v");
+        assert!(matches!(res, Err(Error::ParseError(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn oversized_message() {
+        let (client, mut bus) = UnixStream::pair().unwrap();
+        let mut conn = Connection {
+            stream: client,
+            serial: 0,
+        };
+        // a reply that announces a body of 4 GiB
+        let mut header = vec![b'l', METHOD_RETURN, 0, 1];
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        header.extend_from_slice(&1u32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        bus.write_all(&header).unwrap();
+        let res = conn.read_message().map(|_| ());
+        assert!(matches!(res, Err(Error::ParseError(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn call_over_handshake() {
+        let (client, mut bus) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            accept(&mut bus);
+
+            let (serial, member, args) = read_call(&mut bus);
+            assert_eq!(member, "GetAll");
+            assert_eq!(args, vec![Value::Str(String::from(MACHINE_INTERFACE))]);
+            // signals in between are skipped
+            let signal = bus_message(SIGNAL, None, None, "s", &string_body(":1.7"));
+            bus.write_all(&signal).unwrap();
+            let body = properties_body(&[
+                ("Name", Value::Str(String::from("web"))),
+                ("Leader", Value::Uint32(9120)),
+            ]);
+            let reply = bus_message(METHOD_RETURN, Some(serial), None, "a{sv}", &body);
+            bus.write_all(&reply).unwrap();
+
+            let (serial, member, _) = read_call(&mut bus);
+            assert_eq!(member, "GetMachine");
+            let error = bus_message(
+                ERROR,
+                Some(serial),
+                Some("org.freedesktop.machine1.NoSuchMachine"),
+                "s",
+                &string_body("No machine 'nope' known"),
+            );
+            bus.write_all(&error).unwrap();
+        });
+
+        let mut conn = Connection::open(client).unwrap();
+        let reply = conn
+            .call(
+                "org.freedesktop.machine1",
+                "/org/freedesktop/machine1/machine/web",
+                "org.freedesktop.DBus.Properties",
+                "GetAll",
+                &[MACHINE_INTERFACE],
+            )
+            .unwrap();
+        assert_eq!(reply[0].get("Name").and_then(Value::as_str), Some("web"));
+        assert_eq!(reply[0].get("Leader").and_then(Value::as_u32), Some(9120));
+
+        let res = conn.call(
+            "org.freedesktop.machine1",
+            "/org/freedesktop/machine1",
+            "org.freedesktop.machine1.Manager",
+            "GetMachine",
+            &["nope"],
+        );
+        match res {
+            Err(Error::NotFound(message)) => assert!(message.contains("No machine 'nope' known")),
+            res => panic!("expected NotFound: {:?}", res.map(|_| ())),
+        }
+        server.join().unwrap();
+    }
+
+    /// A private session bus, stopped and removed when dropped.
+    struct DbusDaemon {
+        daemon: Child,
+        dir: PathBuf,
+    }
+
+    impl DbusDaemon {
+        fn spawn() -> (DbusDaemon, PathBuf) {
+            let dir = env::temp_dir().join(format!("container-pid-dbus-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let socket = dir.join("bus");
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .arg(format!("--address=unix:path={}", socket.display()))
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon is not installed");
+            // the address is printed once the bus listens
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            assert!(address.starts_with("unix:path="), "{}", address);
+            (DbusDaemon { daemon, dir }, socket)
+        }
+    }
+
+    impl Drop for DbusDaemon {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    #[ignore = "spawns dbus-daemon"]
+    fn call_dbus_daemon() {
+        let (_daemon, socket) = DbusDaemon::spawn();
+        let mut conn = Connection::open(UnixStream::connect(&socket).unwrap()).unwrap();
+
+        let reply = conn
+            .call(
+                "org.freedesktop.DBus",
+                "/org/freedesktop/DBus",
+                "org.freedesktop.DBus.Properties",
+                "GetAll",
+                &["org.freedesktop.DBus"],
+            )
+            .unwrap();
+        let interfaces = reply[0]
+            .get("Interfaces")
+            .and_then(Value::as_array)
+            .unwrap();
+        assert!(interfaces
+            .iter()
+            .any(|interface| interface.as_str() == Some("org.freedesktop.DBus.Monitoring")));
+
+        let owner = conn
+            .call(
+                "org.freedesktop.DBus",
+                "/org/freedesktop/DBus",
+                "org.freedesktop.DBus",
+                "GetNameOwner",
+                &["org.freedesktop.DBus"],
+            )
+            .unwrap();
+        assert_eq!(
+            owner,
+            vec![Value::Str(String::from("org.freedesktop.DBus"))]
+        );
+
+        // what a missing machined looks like to `check_required_tools`
+        let res = conn.call(
+            "org.freedesktop.machine1",
+            "/org/freedesktop/machine1",
+            "org.freedesktop.DBus.Peer",
+            "Ping",
+            &[],
+        );
+        assert!(matches!(res, Err(Error::ToolUnavailable(_))), "{:?}", res);
+        let res = conn.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "GetNameOwner",
+            &["org.freedesktop.machine1"],
+        );
+        assert!(matches!(res, Err(Error::DaemonUnreachable(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn rejected_handshake() {
+        let (client, mut bus) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            read_line(&mut bus);
+            bus.write_all(b"REJECTED EXTERNAL\r\n").unwrap();
+        });
+        let res = Connection::open(client);
+        assert!(matches!(res, Err(Error::PermissionDenied(_))));
+        server.join().unwrap();
+    }
+}
@@ -1,74 +1,520 @@
-use anyhow::{bail, Context};
 use libc::pid_t;
-use std::process::Command;
+use serde_json::Value;
+use std::env;
+use std::path::{Path, PathBuf};
 
-use crate::cmd;
-use crate::result::Result;
-use crate::Container;
+use crate::result::{Error, Result};
+use crate::rootless::{self, User};
+use crate::{cmd, http};
+use crate::{Container, ContainerProcess, LookupOptions};
 
 #[derive(Clone, Debug)]
 pub(crate) struct Docker {}
 
-pub(crate) fn parse_docker_output(cmd: &[&str], container_id: &str) -> Result<pid_t> {
-    let cmd_str = cmd.join(" ");
-    let output = Command::new(&cmd[0])
-        .args(&cmd[1..])
-        .output()
-        .with_context(|| format!("failed to execute command: {}", cmd_str))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!(
-            "docker command failed (exit status {}): {}\nCommand: {}",
-            output.status,
-            stderr.trim_end(),
-            cmd_str
-        );
-    }
-
-    let fields: Vec<&[u8]> = output.stdout.splitn(2, |c| *c == b';').collect();
-    if fields.len() != 2 {
-        bail!(
-            "unexpected docker output format for container '{}'",
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// Socket of the docker daemon, `None` if `DOCKER_HOST` points to a non-unix address.
+fn socket_path(options: &LookupOptions) -> Option<PathBuf> {
+    match env::var("DOCKER_HOST") {
+        Ok(host) if !host.is_empty() => host.strip_prefix("unix://").map(PathBuf::from),
+        _ => Some(options.state_path(DEFAULT_SOCKET)),
+    }
+}
+
+/// The socket to talk to the Engine API on, `None` if we have to fall back to the CLI.
+fn api_socket(options: &LookupOptions) -> Option<PathBuf> {
+    socket_path(options).filter(|path| path.exists())
+}
+
+/// Converts the output of `GET /containers/{id}/json` (or `docker inspect`) to a process.
+pub(crate) fn parse_inspect_json(
+    runtime: &'static str,
+    inspect: &Value,
+    container_id: &str,
+    proc_root: &Path,
+) -> Result<ContainerProcess> {
+    let state = &inspect["State"];
+    let running = state["Running"].as_bool().ok_or_else(|| {
+        Error::ParseError(format!(
+            "no 'State.Running' field in {} response for container '{}'",
+            runtime, container_id
+        ))
+    })?;
+    if !running {
+        return Err(Error::NotRunning(format!(
+            "container '{}' is not running",
             container_id
-        );
+        )));
+    }
+    let pid = state["Pid"].as_i64().ok_or_else(|| {
+        Error::ParseError(format!(
+            "no 'State.Pid' field in {} response for container '{}'",
+            runtime, container_id
+        ))
+    })?;
+    let id = inspect["Id"].as_str().unwrap_or(container_id);
+    // docker prefixes container names with a slash
+    let name = inspect["Name"]
+        .as_str()
+        .map(|name| name.trim_start_matches('/'))
+        .unwrap_or(id);
+
+    Ok(ContainerProcess::new(
+        runtime,
+        id,
+        name,
+        pid as pid_t,
+        container_id,
+        proc_root,
+    ))
+}
+
+/// Fetches `path` from a docker compatible API, 404 is reported as `NotFound`.
+///
+/// `container_id` is the container that `path` refers to, `None` for listings.
+pub(crate) fn api_get(socket: &Path, path: &str, container_id: Option<&str>) -> Result<Value> {
+    let response = http::get(socket, path)?;
+    match (response.status, container_id) {
+        (200, _) => response.json(),
+        (404, Some(container_id)) => Err(Error::NotFound(format!(
+            "no such container '{}': {}",
+            container_id,
+            response.error_message()
+        ))),
+        (404, None) => Err(Error::NotFound(format!(
+            "GET {} on '{}' was not found: {}",
+            path,
+            socket.display(),
+            response.error_message()
+        ))),
+        (401, _) | (403, _) => Err(Error::PermissionDenied(format!(
+            "GET {} on '{}' was denied: {}",
+            path,
+            socket.display(),
+            response.error_message()
+        ))),
+        (status, _) => Err(Error::CommandFailed(format!(
+            "GET {} on '{}' failed with status {}: {}",
+            path,
+            socket.display(),
+            status,
+            response.error_message()
+        ))),
     }
+}
+
+/// Inspects the container `id`, the process is reported as resolved from `query`.
+fn lookup_api(socket: &Path, id: &str, query: &str, proc_root: &Path) -> Result<ContainerProcess> {
+    let path = format!("/containers/{}/json", http::encode_path_segment(id));
+    let inspect = api_get(socket, &path, Some(query))?;
+    parse_inspect_json("docker", &inspect, query, proc_root)
+}
+
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+const COMPOSE_NUMBER_LABEL: &str = "com.docker.compose.container-number";
+
+/// A container as listed by `GET /containers/json` or `docker ps`.
+struct Summary {
+    id: String,
+    names: Vec<String>,
+    running: bool,
+    /// Compose project, service and container number.
+    compose: Option<(String, String, String)>,
+}
 
-    if fields[0] != b"true" {
-        bail!("container '{}' is not running", container_id);
+impl Summary {
+    fn describe(&self) -> String {
+        let name = self.names.first().map(String::as_str).unwrap_or_default();
+        format!("{} ({})", name, self.id.get(..12).unwrap_or(&self.id))
     }
+}
 
-    let pid = String::from_utf8_lossy(fields[1]);
+fn compose_labels(project: &str, service: &str, number: &str) -> Option<(String, String, String)> {
+    if project.is_empty() || service.is_empty() {
+        return None;
+    }
+    Some((project.into(), service.into(), number.into()))
+}
 
-    pid.trim_end().parse::<pid_t>().with_context(|| {
-        format!(
-            "invalid PID '{}' from docker for container '{}'",
-            pid.trim_end(),
+/// Lists all containers, including stopped ones, from the Engine API.
+fn summaries_api(socket: &Path) -> Result<Vec<Summary>> {
+    let containers = api_get(socket, "/containers/json?all=1", None)?;
+    let containers = containers.as_array().ok_or_else(|| {
+        Error::ParseError(String::from(
+            "expected a list of containers from GET /containers/json",
+        ))
+    })?;
+    Ok(containers
+        .iter()
+        .map(|c| {
+            let label = |name: &str| c["Labels"][name].as_str().unwrap_or_default();
+            Summary {
+                id: c["Id"].as_str().unwrap_or_default().to_string(),
+                // docker prefixes container names with a slash
+                names: c["Names"]
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(|name| name.trim_start_matches('/').to_string())
+                    .collect(),
+                running: c["State"].as_str() == Some("running"),
+                compose: compose_labels(
+                    label(COMPOSE_PROJECT_LABEL),
+                    label(COMPOSE_SERVICE_LABEL),
+                    label(COMPOSE_NUMBER_LABEL),
+                ),
+            }
+        })
+        .collect())
+}
+
+/// Lists all containers, including stopped ones, with `docker ps`.
+fn summaries_cli(options: &LookupOptions) -> Result<Vec<Summary>> {
+    let format = format!(
+        "{{{{.ID}}}}\t{{{{.Names}}}}\t{{{{.State}}}}\t{{{{.Label \"{}\"}}}}\t{{{{.Label \"{}\"}}}}\t{{{{.Label \"{}\"}}}}",
+        COMPOSE_PROJECT_LABEL, COMPOSE_SERVICE_LABEL, COMPOSE_NUMBER_LABEL
+    );
+    let stdout = cmd::run(
+        options,
+        "docker",
+        &["ps", "--all", "--no-trunc", "--format", &format],
+    )?;
+    let mut containers = vec![];
+    for line in String::from_utf8_lossy(&stdout).lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 6 {
+            continue;
+        }
+        containers.push(Summary {
+            id: fields[0].to_string(),
+            names: fields[1].split(',').map(String::from).collect(),
+            running: fields[2] == "running",
+            compose: compose_labels(fields[3], fields[4], fields[5]),
+        });
+    }
+    Ok(containers)
+}
+
+/// Resolves a full ID, a name, a Compose `project/service[/index]` or a unique ID prefix to one
+/// container. Prefixes and services may match several containers, only running ones count.
+fn resolve<'a>(containers: &'a [Summary], query: &str) -> Result<&'a Summary> {
+    let exact = containers
+        .iter()
+        .find(|c| c.id == query || c.names.iter().any(|name| name == query));
+    if let Some(container) = exact {
+        return Ok(container);
+    }
+
+    // container names can't contain slashes
+    let parts: Vec<&str> = query.split('/').collect();
+    let matches: Vec<&Summary> = match parts.as_slice() {
+        [project, service] | [project, service, _] => containers
+            .iter()
+            .filter(|c| {
+                c.compose.as_ref().is_some_and(|(p, s, number)| {
+                    p == project && s == service && parts.get(2).is_none_or(|n| n == number)
+                })
+            })
+            .collect(),
+        [prefix] if prefix.bytes().all(|b| b.is_ascii_hexdigit()) && !prefix.is_empty() => {
+            containers
+                .iter()
+                .filter(|c| c.id.starts_with(prefix))
+                .collect()
+        }
+        _ => vec![],
+    };
+
+    let mut running: Vec<&Summary> = matches.iter().copied().filter(|c| c.running).collect();
+    if running.len() > 1 {
+        return Err(Error::Ambiguous {
+            message: format!("'{}' matches {} running containers", query, running.len()),
+            candidates: running.iter().map(|c| c.describe()).collect(),
+        });
+    }
+    match (running.pop(), matches.first()) {
+        (Some(container), _) => Ok(container),
+        (None, Some(container)) => Err(Error::NotRunning(format!(
+            "container '{}' is not running",
+            container.describe()
+        ))),
+        (None, None) => Err(Error::NotFound(format!("no such container '{}'", query))),
+    }
+}
+
+/// `--format` argument for `inspect` that produces the output expected by `parse_docker_output`.
+pub(crate) const INSPECT_FORMAT: &str = "{{.State.Running}};{{.State.Pid}};{{.Id}};{{.Name}}";
+
+/// Runs an `inspect`-like command that prints `running;pid;id;name` and parses its output.
+/// `docker-pid` only prints the first two fields, in that case the query is used as ID and name.
+pub(crate) fn parse_docker_output(
+    options: &LookupOptions,
+    runtime: &'static str,
+    cmd: &[&str],
+    container_id: &str,
+) -> Result<ContainerProcess> {
+    let stdout = cmd::run(options, cmd[0], &cmd[1..])?;
+    parse_inspect_line(
+        runtime,
+        String::from_utf8_lossy(&stdout).trim_end(),
+        container_id,
+        &options.proc_root,
+    )
+}
+
+pub(crate) fn parse_inspect_line(
+    runtime: &'static str,
+    line: &str,
+    container_id: &str,
+    proc_root: &Path,
+) -> Result<ContainerProcess> {
+    let fields: Vec<&str> = line.splitn(4, ';').collect();
+    if fields.len() < 2 {
+        return Err(Error::ParseError(format!(
+            "unexpected {} output format for container '{}'",
+            runtime, container_id
+        )));
+    }
+
+    if fields[0] != "true" {
+        return Err(Error::NotRunning(format!(
+            "container '{}' is not running",
             container_id
-        )
-    })
+        )));
+    }
+
+    let pid = fields[1].parse::<pid_t>().map_err(|e| {
+        Error::ParseError(format!(
+            "invalid PID '{}' from {} for container '{}': {}",
+            fields[1], runtime, container_id, e
+        ))
+    })?;
+    let id = fields.get(2).copied().unwrap_or(container_id);
+    // docker prefixes container names with a slash
+    let name = fields
+        .get(3)
+        .map(|name| name.trim_start_matches('/'))
+        .unwrap_or(id);
+
+    Ok(ContainerProcess::new(
+        runtime,
+        id,
+        name,
+        pid,
+        container_id,
+        proc_root,
+    ))
 }
 
-impl Container for Docker {
-    fn lookup(&self, container_id: &str) -> Result<pid_t> {
-        let command = if cmd::which("docker-pid").is_some() {
-            vec!["docker-pid", container_id]
+/// Lists the running containers of a docker compatible CLI, i.e. `docker` or `podman`. `run`
+/// executes the CLI with the given arguments.
+pub(crate) fn list_docker_containers(
+    runtime: &'static str,
+    run: &dyn Fn(&[&str]) -> Result<Vec<u8>>,
+    proc_root: &Path,
+) -> Result<Vec<ContainerProcess>> {
+    let stdout = run(&["ps", "--quiet", "--no-trunc"])?;
+    let stdout = String::from_utf8_lossy(&stdout);
+    let ids = stdout.split_whitespace().collect::<Vec<_>>();
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut args = vec!["inspect", "--format", INSPECT_FORMAT];
+    args.extend(ids);
+    let stdout = run(&args)?;
+    let mut containers = vec![];
+    for line in String::from_utf8_lossy(&stdout).lines() {
+        if line.is_empty() {
+            continue;
+        }
+        match parse_inspect_line(runtime, line, "", proc_root) {
+            Ok(mut process) => {
+                process.query = process.id.clone();
+                containers.push(process);
+            }
+            // container stopped after `ps`
+            Err(Error::NotRunning(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(containers)
+}
+
+/// Whether `query` can only be a full ID or a name, which the daemon resolves itself. ID
+/// prefixes and Compose services need the list of containers instead.
+fn is_exact(query: &str) -> bool {
+    let hex = query.bytes().all(|b| b.is_ascii_hexdigit());
+    !query.contains('/')
+        && if hex {
+            query.len() == 64
         } else {
-            vec![
-                "docker",
-                "inspect",
-                "--format",
-                "{{.State.Running}};{{.State.Pid}}",
-                container_id,
-            ]
+            !query.is_empty()
+        }
+}
+
+/// Resolves `container_id` through the API of a daemon, the process is reported as resolved
+/// from `query`.
+fn lookup_socket(
+    socket: &Path,
+    container_id: &str,
+    query: &str,
+    proc_root: &Path,
+) -> Result<ContainerProcess> {
+    if is_exact(container_id) {
+        return lookup_api(socket, container_id, query, proc_root);
+    }
+    let containers = summaries_api(socket)?;
+    let container = resolve(&containers, container_id)?;
+    lookup_api(socket, &container.id, query, proc_root)
+}
+
+/// Resolves a container of the system daemon.
+fn lookup_system(container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+    if let Some(socket) = api_socket(options) {
+        return lookup_socket(&socket, container_id, container_id, &options.proc_root);
+    }
+
+    // docker-pid alone can only resolve what it is given
+    let id = if !is_exact(container_id) && cmd::which(options, "docker").is_some() {
+        let containers = summaries_cli(options)?;
+        resolve(&containers, container_id)?.id.clone()
+    } else {
+        container_id.to_string()
+    };
+    let command = if cmd::which(options, "docker-pid").is_some() {
+        vec!["docker-pid", &id]
+    } else {
+        // names may also be those of images, networks or volumes
+        vec![
+            "docker",
+            "inspect",
+            "--type",
+            "container",
+            "--format",
+            INSPECT_FORMAT,
+            &id,
+        ]
+    };
+    let mut process = parse_docker_output(options, "docker", command.as_slice(), &id)?;
+    process.query = container_id.to_string();
+    Ok(process)
+}
+
+/// Socket of the rootless daemon of a user.
+fn user_socket(user: &User, options: &LookupOptions) -> PathBuf {
+    user.runtime_dir(options).join("docker.sock")
+}
+
+/// Resolves `container_id` with the rootless daemon of `user`.
+fn lookup_user(
+    user: &User,
+    container_id: &str,
+    query: &str,
+    options: &LookupOptions,
+) -> Result<ContainerProcess> {
+    let socket = user_socket(user, options);
+    if !socket.exists() {
+        return Err(Error::DaemonUnreachable(format!(
+            "user '{}' has no rootless docker daemon running",
+            user.name
+        )));
+    }
+    lookup_socket(&socket, container_id, query, &options.proc_root)
+}
+
+/// Lists the running containers of a daemon through its API. Containers that can't be
+/// inspected are skipped as long as any other one could be.
+fn list_api(socket: &Path, proc_root: &Path) -> Result<Vec<ContainerProcess>> {
+    let mut processes = vec![];
+    let mut errors = vec![];
+    for container in summaries_api(socket)?.iter().filter(|c| c.running) {
+        match lookup_api(socket, &container.id, &container.id, proc_root) {
+            Ok(process) => processes.push(process),
+            // stopped since it was listed
+            Err(Error::NotFound(_)) | Err(Error::NotRunning(_)) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    match errors.into_iter().next() {
+        Some(e) if processes.is_empty() => Err(e),
+        _ => Ok(processes),
+    }
+}
+
+impl Container for Docker {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+    /// Accepts a full ID, a unique ID prefix, a name or a Compose `project/service[/index]`,
+    /// optionally prefixed with `user@` to search the rootless daemon of that user.
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        if let (Some(user), name) = rootless::split_user(container_id) {
+            return lookup_user(&User::by_name(user)?, name, container_id, options);
+        }
+        match lookup_system(container_id, options) {
+            Err(e @ Error::NotFound(_))
+            | Err(e @ Error::DaemonUnreachable(_))
+            | Err(e @ Error::ToolUnavailable(_)) => {
+                rootless::lookup_other_users(options, e, container_id, |user| {
+                    lookup_user(user, container_id, container_id, options)
+                })
+            }
+            res => res,
+        }
+    }
+    /// Lists the containers of the system daemon and of other users' rootless daemons. Daemons
+    /// that fail are skipped as long as any of them could be listed.
+    fn list(&self, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        let system = match api_socket(options) {
+            Some(socket) => list_api(&socket, &options.proc_root),
+            None => list_docker_containers(
+                self.name(),
+                &|args| cmd::run(options, "docker", args),
+                &options.proc_root,
+            ),
         };
-        parse_docker_output(command.as_slice(), container_id)
+        let (users, mut errors) = rootless::list_other_users(options, |user| {
+            list_api(&user_socket(user, options), &options.proc_root)
+        });
+
+        let mut answered = !users.is_empty();
+        let mut processes = vec![];
+        match system {
+            Ok(mut found) => {
+                answered = true;
+                processes.append(&mut found);
+            }
+            // without a system daemon the errors of the rootless ones are more telling
+            Err(e @ Error::DaemonUnreachable(_)) | Err(e @ Error::ToolUnavailable(_)) => {
+                errors.push(e)
+            }
+            Err(e) => errors.insert(0, e),
+        }
+        processes.extend(users);
+        match errors.into_iter().next() {
+            Some(e) if !answered => Err(e),
+            _ => Ok(processes),
+        }
     }
-    fn check_required_tools(&self) -> Result<()> {
-        if cmd::which("docker-pid").is_some() || cmd::which("docker").is_some() {
+    fn check_required_tools(&self, options: &LookupOptions) -> Result<()> {
+        let rootless = || {
+            rootless::other_users(options)
+                .is_ok_and(|users| users.iter().any(|user| user_socket(user, options).exists()))
+        };
+        if api_socket(options).is_some()
+            || cmd::which(options, "docker-pid").is_some()
+            || cmd::which(options, "docker").is_some()
+            || rootless()
+        {
             return Ok(());
         }
 
-        bail!("docker runtime not found: neither 'docker' nor 'docker-pid' command is available")
+        Err(Error::ToolUnavailable(String::from(
+            "docker runtime not found: no daemon socket and neither 'docker' nor 'docker-pid' command is available",
+        )))
     }
 }
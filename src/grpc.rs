@@ -0,0 +1,576 @@
+//! Minimal gRPC client for unary calls over a unix socket, as used by the CRI runtime service.
+//!
+//! Implements just enough of HTTP/2 (RFC 9113) to send one request per connection and to read the
+//! response and its trailers. Messages are sent uncompressed.
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::hpack;
+use crate::result::{Error, Result};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0;
+const HEADERS: u8 = 1;
+const RST_STREAM: u8 = 3;
+const SETTINGS: u8 = 4;
+const PING: u8 = 6;
+const GOAWAY: u8 = 7;
+const WINDOW_UPDATE: u8 = 8;
+const CONTINUATION: u8 = 9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+
+/// The only stream used on a connection.
+const STREAM_ID: u32 = 1;
+
+/// Largest response accepted, the flow control windows are opened this far up front so the
+/// server never has to wait for a window update.
+const MAX_RESPONSE_SIZE: u32 = 16 << 20;
+
+/// Default `SETTINGS_MAX_FRAME_SIZE`, the request is split into frames of at most this size.
+const MAX_FRAME_SIZE: usize = 16384;
+
+// https://grpc.github.io/grpc/core/md_doc_statuscodes.html
+const NOT_FOUND: u32 = 5;
+const PERMISSION_DENIED: u32 = 7;
+const UNIMPLEMENTED: u32 = 12;
+const UNAVAILABLE: u32 = 14;
+const UNAUTHENTICATED: u32 = 16;
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+/// Calls `method` (`/package.Service/Method`) with the encoded request message and returns the
+/// encoded response message.
+pub(crate) fn call(socket: &Path, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+    let stream = UnixStream::connect(socket).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+            Error::DaemonUnreachable(format!("cannot connect to '{}': {}", socket.display(), e))
+        }
+        _ => Error::io(format!("cannot connect to '{}'", socket.display()), e),
+    })?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|e| Error::io("failed to set socket timeout", e))?;
+    unary(stream, method, request)
+        .map_err(|e| e.context(format!("{} on '{}'", method, socket.display())))
+}
+
+fn unary<S: Read + Write>(mut stream: S, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+    let mut out = PREFACE.to_vec();
+    let mut settings = vec![];
+    for (id, value) in &[
+        (SETTINGS_ENABLE_PUSH, 0),
+        (SETTINGS_INITIAL_WINDOW_SIZE, MAX_RESPONSE_SIZE),
+    ] {
+        settings.extend_from_slice(&id.to_be_bytes());
+        settings.extend_from_slice(&value.to_be_bytes());
+    }
+    write_frame(&mut out, SETTINGS, 0, 0, &settings);
+    // the connection window is not covered by SETTINGS_INITIAL_WINDOW_SIZE
+    write_frame(
+        &mut out,
+        WINDOW_UPDATE,
+        0,
+        0,
+        &(MAX_RESPONSE_SIZE - 65535).to_be_bytes(),
+    );
+
+    let mut block = vec![];
+    for (name, value) in &[
+        (":method", "POST"),
+        (":scheme", "http"),
+        (":path", method),
+        (":authority", "localhost"),
+        ("content-type", "application/grpc"),
+        ("te", "trailers"),
+    ] {
+        hpack::encode_literal(&mut block, name, value);
+    }
+    write_frame(&mut out, HEADERS, END_HEADERS, STREAM_ID, &block);
+
+    let mut message = vec![0];
+    message.extend_from_slice(&(request.len() as u32).to_be_bytes());
+    message.extend_from_slice(request);
+    let mut chunks = message.chunks(MAX_FRAME_SIZE).peekable();
+    while let Some(chunk) = chunks.next() {
+        let flags = if chunks.peek().is_none() {
+            END_STREAM
+        } else {
+            0
+        };
+        write_frame(&mut out, DATA, flags, STREAM_ID, chunk);
+    }
+    stream
+        .write_all(&out)
+        .map_err(|e| Error::io("failed to send request", e))?;
+
+    let (headers, body) = read_response(&mut stream)?;
+    let header = |name: &str| {
+        headers
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let status = header(":status").unwrap_or_default();
+    if status != "200" {
+        return Err(Error::CommandFailed(format!(
+            "server responded with HTTP status {}",
+            status
+        )));
+    }
+    let code = header("grpc-status")
+        .ok_or_else(|| Error::ParseError(String::from("response without grpc-status")))?
+        .parse::<u32>()
+        .map_err(|_| Error::ParseError(String::from("invalid grpc-status")))?;
+    if code != 0 {
+        let message = percent_decode(header("grpc-message").unwrap_or_default());
+        return Err(status_error(code, message));
+    }
+
+    if body.len() < 5 {
+        return Err(Error::ParseError(String::from("response without message")));
+    }
+    if body[0] != 0 {
+        return Err(Error::ParseError(String::from(
+            "server sent a compressed message",
+        )));
+    }
+    let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    if body.len() < 5 + len {
+        return Err(Error::ParseError(String::from(
+            "truncated response message",
+        )));
+    }
+    Ok(body[5..5 + len].to_vec())
+}
+
+fn status_error(code: u32, message: String) -> Error {
+    match code {
+        NOT_FOUND => Error::NotFound(message),
+        PERMISSION_DENIED | UNAUTHENTICATED => Error::PermissionDenied(message),
+        UNAVAILABLE => Error::DaemonUnreachable(message),
+        UNIMPLEMENTED => Error::Unsupported(message),
+        _ => Error::CommandFailed(format!("{} (gRPC status {})", message, code)),
+    }
+}
+
+/// Reads frames until the server closes the stream, returns headers and trailers as well as the
+/// concatenated body.
+fn read_response<S: Read + Write>(stream: &mut S) -> Result<(hpack::Headers, Vec<u8>)> {
+    let mut decoder = hpack::Decoder::new();
+    let mut headers = vec![];
+    let mut body = vec![];
+    // header block fragments until END_HEADERS, and whether the stream ends with the block
+    let mut pending: Option<(Vec<u8>, bool)> = None;
+
+    loop {
+        let frame = read_frame(stream)?;
+        if let Some((mut block, end_stream)) = pending.take() {
+            if frame.kind != CONTINUATION || frame.stream != STREAM_ID {
+                return Err(protocol_error("expected CONTINUATION frame"));
+            }
+            block.extend_from_slice(&frame.payload);
+            if frame.flags & END_HEADERS == 0 {
+                pending = Some((block, end_stream));
+                continue;
+            }
+            headers.append(&mut decoder.decode(&block)?);
+            if end_stream {
+                return Ok((headers, body));
+            }
+            continue;
+        }
+
+        match frame.kind {
+            SETTINGS if frame.flags & ACK == 0 => {
+                let mut ack = vec![];
+                write_frame(&mut ack, SETTINGS, ACK, 0, &[]);
+                stream
+                    .write_all(&ack)
+                    .map_err(|e| Error::io("failed to acknowledge settings", e))?;
+            }
+            PING if frame.flags & ACK == 0 => {
+                let mut pong = vec![];
+                write_frame(&mut pong, PING, ACK, 0, &frame.payload);
+                stream
+                    .write_all(&pong)
+                    .map_err(|e| Error::io("failed to answer ping", e))?;
+            }
+            GOAWAY => {
+                let reason = String::from_utf8_lossy(frame.payload.get(8..).unwrap_or_default());
+                return Err(Error::CommandFailed(format!(
+                    "server closed the connection: {}",
+                    reason
+                )));
+            }
+            RST_STREAM if frame.stream == STREAM_ID => {
+                let code = frame
+                    .payload
+                    .get(..4)
+                    .map_or(0, |c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]));
+                return Err(Error::CommandFailed(format!(
+                    "server reset the stream with error code {}",
+                    code
+                )));
+            }
+            HEADERS if frame.stream == STREAM_ID => {
+                let mut fragment = unpad(&frame)?;
+                if frame.flags & PRIORITY != 0 {
+                    fragment = fragment
+                        .get(5..)
+                        .ok_or_else(|| protocol_error("truncated HEADERS frame"))?;
+                }
+                let end_stream = frame.flags & END_STREAM != 0;
+                if frame.flags & END_HEADERS == 0 {
+                    pending = Some((fragment.to_vec(), end_stream));
+                    continue;
+                }
+                headers.append(&mut decoder.decode(fragment)?);
+                if end_stream {
+                    return Ok((headers, body));
+                }
+            }
+            DATA if frame.stream == STREAM_ID => {
+                body.extend_from_slice(unpad(&frame)?);
+                if body.len() > MAX_RESPONSE_SIZE as usize {
+                    return Err(protocol_error("response exceeds the flow control window"));
+                }
+                if frame.flags & END_STREAM != 0 {
+                    return Ok((headers, body));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn protocol_error(msg: &str) -> Error {
+    Error::ParseError(format!("HTTP/2 protocol error: {}", msg))
+}
+
+/// Payload of a DATA or HEADERS frame without padding.
+fn unpad(frame: &Frame) -> Result<&[u8]> {
+    if frame.flags & PADDED == 0 {
+        return Ok(&frame.payload);
+    }
+    let pad = *frame.payload.first().unwrap_or(&0) as usize;
+    if frame.payload.len() < 1 + pad {
+        return Err(protocol_error("invalid padding"));
+    }
+    Ok(&frame.payload[1..frame.payload.len() - pad])
+}
+
+fn read_frame<S: Read>(stream: &mut S) -> Result<Frame> {
+    let mut header = [0u8; 9];
+    stream
+        .read_exact(&mut header)
+        .map_err(|e| Error::io("failed to read response", e))?;
+    let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    let mut payload = vec![0; len];
+    stream
+        .read_exact(&mut payload)
+        .map_err(|e| Error::io("failed to read response", e))?;
+    Ok(Frame {
+        kind: header[3],
+        flags: header[4],
+        stream: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff,
+        payload,
+    })
+}
+
+fn write_frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Decodes the percent-encoded `grpc-message` trailer.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    /// What a test server answers: the response message, or a gRPC status code and message.
+    pub(crate) type Reply = std::result::Result<Vec<u8>, (u32, &'static str)>;
+
+    /// Answers a call to a method with the encoded request message.
+    pub(crate) type Handler = fn(&str, &[u8]) -> Reply;
+
+    fn header_block(headers: &[(&str, &str)]) -> Vec<u8> {
+        let mut block = vec![];
+        for (name, value) in headers {
+            hpack::encode_literal(&mut block, name, value);
+        }
+        block
+    }
+
+    /// The frames of a complete response, as a server would send them.
+    fn response_frames(reply: &Reply) -> Vec<u8> {
+        let mut out = vec![];
+        write_frame(&mut out, SETTINGS, 0, 0, &[]);
+        match reply {
+            Ok(message) => {
+                let headers =
+                    header_block(&[(":status", "200"), ("content-type", "application/grpc")]);
+                write_frame(&mut out, HEADERS, END_HEADERS, STREAM_ID, &headers);
+                let mut body = vec![0];
+                body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+                body.extend_from_slice(message);
+                write_frame(&mut out, DATA, 0, STREAM_ID, &body);
+                let trailers = header_block(&[("grpc-status", "0")]);
+                write_frame(
+                    &mut out,
+                    HEADERS,
+                    END_HEADERS | END_STREAM,
+                    STREAM_ID,
+                    &trailers,
+                );
+            }
+            Err((code, message)) => {
+                // trailers-only response
+                let code = code.to_string();
+                let headers = header_block(&[
+                    (":status", "200"),
+                    ("grpc-status", &code),
+                    ("grpc-message", message),
+                ]);
+                write_frame(
+                    &mut out,
+                    HEADERS,
+                    END_HEADERS | END_STREAM,
+                    STREAM_ID,
+                    &headers,
+                );
+            }
+        }
+        out
+    }
+
+    /// Reads one request from a client, returns the method and the request message.
+    fn read_request<S: Read>(stream: &mut S) -> (String, Vec<u8>) {
+        let mut preface = [0; 24];
+        stream.read_exact(&mut preface).unwrap();
+        assert_eq!(&preface[..], PREFACE);
+        let mut decoder = hpack::Decoder::new();
+        let mut method = None;
+        let mut body = vec![];
+        loop {
+            let frame = read_frame(stream).unwrap();
+            match frame.kind {
+                HEADERS => {
+                    let headers = decoder.decode(&frame.payload).unwrap();
+                    assert!(headers.contains(&("te".to_string(), "trailers".to_string())));
+                    method = headers
+                        .into_iter()
+                        .find(|(n, _)| n == ":path")
+                        .map(|(_, v)| v);
+                }
+                DATA => {
+                    body.extend_from_slice(&frame.payload);
+                    if frame.flags & END_STREAM != 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(body[0], 0, "compressed request");
+        (method.expect("request without :path"), body[5..].to_vec())
+    }
+
+    /// Serves unary calls on a new socket at `path`, answering with `handler(method, request)`.
+    pub(crate) fn serve(path: &Path, handler: Handler) {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let (method, request) = read_request(&mut stream);
+                stream
+                    .write_all(&response_frames(&handler(&method, &request)))
+                    .unwrap();
+                // wait for the client to close the connection, it still acknowledges the settings
+                let _ = stream.read_to_end(&mut vec![]);
+            }
+        });
+    }
+
+    /// A connection whose server side is scripted, the client's writes are kept.
+    struct Mock {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn mock(input: Vec<u8>) -> Mock {
+        Mock {
+            input: Cursor::new(input),
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn request_frames() {
+        let mut stream = mock(response_frames(&Ok(b"pong".to_vec())));
+        let response = unary(&mut stream, "/test.Service/Ping", b"ping").unwrap();
+        assert_eq!(response, b"pong");
+        let (method, request) = read_request(&mut Cursor::new(&stream.output));
+        assert_eq!(method, "/test.Service/Ping");
+        assert_eq!(request, b"ping");
+    }
+
+    #[test]
+    fn continuation_padding_and_ping() {
+        let mut input = vec![];
+        write_frame(&mut input, PING, 0, 0, b"12345678");
+        let headers = header_block(&[(":status", "200"), ("content-type", "application/grpc")]);
+        let (first, second) = headers.split_at(4);
+        // padded with 2 bytes, the block continues in a CONTINUATION frame
+        let mut padded = vec![2];
+        padded.extend_from_slice(first);
+        padded.extend_from_slice(&[0, 0]);
+        write_frame(&mut input, HEADERS, PADDED, STREAM_ID, &padded);
+        write_frame(&mut input, CONTINUATION, END_HEADERS, STREAM_ID, second);
+        write_frame(&mut input, DATA, 0, STREAM_ID, &[0, 0, 0, 0, 2, b'o']);
+        write_frame(&mut input, DATA, 0, STREAM_ID, b"k");
+        let trailers = header_block(&[("grpc-status", "0")]);
+        write_frame(
+            &mut input,
+            HEADERS,
+            END_HEADERS | END_STREAM,
+            STREAM_ID,
+            &trailers,
+        );
+
+        let mut stream = mock(input);
+        assert_eq!(unary(&mut stream, "/test.Service/Get", b"").unwrap(), b"ok");
+        let mut pong = vec![];
+        write_frame(&mut pong, PING, ACK, 0, b"12345678");
+        assert!(stream.output.ends_with(&pong));
+    }
+
+    #[test]
+    fn status_codes() {
+        for (code, check) in &[
+            (
+                NOT_FOUND,
+                (|e| matches!(e, Error::NotFound(_))) as fn(&Error) -> bool,
+            ),
+            (PERMISSION_DENIED, |e| {
+                matches!(e, Error::PermissionDenied(_))
+            }),
+            (UNIMPLEMENTED, |e| matches!(e, Error::Unsupported(_))),
+            (UNAVAILABLE, |e| matches!(e, Error::DaemonUnreachable(_))),
+            (2, |e| matches!(e, Error::CommandFailed(_))),
+        ] {
+            let mut stream = mock(response_frames(&Err((*code, "no%20such%20container"))));
+            let err = unary(&mut stream, "/test.Service/Get", b"").unwrap_err();
+            assert!(check(&err), "{}: {:?}", code, err);
+            assert!(err.to_string().contains("no such container"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn missing_grpc_status() {
+        let mut input = vec![];
+        let headers = header_block(&[(":status", "200")]);
+        write_frame(
+            &mut input,
+            HEADERS,
+            END_HEADERS | END_STREAM,
+            STREAM_ID,
+            &headers,
+        );
+        let res = unary(&mut mock(input), "/test.Service/Get", b"");
+        assert!(matches!(res, Err(Error::ParseError(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn reset_stream() {
+        let mut input = vec![];
+        write_frame(&mut input, RST_STREAM, 0, STREAM_ID, &8u32.to_be_bytes());
+        let res = unary(&mut mock(input), "/test.Service/Get", b"");
+        assert!(matches!(res, Err(Error::CommandFailed(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn unix_socket_call() {
+        let dir = std::env::temp_dir().join(format!("container-pid-grpc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("echo.sock");
+        serve(&socket, |method, request| {
+            assert_eq!(method, "/test.Service/Echo");
+            Ok(request.to_vec())
+        });
+        assert_eq!(
+            call(&socket, "/test.Service/Echo", b"hello").unwrap(),
+            b"hello"
+        );
+
+        let res = call(&dir.join("missing.sock"), "/test.Service/Echo", b"");
+        assert!(matches!(res, Err(Error::DaemonUnreachable(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("a%20b%zz%2"), "a b%zz%2");
+    }
+}
@@ -0,0 +1,852 @@
+//! Header compression for HTTP/2 as described in RFC 7541.
+//!
+//! The decoder implements the full format including the dynamic table and Huffman coded strings,
+//! the encoder only emits literals that are neither indexed nor Huffman coded.
+
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+use crate::result::{Error, Result};
+
+/// Entries of the static table, index 1 is the first entry (RFC 7541, Appendix A).
+const STATIC_TABLE: &[(&str, &str)] = &[
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Huffman code and its length in bits for every byte value (RFC 7541, Appendix B).
+const HUFFMAN_CODES: [(u32, u8); 256] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+];
+
+/// Size of the dynamic table before the peer changes it, `SETTINGS_HEADER_TABLE_SIZE` default.
+const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Every dynamic table entry is accounted with this overhead on top of name and value.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Decoded (name, value) pairs in the order they were sent.
+pub(crate) type Headers = Vec<(String, String)>;
+
+pub(crate) struct Decoder {
+    /// most recently added entry first
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Decoder {
+    pub(crate) fn new() -> Decoder {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    /// Decodes a complete header block into (name, value) pairs.
+    pub(crate) fn decode(&mut self, mut block: &[u8]) -> Result<Headers> {
+        let mut headers = vec![];
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                let index = read_integer(&mut block, 7)?;
+                headers.push(self.entry(index)?);
+            } else if first & 0x40 != 0 {
+                let header = self.read_literal(&mut block, 6)?;
+                self.insert(header.clone());
+                headers.push(header);
+            } else if first & 0x20 != 0 {
+                let size = read_integer(&mut block, 5)?;
+                if size > DEFAULT_TABLE_SIZE {
+                    return Err(invalid("dynamic table size exceeds the advertised limit"));
+                }
+                self.max_size = size;
+                self.evict();
+            } else {
+                // literal without indexing or never indexed
+                headers.push(self.read_literal(&mut block, 4)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String)> {
+        if index == 0 {
+            return Err(invalid("header index 0"));
+        }
+        if let Some((name, value)) = STATIC_TABLE.get(index - 1) {
+            return Ok((name.to_string(), value.to_string()));
+        }
+        self.table
+            .get(index - 1 - STATIC_TABLE.len())
+            .cloned()
+            .ok_or_else(|| invalid("header index out of range"))
+    }
+
+    fn read_literal(&self, block: &mut &[u8], prefix: u8) -> Result<(String, String)> {
+        let index = read_integer(block, prefix)?;
+        let name = if index == 0 {
+            read_string(block)?
+        } else {
+            self.entry(index)?.0
+        };
+        Ok((name, read_string(block)?))
+    }
+
+    fn insert(&mut self, header: (String, String)) {
+        self.size += header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+        self.table.push_front(header);
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::ParseError(format!("invalid HTTP/2 header block: {}", msg))
+}
+
+/// Reads an integer whose first byte uses the lowest `prefix` bits.
+fn read_integer(block: &mut &[u8], prefix: u8) -> Result<usize> {
+    let (&first, mut rest) = block
+        .split_first()
+        .ok_or_else(|| invalid("truncated integer"))?;
+    let max_prefix = (1usize << prefix) - 1;
+    let mut value = first as usize & max_prefix;
+    if value == max_prefix {
+        let mut shift = 0;
+        loop {
+            let (&byte, tail) = rest
+                .split_first()
+                .ok_or_else(|| invalid("truncated integer"))?;
+            rest = tail;
+            if shift > 28 {
+                return Err(invalid("integer overflow"));
+            }
+            value += ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+    *block = rest;
+    Ok(value)
+}
+
+fn read_string(block: &mut &[u8]) -> Result<String> {
+    let huffman = block.first().is_some_and(|b| b & 0x80 != 0);
+    let len = read_integer(block, 7)?;
+    if block.len() < len {
+        return Err(invalid("truncated string"));
+    }
+    let (data, rest) = block.split_at(len);
+    *block = rest;
+    let bytes = if huffman {
+        decode_huffman(data)?
+    } else {
+        data.to_vec()
+    };
+    String::from_utf8(bytes).map_err(|_| invalid("header is not valid UTF-8"))
+}
+
+/// Marks a child of a `HuffmanTree` node as a decoded symbol instead of another node.
+const SYMBOL: u16 = 0x8000;
+
+/// Binary tree of the Huffman codes: node `n` has the children `nodes[n][0]` and `nodes[n][1]`
+/// for the next bit, 0 for no child, as the root is never a child.
+struct HuffmanTree {
+    nodes: Vec<[u16; 2]>,
+}
+
+impl HuffmanTree {
+    fn get() -> &'static HuffmanTree {
+        static TREE: OnceLock<HuffmanTree> = OnceLock::new();
+        TREE.get_or_init(|| {
+            let mut nodes = vec![[0u16; 2]];
+            for (symbol, &(code, len)) in HUFFMAN_CODES.iter().enumerate() {
+                let mut node = 0;
+                for shift in (0..len).rev() {
+                    let bit = ((code >> shift) & 1) as usize;
+                    if shift == 0 {
+                        nodes[node][bit] = SYMBOL | symbol as u16;
+                    } else {
+                        if nodes[node][bit] == 0 {
+                            nodes.push([0; 2]);
+                            nodes[node][bit] = (nodes.len() - 1) as u16;
+                        }
+                        node = usize::from(nodes[node][bit]);
+                    }
+                }
+            }
+            HuffmanTree { nodes }
+        })
+    }
+}
+
+fn decode_huffman(data: &[u8]) -> Result<Vec<u8>> {
+    let tree = HuffmanTree::get();
+    let mut decoded = vec![];
+    let mut node = 0;
+    // bits since the last symbol, and whether all of them were ones
+    let mut len = 0u8;
+    let mut ones = true;
+    for byte in data {
+        for shift in (0..8).rev() {
+            let bit = (byte >> shift) & 1;
+            len += 1;
+            ones &= bit == 1;
+            match tree.nodes[node][usize::from(bit)] {
+                // the EOS code or not a code at all
+                0 => return Err(invalid("invalid Huffman code")),
+                child if child & SYMBOL != 0 => {
+                    decoded.push((child & !SYMBOL) as u8);
+                    node = 0;
+                    len = 0;
+                    ones = true;
+                }
+                child => node = usize::from(child),
+            }
+        }
+    }
+    // the last byte is padded with the most significant bits of the EOS code, which are all ones
+    if len > 7 || !ones {
+        return Err(invalid("invalid Huffman padding"));
+    }
+    Ok(decoded)
+}
+
+/// Appends a header as literal without indexing and with a literal name.
+pub(crate) fn encode_literal(out: &mut Vec<u8>, name: &str, value: &str) {
+    out.push(0);
+    for s in &[name, value] {
+        encode_integer(out, 0, 7, s.len());
+        out.extend_from_slice(s.as_bytes());
+    }
+}
+
+fn encode_integer(out: &mut Vec<u8>, flags: u8, prefix: u8, value: usize) {
+    let max_prefix = (1usize << prefix) - 1;
+    if value < max_prefix {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max_prefix as u8);
+    let mut rest = value - max_prefix;
+    while rest >= 0x80 {
+        out.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> Headers {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// A header block in hex, the headers it encodes and the dynamic table size afterwards.
+    type Example<'a> = (&'a str, &'a [(&'a str, &'a str)], usize);
+
+    /// Decodes the blocks of one of the RFC 7541 examples in order, checking the headers and the
+    /// dynamic table after each block.
+    fn check_sequence(decoder: &mut Decoder, blocks: &[Example<'_>]) {
+        for (block, expected, size) in blocks {
+            assert_eq!(decoder.decode(&hex(block)).unwrap(), headers(expected));
+            assert_eq!(decoder.size, *size);
+        }
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc7541#appendix-C.2
+    #[test]
+    fn header_field_representations() {
+        let mut decoder = Decoder::new();
+        let block = "400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572";
+        check_sequence(
+            &mut decoder,
+            &[(block, &[("custom-key", "custom-header")], 55)],
+        );
+
+        let mut decoder = Decoder::new();
+        check_sequence(
+            &mut decoder,
+            &[
+                (
+                    "040c 2f73 616d 706c 652f 7061 7468",
+                    &[(":path", "/sample/path")],
+                    0,
+                ),
+                (
+                    "1008 7061 7373 776f 7264 0673 6563 7265 74",
+                    &[("password", "secret")],
+                    0,
+                ),
+                ("82", &[(":method", "GET")], 0),
+            ],
+        );
+        assert!(decoder.table.is_empty());
+    }
+
+    const REQUESTS: [&[(&str, &str)]; 3] = [
+        &[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ],
+        &[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+            ("cache-control", "no-cache"),
+        ],
+        &[
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value"),
+        ],
+    ];
+
+    fn check_request_table(decoder: &Decoder) {
+        let table: Vec<_> = decoder.table.iter().cloned().collect();
+        assert_eq!(
+            table,
+            headers(&[
+                ("custom-key", "custom-value"),
+                ("cache-control", "no-cache"),
+                (":authority", "www.example.com"),
+            ])
+        );
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc7541#appendix-C.3
+    #[test]
+    fn requests_without_huffman() {
+        let mut decoder = Decoder::new();
+        check_sequence(
+            &mut decoder,
+            &[
+                (
+                    "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                    REQUESTS[0],
+                    57,
+                ),
+                ("8286 84be 5808 6e6f 2d63 6163 6865", REQUESTS[1], 110),
+                (
+                    "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+                    REQUESTS[2],
+                    164,
+                ),
+            ],
+        );
+        check_request_table(&decoder);
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc7541#appendix-C.4
+    #[test]
+    fn requests_with_huffman() {
+        let mut decoder = Decoder::new();
+        check_sequence(
+            &mut decoder,
+            &[
+                (
+                    "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+                    REQUESTS[0],
+                    57,
+                ),
+                ("8286 84be 5886 a8eb 1064 9cbf", REQUESTS[1], 110),
+                (
+                    "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+                    REQUESTS[2],
+                    164,
+                ),
+            ],
+        );
+        check_request_table(&decoder);
+    }
+
+    const RESPONSES: [&[(&str, &str)]; 3] = [
+        &[
+            (":status", "302"),
+            ("cache-control", "private"),
+            ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+            ("location", "https://www.example.com"),
+        ],
+        &[
+            (":status", "307"),
+            ("cache-control", "private"),
+            ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+            ("location", "https://www.example.com"),
+        ],
+        &[
+            (":status", "200"),
+            ("cache-control", "private"),
+            ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+            ("location", "https://www.example.com"),
+            ("content-encoding", "gzip"),
+            (
+                "set-cookie",
+                "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1",
+            ),
+        ],
+    ];
+
+    /// The response examples use a 256 byte table, so entries get evicted. The server announces
+    /// the size with a dynamic table size update.
+    fn response_decoder() -> Decoder {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(&hex("3fe1 01")).unwrap(), vec![]);
+        assert_eq!(decoder.max_size, 256);
+        decoder
+    }
+
+    fn check_response_table(decoder: &Decoder) {
+        let table: Vec<_> = decoder.table.iter().cloned().collect();
+        assert_eq!(
+            table,
+            headers(&[
+                (
+                    "set-cookie",
+                    "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1",
+                ),
+                ("content-encoding", "gzip"),
+                ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+            ])
+        );
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc7541#appendix-C.5
+    #[test]
+    fn responses_without_huffman() {
+        let mut decoder = response_decoder();
+        check_sequence(
+            &mut decoder,
+            &[
+                (
+                    "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230
+                     3133 2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65
+                     7861 6d70 6c65 2e63 6f6d",
+                    RESPONSES[0],
+                    222,
+                ),
+                ("4803 3330 37c1 c0bf", RESPONSES[1], 222),
+                (
+                    "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220
+                     474d 54c0 5a04 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157
+                     454f 5049 5541 5851 5745 4f49 553b 206d 6178 2d61 6765 3d33 3630 303b 2076
+                     6572 7369 6f6e 3d31",
+                    RESPONSES[2],
+                    215,
+                ),
+            ],
+        );
+        check_response_table(&decoder);
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc7541#appendix-C.6
+    #[test]
+    fn responses_with_huffman() {
+        let mut decoder = response_decoder();
+        check_sequence(
+            &mut decoder,
+            &[
+                (
+                    "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0
+                     82a6 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+                    RESPONSES[0],
+                    222,
+                ),
+                ("4883 640e ffc1 c0bf", RESPONSES[1], 222),
+                (
+                    "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b
+                     d9ab 77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27
+                     0fb5 291f 9587 3160 65c0 03ed 4ee5 b106 3d50 07",
+                    RESPONSES[2],
+                    215,
+                ),
+            ],
+        );
+        check_response_table(&decoder);
+    }
+
+    #[test]
+    fn invalid_blocks() {
+        for block in &[
+            // index 0
+            "80",
+            // beyond the empty dynamic table
+            "be",
+            // string longer than the block
+            "0003 6162",
+            // integer continuation missing
+            "7f",
+            // table size above the advertised limit
+            "3fe2 1f",
+            // padding that is not a prefix of EOS
+            "0081 00",
+        ] {
+            let res = Decoder::new().decode(&hex(block));
+            assert!(
+                matches!(res, Err(Error::ParseError(_))),
+                "{}: {:?}",
+                block,
+                res
+            );
+        }
+    }
+
+    #[test]
+    fn literal_round_trip() {
+        let long = "x".repeat(300);
+        let mut block = vec![];
+        encode_literal(&mut block, ":path", "/runtime.v1.RuntimeService/Version");
+        encode_literal(&mut block, "long", &long);
+        let mut decoder = Decoder::new();
+        assert_eq!(
+            decoder.decode(&block).unwrap(),
+            headers(&[
+                (":path", "/runtime.v1.RuntimeService/Version"),
+                ("long", &long),
+            ])
+        );
+        assert!(decoder.table.is_empty());
+    }
+}
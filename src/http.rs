@@ -0,0 +1,256 @@
+//! Minimal HTTP/1.1 client for the REST APIs that container runtimes expose on unix sockets.
+//!
+//! Only what is needed to fetch small JSON documents is implemented: `GET` requests, one request
+//! per connection and `Content-Length` or chunked response bodies.
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::result::{Error, Result};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Response {
+    pub(crate) fn json(&self) -> Result<serde_json::Value> {
+        serde_json::from_slice(&self.body)
+            .map_err(|e| Error::ParseError(format!("invalid JSON in API response: {}", e)))
+    }
+
+    /// Message of an API error response, most runtimes return `{"message": "..."}`, LXD returns
+    /// `{"error": "..."}`.
+    pub(crate) fn error_message(&self) -> String {
+        let message = self.json().ok().and_then(|v| {
+            v["message"]
+                .as_str()
+                .or_else(|| v["error"].as_str())
+                .map(String::from)
+        });
+        message.unwrap_or_else(|| String::from_utf8_lossy(&self.body).trim().to_string())
+    }
+}
+
+/// Sends `GET path` to the HTTP server listening on the unix socket `socket`.
+pub(crate) fn get(socket: &Path, path: &str) -> Result<Response> {
+    let stream = UnixStream::connect(socket).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+            Error::DaemonUnreachable(format!("cannot connect to '{}': {}", socket.display(), e))
+        }
+        _ => Error::io(format!("cannot connect to '{}'", socket.display()), e),
+    })?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|e| Error::io("failed to set socket timeout", e))?;
+    request(stream, "localhost", path, &[])
+        .map_err(|e| e.context(format!("GET {} on '{}'", path, socket.display())))
+}
+
+/// Sends a `GET` request with additional `headers` over an established connection and reads the
+/// response.
+pub(crate) fn request<S: Read + Write>(
+    mut stream: S,
+    host: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Result<Response> {
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nAccept: application/json\r\nConnection: close\r\n",
+        path, host
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| Error::io("failed to send request", e))?;
+
+    let mut raw = vec![];
+    stream
+        .read_to_end(&mut raw)
+        .map_err(|e| Error::io("failed to read response", e))?;
+    parse_response(&raw)
+}
+
+fn parse_response(raw: &[u8]) -> Result<Response> {
+    let header_end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| Error::ParseError(String::from("incomplete HTTP response header")))?;
+    let head = String::from_utf8_lossy(&raw[..header_end]);
+    let body = &raw[header_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    // HTTP/1.1 200 OK
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| Error::ParseError(String::from("invalid HTTP status line")))?;
+
+    let mut chunked = false;
+    let mut content_length = None;
+    for line in lines {
+        let fields: Vec<&str> = line.splitn(2, ':').collect();
+        if fields.len() != 2 {
+            continue;
+        }
+        let value = fields[1].trim();
+        if fields[0].eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if fields[0].eq_ignore_ascii_case("content-length") {
+            content_length = value.parse::<usize>().ok();
+        }
+    }
+
+    let body = if chunked {
+        decode_chunked(body)?
+    } else if let Some(len) = content_length {
+        if body.len() < len {
+            return Err(Error::ParseError(String::from(
+                "truncated HTTP response body",
+            )));
+        }
+        body[..len].to_vec()
+    } else {
+        body.to_vec()
+    };
+    Ok(Response { status, body })
+}
+
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+    let invalid = || Error::ParseError(String::from("invalid chunked HTTP response body"));
+    let mut body = vec![];
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(invalid)?;
+        let size = String::from_utf8_lossy(&data[..line_end]);
+        // chunk extensions are separated by ';'
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid())?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        // a garbled size must not overflow
+        match size.checked_add(2) {
+            Some(len) if data.len() >= len => {}
+            _ => return Err(invalid()),
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
+
+/// Percent-encodes a user supplied string so it can be used as a single path segment.
+pub(crate) fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::thread;
+
+    /// Serves `response` to a single client on a fresh socket and returns the socket path.
+    fn serve(name: &str, response: &'static [u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("container-pid-http-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join(name);
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                assert!(
+                    n > 0,
+                    "client closed the connection before the request ended"
+                );
+                request.extend_from_slice(&buf[..n]);
+            }
+            assert!(request.starts_with(b"GET /containers/json HTTP/1.1\r\n"));
+            stream.write_all(response).unwrap();
+        });
+        socket
+    }
+
+    #[test]
+    fn content_length() {
+        let socket = serve(
+            "content-length.sock",
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 8\r\n\r\n[\"a\",1]\n",
+        );
+        let response = get(&socket, "/containers/json").unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.json().unwrap(), serde_json::json!(["a", 1]));
+    }
+
+    #[test]
+    fn chunked() {
+        let socket = serve(
+            "chunked.sock",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4;ext=1\r\n[\"a\"\r\n3\r\n,1]\r\n0\r\n\r\n",
+        );
+        let response = get(&socket, "/containers/json").unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.json().unwrap(), serde_json::json!(["a", 1]));
+    }
+
+    #[test]
+    fn error_status() {
+        let socket = serve(
+            "error.sock",
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 35\r\n\r\n{\"message\":\"No such container: db\"}",
+        );
+        let response = get(&socket, "/containers/json").unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.error_message(), "No such container: db");
+    }
+
+    #[test]
+    fn unreachable() {
+        let res = get(Path::new("/nonexistent/docker.sock"), "/containers/json");
+        assert!(matches!(res, Err(Error::DaemonUnreachable(_))));
+    }
+
+    #[test]
+    fn invalid_chunk_size() {
+        for body in [
+            &b"ffffffffffffffff\r\nabc\r\n0\r\n\r\n"[..],
+            b"zz\r\n",
+            b"5\r\nab\r\n",
+        ] {
+            let res = decode_chunked(body);
+            assert!(matches!(res, Err(Error::ParseError(_))), "{:?}", res.err());
+        }
+    }
+
+    #[test]
+    fn truncated_body() {
+        let res = parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n{}");
+        assert!(matches!(res, Err(Error::ParseError(_))));
+    }
+}
@@ -0,0 +1,874 @@
+//! Client for the Kubernetes API server, used instead of `kubectl` with the `kube-api` feature.
+//!
+//! The connection is configured like kubectl does it: from the kubeconfig files (`--kubeconfig`,
+//! `$KUBECONFIG` or `~/.kube/config`), or else from the service account a pod gets when it runs
+//! in the cluster. Tokens, basic auth and client certificates are supported as credentials,
+//! `exec` and `auth-provider` plugins are not.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use openssl::base64;
+use openssl::error::ErrorStack;
+use openssl::pkey::PKey;
+use openssl::ssl::{SslConnector, SslConnectorBuilder, SslMethod, SslVerifyMode};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::X509;
+use serde_json::Value;
+
+use crate::http;
+use crate::result::{Error, Result};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where the kubelet mounts the credentials of the service account into a pod.
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+pub(crate) struct Client {
+    host: String,
+    port: u16,
+    /// Path of the server URL, API paths are appended to it.
+    prefix: String,
+    /// `None` for `http://` servers.
+    tls: Option<SslConnector>,
+    /// Name the server certificate is verified against.
+    server_name: String,
+    authorization: Option<String>,
+    /// Namespace of the kubeconfig context or of the service account.
+    pub(crate) namespace: Option<String>,
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| Error::io(format!("failed to read '{}'", path.display()), e))
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::ParseError(format!("invalid kubeconfig: {}", message.into()))
+}
+
+/// The kubeconfig files kubectl would read, only those that exist.
+fn kubeconfig_paths(kubeconfig: Option<&Path>) -> Vec<PathBuf> {
+    if let Some(path) = kubeconfig {
+        // a missing file given explicitly is reported when it is read
+        return vec![path.to_path_buf()];
+    }
+    let paths = match env::var_os("KUBECONFIG") {
+        Some(paths) => env::split_paths(&paths).collect(),
+        None => match env::var_os("HOME") {
+            Some(home) => vec![Path::new(&home).join(".kube").join("config")],
+            None => vec![],
+        },
+    };
+    paths
+        .into_iter()
+        .filter(|p| !p.as_os_str().is_empty() && p.is_file())
+        .collect()
+}
+
+fn in_cluster() -> bool {
+    env::var_os("KUBERNETES_SERVICE_HOST").is_some()
+        && Path::new(SERVICE_ACCOUNT_DIR).join("token").is_file()
+}
+
+/// Whether there is a kubeconfig or a service account to configure a client with.
+pub(crate) fn is_configured(kubeconfig: Option<&Path>) -> bool {
+    !kubeconfig_paths(kubeconfig).is_empty() || in_cluster()
+}
+
+/// A kubeconfig file, relative paths in it are relative to the file.
+struct Kubeconfig {
+    dir: PathBuf,
+    config: Value,
+}
+
+impl Kubeconfig {
+    fn load(path: &Path) -> Result<Kubeconfig> {
+        let content = read_file(path)?;
+        let config: Value = serde_yaml_ng::from_slice(&content)
+            .map_err(|e| invalid(format!("'{}': {}", path.display(), e)))?;
+        Ok(Kubeconfig {
+            dir: path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+            config,
+        })
+    }
+
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+}
+
+/// Finds the entry `name` of the list `field` (`clusters`, `users` or `contexts`). Like kubectl,
+/// the first file that defines an entry wins.
+fn named<'a>(
+    configs: &'a [Kubeconfig],
+    field: &str,
+    key: &str,
+    name: &str,
+) -> Option<(&'a Kubeconfig, &'a Value)> {
+    configs.iter().find_map(|config| {
+        config.config[field]
+            .as_array()?
+            .iter()
+            .find(|entry| entry["name"].as_str() == Some(name))
+            .map(|entry| (config, &entry[key]))
+    })
+}
+
+/// Inline `*-data` fields are base64 encoded, the others are paths.
+fn data_or_file(config: &Kubeconfig, entry: &Value, field: &str) -> Result<Option<Vec<u8>>> {
+    if let Some(data) = entry[format!("{}-data", field)].as_str() {
+        let data = base64::decode_block(data.trim())
+            .map_err(|e| invalid(format!("{}-data is not base64: {}", field, e)))?;
+        return Ok(Some(data));
+    }
+    match entry[field].as_str() {
+        Some(path) => read_file(&config.resolve(path)).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn tls_error(message: &str, e: impl std::fmt::Display) -> Error {
+    Error::ParseError(format!("{}: {}", message, e))
+}
+
+/// Makes the certificate authorities in `pem` the only ones trusted, like kubectl does, instead
+/// of adding them to the system trust store the connector starts with.
+fn trust_only(
+    builder: &mut SslConnectorBuilder,
+    pem: &[u8],
+) -> std::result::Result<(), ErrorStack> {
+    let mut store = X509StoreBuilder::new()?;
+    for cert in X509::stack_from_pem(pem)? {
+        store.add_cert(cert)?;
+    }
+    builder.set_cert_store(store.build());
+    Ok(())
+}
+
+/// Splits `https://host:port/prefix` into its parts.
+fn parse_server(server: &str) -> Result<(bool, String, u16, String)> {
+    let (tls, rest) = if let Some(rest) = server.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = server.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        return Err(invalid(format!("unsupported server URL '{}'", server)));
+    };
+    let (authority, prefix) = match rest.find('/') {
+        Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+        None => (rest, ""),
+    };
+    let default_port = if tls { 443 } else { 80 };
+    // IPv6 addresses are written as [::1]:6443
+    let (host, port) = match authority.rfind(':') {
+        Some(i) if !authority[i..].contains(']') => {
+            let port = authority[i + 1..]
+                .parse()
+                .map_err(|_| invalid(format!("invalid port in server URL '{}'", server)))?;
+            (&authority[..i], port)
+        }
+        _ => (authority, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(invalid(format!("no host in server URL '{}'", server)));
+    }
+    Ok((tls, host.to_string(), port, prefix.to_string()))
+}
+
+impl Client {
+    /// Configures a client from the kubeconfig, or from the service account if there is no
+    /// kubeconfig. Returns `None` if there is neither.
+    pub(crate) fn new(kubeconfig: Option<&Path>, context: Option<&str>) -> Result<Option<Client>> {
+        let paths = kubeconfig_paths(kubeconfig);
+        if !paths.is_empty() {
+            let configs = paths
+                .iter()
+                .map(|path| Kubeconfig::load(path))
+                .collect::<Result<Vec<_>>>()?;
+            return Client::from_kubeconfig(&configs, context).map(Some);
+        }
+        if in_cluster() {
+            return Client::from_service_account().map(Some);
+        }
+        Ok(None)
+    }
+
+    fn from_kubeconfig(configs: &[Kubeconfig], context: Option<&str>) -> Result<Client> {
+        let context_name = match context {
+            Some(context) => context,
+            None => configs
+                .iter()
+                .find_map(|c| {
+                    c.config["current-context"]
+                        .as_str()
+                        .filter(|s| !s.is_empty())
+                })
+                .ok_or_else(|| invalid("no current-context is set"))?,
+        };
+        let (_, context) =
+            named(configs, "contexts", "context", context_name).ok_or_else(|| {
+                Error::NotFound(format!(
+                    "context '{}' not found in kubeconfig",
+                    context_name
+                ))
+            })?;
+
+        let cluster_name = context["cluster"].as_str().unwrap_or_default();
+        let (cluster_config, cluster) = named(configs, "clusters", "cluster", cluster_name)
+            .ok_or_else(|| invalid(format!("cluster '{}' not found", cluster_name)))?;
+        let server = cluster["server"]
+            .as_str()
+            .ok_or_else(|| invalid(format!("cluster '{}' has no server", cluster_name)))?;
+        let (tls, host, port, prefix) = parse_server(server)?;
+
+        let mut client = Client {
+            server_name: cluster["tls-server-name"]
+                .as_str()
+                .unwrap_or(&host)
+                .to_string(),
+            host,
+            port,
+            prefix,
+            tls: None,
+            authorization: None,
+            namespace: context["namespace"].as_str().map(String::from),
+        };
+
+        let mut connector = None;
+        if tls {
+            let mut builder = SslConnector::builder(SslMethod::tls_client())
+                .map_err(|e| tls_error("failed to set up TLS", e))?;
+            if cluster["insecure-skip-tls-verify"].as_bool() == Some(true) {
+                builder.set_verify(SslVerifyMode::NONE);
+            } else if let Some(ca) = data_or_file(cluster_config, cluster, "certificate-authority")?
+            {
+                trust_only(&mut builder, &ca)
+                    .map_err(|e| tls_error("invalid certificate authority", e))?;
+            }
+            connector = Some(builder);
+        }
+
+        let user_name = context["user"].as_str().unwrap_or_default();
+        if let Some((user_config, user)) = named(configs, "users", "user", user_name) {
+            if user["exec"].is_object() || user["auth-provider"].is_object() {
+                return Err(Error::Unsupported(format!(
+                    "user '{}' of the kubeconfig uses a credential plugin, which requires kubectl",
+                    user_name
+                )));
+            }
+            if let Some(token) = user["token"].as_str() {
+                client.authorization = Some(format!("Bearer {}", token));
+            } else if let Some(token_file) = user["tokenFile"].as_str() {
+                let token = read_file(&user_config.resolve(token_file))?;
+                client.authorization =
+                    Some(format!("Bearer {}", String::from_utf8_lossy(&token).trim()));
+            } else if let (Some(username), Some(password)) =
+                (user["username"].as_str(), user["password"].as_str())
+            {
+                let credentials = format!("{}:{}", username, password);
+                client.authorization = Some(format!(
+                    "Basic {}",
+                    base64::encode_block(credentials.as_bytes())
+                ));
+            }
+
+            let cert = data_or_file(user_config, user, "client-certificate")?;
+            let key = data_or_file(user_config, user, "client-key")?;
+            if let (Some(builder), Some(cert), Some(key)) = (connector.as_mut(), cert, key) {
+                let mut chain = X509::stack_from_pem(&cert)
+                    .map_err(|e| tls_error("invalid client certificate", e))?
+                    .into_iter();
+                let leaf = chain
+                    .next()
+                    .ok_or_else(|| invalid("empty client certificate"))?;
+                builder
+                    .set_certificate(&leaf)
+                    .map_err(|e| tls_error("invalid client certificate", e))?;
+                for cert in chain {
+                    builder
+                        .add_extra_chain_cert(cert)
+                        .map_err(|e| tls_error("invalid client certificate", e))?;
+                }
+                let key = PKey::private_key_from_pem(&key)
+                    .map_err(|e| tls_error("invalid client key", e))?;
+                builder
+                    .set_private_key(&key)
+                    .map_err(|e| tls_error("invalid client key", e))?;
+            }
+        }
+        client.tls = connector.map(|builder| builder.build());
+        Ok(client)
+    }
+
+    fn from_service_account() -> Result<Client> {
+        let dir = Path::new(SERVICE_ACCOUNT_DIR);
+        let host = env::var("KUBERNETES_SERVICE_HOST").unwrap_or_default();
+        let port = match env::var("KUBERNETES_SERVICE_PORT") {
+            Ok(port) => port.parse().map_err(|_| {
+                Error::ParseError(format!("invalid KUBERNETES_SERVICE_PORT '{}'", port))
+            })?,
+            Err(_) => 443,
+        };
+        let token = read_file(&dir.join("token"))?;
+        let namespace = fs::read_to_string(dir.join("namespace"))
+            .ok()
+            .map(|ns| ns.trim().to_string());
+
+        let mut builder = SslConnector::builder(SslMethod::tls_client())
+            .map_err(|e| tls_error("failed to set up TLS", e))?;
+        let ca = read_file(&dir.join("ca.crt"))?;
+        trust_only(&mut builder, &ca).map_err(|e| tls_error("invalid service account CA", e))?;
+        Ok(Client {
+            server_name: host.clone(),
+            host,
+            port,
+            prefix: String::new(),
+            tls: Some(builder.build()),
+            authorization: Some(format!("Bearer {}", String::from_utf8_lossy(&token).trim())),
+            namespace,
+        })
+    }
+
+    fn connect(&self) -> Result<TcpStream> {
+        let address = format!("{}:{}", self.host, self.port);
+        let unreachable = |e: io::Error| {
+            Error::DaemonUnreachable(format!("cannot connect to API server {}: {}", address, e))
+        };
+        let addrs = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(unreachable)?;
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, TIMEOUT) {
+                Ok(stream) => {
+                    stream
+                        .set_read_timeout(Some(TIMEOUT))
+                        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+                        .map_err(|e| Error::io("failed to set socket timeout", e))?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(unreachable(last_error))
+    }
+
+    /// Fetches the JSON document at the API `path`, i.e. `/api/v1/namespaces/default/pods/x`.
+    pub(crate) fn get(&self, path: &str) -> Result<Value> {
+        let path = format!("{}{}", self.prefix, path);
+        let stream = self.connect()?;
+        let host = if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        };
+        let mut headers = vec![];
+        if let Some(authorization) = &self.authorization {
+            headers.push(("Authorization", authorization.as_str()));
+        }
+        let response = match &self.tls {
+            Some(connector) => {
+                let stream = connector.connect(&self.server_name, stream).map_err(|e| {
+                    Error::DaemonUnreachable(format!("TLS handshake with {} failed: {}", host, e))
+                })?;
+                http::request(stream, &host, &path, &headers)
+            }
+            None => http::request(stream, &host, &path, &headers),
+        }
+        .map_err(|e| e.context(format!("GET {} on {}", path, host)))?;
+
+        match response.status {
+            200 => response.json(),
+            404 => Err(Error::NotFound(response.error_message())),
+            401 | 403 => Err(Error::PermissionDenied(format!(
+                "GET {} on {} was denied: {}",
+                path,
+                host,
+                response.error_message()
+            ))),
+            status => Err(Error::CommandFailed(format!(
+                "GET {} on {} failed with status {}: {}",
+                path,
+                host,
+                status,
+                response.error_message()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+    use openssl::ssl::{SslAcceptor, SslStream};
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::{X509Builder, X509NameBuilder};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const POD: &str = r#"{"kind":"Pod","metadata":{"name":"web","namespace":"default"}}"#;
+
+    struct Certificate {
+        cert: X509,
+        key: PKey<Private>,
+    }
+
+    impl Certificate {
+        /// A certificate for `name`, signed by `issuer` or self-signed.
+        fn new(name: &str, issuer: Option<&Certificate>) -> Certificate {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+            let mut subject = X509NameBuilder::new().unwrap();
+            subject.append_entry_by_text("CN", name).unwrap();
+            let subject = subject.build();
+
+            let mut builder = X509Builder::new().unwrap();
+            builder.set_version(2).unwrap();
+            let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+            builder.set_serial_number(&serial).unwrap();
+            builder.set_subject_name(&subject).unwrap();
+            builder
+                .set_issuer_name(issuer.map_or(&subject, |i| i.cert.subject_name()))
+                .unwrap();
+            builder.set_pubkey(&key).unwrap();
+            builder
+                .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+                .unwrap();
+            builder
+                .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+                .unwrap();
+            if issuer.is_none() {
+                let ca = BasicConstraints::new().critical().ca().build().unwrap();
+                builder.append_extension(ca).unwrap();
+            } else {
+                let context = builder.x509v3_context(issuer.map(|i| &*i.cert), None);
+                let san = SubjectAlternativeName::new()
+                    .dns(name)
+                    .build(&context)
+                    .unwrap();
+                builder.append_extension(san).unwrap();
+            }
+            let signer = issuer.map_or(&key, |i| &i.key);
+            builder.sign(signer, MessageDigest::sha256()).unwrap();
+            Certificate {
+                cert: builder.build(),
+                key,
+            }
+        }
+
+        fn cert_pem(&self) -> Vec<u8> {
+            self.cert.to_pem().unwrap()
+        }
+
+        fn key_pem(&self) -> Vec<u8> {
+            self.key.private_key_to_pem_pkcs8().unwrap()
+        }
+    }
+
+    /// A directory for the kubeconfig and keys of a test, removed when it is dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> TestDir {
+            let dir = env::temp_dir().join(format!(
+                "container-pid-kube-api-{}-{}",
+                std::process::id(),
+                name
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+    }
+
+    impl std::ops::Deref for TestDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Writes `kubeconfig` to `dir` and configures a client from it.
+    fn client(dir: &Path, kubeconfig: &str, context: Option<&str>) -> Result<Client> {
+        let path = dir.join("config");
+        fs::write(&path, kubeconfig).unwrap();
+        Client::new(Some(&path), context).map(|client| client.expect("no client configured"))
+    }
+
+    fn error(res: Result<Client>) -> Error {
+        match res {
+            Ok(_) => panic!("client configured despite an invalid kubeconfig"),
+            Err(e) => e,
+        }
+    }
+
+    fn base64(data: &[u8]) -> String {
+        base64::encode_block(data)
+    }
+
+    /// Answers one request with `status` and `body` and returns the request head.
+    fn respond<S: Read + Write>(stream: &mut S, status: &str, body: &str) -> String {
+        let mut request = vec![];
+        let mut buf = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf).unwrap();
+            assert!(
+                n > 0,
+                "client closed the connection before the request ended"
+            );
+            request.extend_from_slice(&buf[..n]);
+        }
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).unwrap();
+        String::from_utf8(request).unwrap()
+    }
+
+    /// Serves one response over plain HTTP, the server thread returns the request head.
+    fn serve_http(status: &'static str, body: &'static str) -> (u16, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            respond(&mut stream, status, body)
+        });
+        (port, server)
+    }
+
+    /// Serves one response over TLS with `server` as certificate, only to clients with a
+    /// certificate signed by `ca`.
+    fn serve_tls(server: Certificate, ca: &Certificate) -> (u16, thread::JoinHandle<String>) {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&server.cert).unwrap();
+        acceptor.set_private_key(&server.key).unwrap();
+        acceptor.cert_store_mut().add_cert(ca.cert.clone()).unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream: SslStream<TcpStream> = match acceptor.accept(stream) {
+                Ok(stream) => stream,
+                Err(e) => return format!("handshake failed: {}", e),
+            };
+            let request = respond(&mut stream, "200 OK", POD);
+            stream.shutdown().unwrap();
+            request
+        });
+        (port, server)
+    }
+
+    const TWO_CONTEXTS: &str = "
+apiVersion: v1
+kind: Config
+current-context: dev
+clusters:
+- name: dev
+  cluster:
+    server: http://127.0.0.1:8080/proxy/
+- name: prod
+  cluster:
+    server: https://[fd00::1]:6443
+    insecure-skip-tls-verify: true
+contexts:
+- name: dev
+  context:
+    cluster: dev
+    user: developer
+    namespace: team-a
+- name: prod
+  context:
+    cluster: prod
+    user: admin
+users:
+- name: developer
+  user:
+    token: dev-token
+- name: admin
+  user:
+    username: admin
+    password: secret
+";
+
+    #[test]
+    fn current_context() {
+        let dir = TestDir::new("current-context");
+        let dev = client(&dir, TWO_CONTEXTS, None).unwrap();
+        assert_eq!((dev.host.as_str(), dev.port), ("127.0.0.1", 8080));
+        assert_eq!(dev.prefix, "/proxy");
+        assert!(dev.tls.is_none());
+        assert_eq!(dev.authorization.as_deref(), Some("Bearer dev-token"));
+        assert_eq!(dev.namespace.as_deref(), Some("team-a"));
+
+        let prod = client(&dir, TWO_CONTEXTS, Some("prod")).unwrap();
+        assert_eq!((prod.host.as_str(), prod.port), ("fd00::1", 6443));
+        assert!(prod.tls.is_some());
+        assert_eq!(
+            prod.authorization.as_deref(),
+            Some("Basic YWRtaW46c2VjcmV0")
+        );
+        assert_eq!(prod.namespace, None);
+
+        let res = client(&dir, TWO_CONTEXTS, Some("staging"));
+        assert!(matches!(error(res), Error::NotFound(_)));
+    }
+
+    #[test]
+    fn first_file_wins() {
+        let dir = TestDir::new("merge");
+        let first = dir.join("first");
+        fs::write(
+            &first,
+            "
+clusters:
+- name: dev
+  cluster:
+    server: http://first:8080
+users:
+- name: developer
+  user:
+    tokenFile: token
+",
+        )
+        .unwrap();
+        fs::write(dir.join("token"), "file-token\n").unwrap();
+        let second = dir.join("second");
+        fs::write(&second, TWO_CONTEXTS).unwrap();
+
+        let configs = [
+            Kubeconfig::load(&first).unwrap(),
+            Kubeconfig::load(&second).unwrap(),
+        ];
+        let client = Client::from_kubeconfig(&configs, None).unwrap();
+        assert_eq!(client.host, "first");
+        // the token file is relative to the kubeconfig that names it
+        assert_eq!(client.authorization.as_deref(), Some("Bearer file-token"));
+        assert_eq!(client.namespace.as_deref(), Some("team-a"));
+    }
+
+    #[test]
+    fn invalid_kubeconfigs() {
+        let dir = TestDir::new("invalid");
+        let res = client(&dir, "clusters: [", None);
+        assert!(matches!(error(res), Error::ParseError(_)));
+        let res = client(&dir, "clusters: []\n", None);
+        assert!(matches!(error(res), Error::ParseError(_)));
+
+        let plugin = TWO_CONTEXTS.replace(
+            "    token: dev-token",
+            "    exec:\n      command: aws\n      apiVersion: client.authentication.k8s.io/v1",
+        );
+        let res = client(&dir, &plugin, None);
+        assert!(matches!(error(res), Error::Unsupported(_)));
+
+        let ca = TWO_CONTEXTS.replace(
+            "    insecure-skip-tls-verify: true",
+            "    certificate-authority-data: not base64!",
+        );
+        let res = client(&dir, &ca, Some("prod"));
+        assert!(matches!(error(res), Error::ParseError(_)));
+        let ca = TWO_CONTEXTS.replace(
+            "    insecure-skip-tls-verify: true",
+            "    certificate-authority: missing.crt",
+        );
+        let res = client(&dir, &ca, Some("prod"));
+        assert!(matches!(error(res), Error::Io { .. }));
+    }
+
+    #[test]
+    fn get_pod_over_http() {
+        let dir = TestDir::new("http");
+        let (port, server) = serve_http("200 OK", POD);
+        let kubeconfig = TWO_CONTEXTS.replace("8080/proxy/", &port.to_string());
+        let api = client(&dir, &kubeconfig, None).unwrap();
+        let pod = api.get("/api/v1/namespaces/default/pods/web").unwrap();
+        assert_eq!(pod["metadata"]["name"], "web");
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /api/v1/namespaces/default/pods/web HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nAuthorization: Bearer dev-token\r\n"));
+
+        let (port, _) = serve_http(
+            "404 Not Found",
+            r#"{"kind":"Status","message":"pods \"nope\" not found","code":404}"#,
+        );
+        let kubeconfig = TWO_CONTEXTS.replace("8080/proxy/", &port.to_string());
+        let api = client(&dir, &kubeconfig, None).unwrap();
+        match api.get("/api/v1/namespaces/default/pods/nope") {
+            Err(Error::NotFound(message)) => assert_eq!(message, "pods \"nope\" not found"),
+            res => panic!("expected NotFound: {:?}", res),
+        }
+
+        let (port, _) = serve_http("403 Forbidden", r#"{"message":"forbidden"}"#);
+        let kubeconfig = TWO_CONTEXTS.replace("8080/proxy/", &port.to_string());
+        let api = client(&dir, &kubeconfig, None).unwrap();
+        let res = api.get("/api/v1/namespaces/default/pods/web");
+        assert!(matches!(res, Err(Error::PermissionDenied(_))), "{:?}", res);
+    }
+
+    /// A kubeconfig for a TLS server on `port` with an inline CA and a client certificate from
+    /// files next to the kubeconfig.
+    fn tls_kubeconfig(dir: &Path, port: u16, ca: &Certificate, user: &Certificate) -> String {
+        fs::write(dir.join("client.crt"), user.cert_pem()).unwrap();
+        fs::write(dir.join("client.key"), user.key_pem()).unwrap();
+        format!(
+            "
+current-context: tls
+clusters:
+- name: tls
+  cluster:
+    server: https://127.0.0.1:{}
+    tls-server-name: kubernetes
+    certificate-authority-data: {}
+contexts:
+- name: tls
+  context:
+    cluster: tls
+    user: node
+users:
+- name: node
+  user:
+    client-certificate: client.crt
+    client-key: client.key
+",
+            port,
+            base64(&ca.cert_pem())
+        )
+    }
+
+    #[test]
+    fn get_pod_over_tls() {
+        let dir = TestDir::new("tls");
+        let ca = Certificate::new("test-ca", None);
+        let user = Certificate::new("system:node:node1", Some(&ca));
+        let (port, server) = serve_tls(Certificate::new("kubernetes", Some(&ca)), &ca);
+        let api = client(&dir, &tls_kubeconfig(&dir, port, &ca, &user), None).unwrap();
+        let pod = api.get("/api/v1/namespaces/default/pods/web").unwrap();
+        assert_eq!(pod["metadata"]["name"], "web");
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /api/v1/namespaces/default/pods/web HTTP/1.1\r\n"));
+        assert!(!request.contains("Authorization"));
+    }
+
+    #[test]
+    fn inline_client_certificate() {
+        let dir = TestDir::new("inline-client-certificate");
+        let ca = Certificate::new("test-ca", None);
+        let user = Certificate::new("admin", Some(&ca));
+        let (port, server) = serve_tls(Certificate::new("kubernetes", Some(&ca)), &ca);
+        let kubeconfig = tls_kubeconfig(&dir, port, &ca, &user)
+            .replace(
+                "client-certificate: client.crt",
+                &format!("client-certificate-data: {}", base64(&user.cert_pem())),
+            )
+            .replace(
+                "client-key: client.key",
+                &format!("client-key-data: {}", base64(&user.key_pem())),
+            );
+        fs::remove_file(dir.join("client.crt")).unwrap();
+        fs::remove_file(dir.join("client.key")).unwrap();
+        let api = client(&dir, &kubeconfig, None).unwrap();
+        api.get("/api/v1/namespaces/default/pods/web").unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn untrusted_server() {
+        let dir = TestDir::new("untrusted");
+        let ca = Certificate::new("test-ca", None);
+        let other_ca = Certificate::new("other-ca", None);
+        let user = Certificate::new("admin", Some(&ca));
+        fs::write(dir.join("ca.crt"), other_ca.cert_pem()).unwrap();
+        let (port, _) = serve_tls(Certificate::new("kubernetes", Some(&ca)), &ca);
+        let kubeconfig = tls_kubeconfig(&dir, port, &ca, &user).replace(
+            &format!("certificate-authority-data: {}", base64(&ca.cert_pem())),
+            "certificate-authority: ca.crt",
+        );
+        let api = client(&dir, &kubeconfig, None).unwrap();
+        let res = api.get("/api/v1/namespaces/default/pods/web");
+        assert!(matches!(res, Err(Error::DaemonUnreachable(_))), "{:?}", res);
+    }
+
+    /// Runs `system_trust_store_child` in a child process with `kubeconfig` and `ca` as the
+    /// system trust store of OpenSSL, leaving the environment of the other tests alone. Returns
+    /// whether the child reached the API server.
+    fn get_with_system_ca(kubeconfig: &Path, ca: &Path) -> bool {
+        let output = std::process::Command::new(env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "kube_api::tests::system_trust_store_child",
+                "--quiet",
+            ])
+            .env("SSL_CERT_FILE", ca)
+            .env("CONTAINER_PID_TEST_KUBECONFIG", kubeconfig)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match (stdout.contains("1 passed"), stdout.contains("1 failed")) {
+            (true, false) => true,
+            (false, true) => false,
+            _ => panic!("child test did not run: {}", stdout),
+        }
+    }
+
+    /// Half of `system_trust_store`, does nothing unless it runs in the child process.
+    #[test]
+    fn system_trust_store_child() {
+        if let Some(kubeconfig) = env::var_os("CONTAINER_PID_TEST_KUBECONFIG") {
+            let api = Client::new(Some(Path::new(&kubeconfig)), None)
+                .unwrap()
+                .unwrap();
+            api.get("/api/v1/namespaces/default/pods/web").unwrap();
+        }
+    }
+
+    #[test]
+    fn system_trust_store() {
+        let dir = TestDir::new("system-trust-store");
+        let ca = Certificate::new("test-ca", None);
+        let public_ca = Certificate::new("public-ca", None);
+        let user = Certificate::new("admin", Some(&ca));
+        let system_ca = dir.join("system.crt");
+        fs::write(&system_ca, public_ca.cert_pem()).unwrap();
+        let kubeconfig = dir.join("config");
+
+        // without a CA of its own the cluster is verified against the system trust store
+        let (port, server) = serve_tls(Certificate::new("kubernetes", Some(&public_ca)), &ca);
+        let config = tls_kubeconfig(&dir, port, &ca, &user).replace(
+            &format!("certificate-authority-data: {}", base64(&ca.cert_pem())),
+            "",
+        );
+        fs::write(&kubeconfig, config).unwrap();
+        assert!(get_with_system_ca(&kubeconfig, &system_ca));
+        server.join().unwrap();
+
+        // with one, the system trust store is no longer consulted
+        let (port, server) = serve_tls(Certificate::new("kubernetes", Some(&public_ca)), &ca);
+        fs::write(&kubeconfig, tls_kubeconfig(&dir, port, &ca, &user)).unwrap();
+        assert!(!get_with_system_ca(&kubeconfig, &system_ca));
+        assert!(server.join().unwrap().starts_with("handshake failed"));
+    }
+}
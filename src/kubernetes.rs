@@ -1,175 +1,1272 @@
-//! This module uses `kubectl` to get a containerd id and then searches cgroups for one with that
-//! id as name. It returns any pid which is a member of that group.
+//! This module uses `kubectl` to get the runtime's container id and then searches cgroups for one
+//! named after that id. It returns any pid which is a member of that group.
 //!
 //! Possible container_id inputs:
 //!
-//! - `podname` to use default namespace and first container in that pod
-//! - one `/`: `namespace/podname` to override default namespace
+//! - `podname` to use the default namespace and the default container of that pod
+//! - one `/`: `namespace/podname` to override the default namespace
 //! - two `/`: `namespace/podname/container` to be super explicit
+//! - `namespace/deploy/name[/container]`, `namespace/sts/name[/container]` or
+//!   `namespace/ds/name[/container]` for the pod of a Deployment, StatefulSet or DaemonSet that
+//!   runs on this node
+//!
+//! The container name can be prefixed with its kind, `init:name` or `ephemeral:name`, to only
+//! search the init or ephemeral (`kubectl debug`) containers of the pod.
+//!
+//! The default namespace is the one from `KubernetesOptions`, or else the one of the kubeconfig
+//! context.
+//!
+//! With the `kube-api` feature the pod is fetched from the API server directly if there is a
+//! kubeconfig or a service account, `kubectl` is only needed for credential plugins.
+//!
+//! Without access to the API server pods are resolved from the state of the local node instead:
+//! the containers the CRI runtime labels with their pod, or the log directories of the kubelet.
 
-use crate::cmd;
-use crate::result::Result;
-use crate::Container;
-use anyhow::{bail, Context};
-use std::ffi::OsString;
+use crate::result::{Error, Result};
+use crate::{cmd, cri, docker};
+#[cfg(feature = "kube-api")]
+use crate::{http, kube_api};
+use crate::{Container, ContainerProcess, LookupOptions};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{CStr, OsString};
+use std::fmt;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::str::from_utf8;
 use std::str::FromStr;
 
-#[derive(Clone, Debug)]
-pub(crate) struct Kubernetes {}
-
+/// Namespace kubectl uses if the context sets none.
 pub(crate) const DEFAULT_NAMESPACE: &str = "default";
 
+/// Selects the cluster, namespace and node used by the `kubernetes` container type.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KubernetesOptions {
+    /// kubeconfig file, kubectl's default (`$KUBECONFIG` or `~/.kube/config`) if unset.
+    pub kubeconfig: Option<PathBuf>,
+    /// Context of the kubeconfig to use instead of the current context.
+    pub context: Option<String>,
+    /// Namespace of pods given without one, instead of the namespace of the context.
+    pub namespace: Option<String>,
+    /// Name of the node this machine runs as, `$NODE_NAME` or the hostname if unset.
+    pub node_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Kubernetes {
+    pub(crate) options: KubernetesOptions,
+}
+
+/// A started container of a pod, as found in the pod status or in the local node state.
+pub(crate) struct PodContainer {
+    pub(crate) namespace: String,
+    pub(crate) pod: String,
+    pub(crate) container: String,
+    pub(crate) id: RuntimeId,
+    pub(crate) uid: String,
+    /// `Guaranteed`, `Burstable` or `BestEffort`, decides where the kubelet puts the cgroup.
+    pub(crate) qos_class: Option<String>,
+}
+
+impl PodContainer {
+    fn name(&self) -> String {
+        format!("{}/{}/{}", self.namespace, self.pod, self.container)
+    }
+}
+
 impl Container for Kubernetes {
-    /// There is many ways to do this:
-    ///  - similar to command.rs: a bit looser pattern matching on /proc/$pid/cmdline
-    ///  - the following:
-    fn lookup(&self, container_id: &str) -> Result<libc::pid_t> {
-        let (namespace, pod_name, container_name) = parse_userinput(container_id)
-            .with_context(|| format!("failed to parse container ID '{}'", container_id))?;
-        let containerdid =
-            get_containerd_id(namespace, pod_name, container_name).with_context(|| {
-                format!(
-                    "failed to get containerd ID for pod '{}' in namespace '{}'",
-                    pod_name, namespace
-                )
-            })?;
-        let cgroup = find_cgroup(containerdid.clone()).with_context(|| {
-            format!("failed to find cgroup for containerd ID '{}'", containerdid)
+    fn name(&self) -> &'static str {
+        "kubernetes"
+    }
+
+    /// Resolves the pod and container to the runtime's container ID, through the API server if
+    /// it can be reached, or else from the CRI runtime labels or the `/var/log/pods` directories
+    /// of this node. The ID is then matched to the container's cgroup below the pod cgroup, and
+    /// the first process of that cgroup is returned. If the cgroup is not where the kubelet
+    /// puts it, the runtime that created the container is asked for its PID instead.
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        let input = parse_userinput(container_id)
+            .map_err(|e| e.context(format!("failed to parse container ID '{}'", container_id)))?;
+        let namespace = input.namespace.or(self.options.namespace.as_deref());
+        let local_namespace = namespace.unwrap_or(DEFAULT_NAMESPACE);
+        let container = if self.has_api(options) {
+            self.get_container_id(
+                options,
+                namespace,
+                &input.target,
+                input.container,
+                input.kind,
+            )
+            .or_else(|e| {
+                if !api_unavailable(&e) {
+                    return Err(e);
+                }
+                local_container_id(local_namespace, &input.target, input.container, options)
+                    .map_err(|_| e)
+            })
+        } else {
+            local_container_id(local_namespace, &input.target, input.container, options)
+        };
+        let container = container.map_err(|e| match namespace {
+            Some(namespace) => e.context(format!(
+                "failed to get container ID for {} in namespace '{}'",
+                input.target, namespace
+            )),
+            None => e.context(format!("failed to get container ID for {}", input.target)),
         })?;
-        let pid = get_cgroup_pid(&cgroup)
-            .with_context(|| format!("failed to get PID from cgroup '{}'", cgroup.display()))?;
-        Ok(pid)
+        let runtime_id = &container.id;
+        let cgroup = match find_cgroup(
+            &options.cgroup_root,
+            runtime_id,
+            Some(&container.uid),
+            container.qos_class.as_deref(),
+        ) {
+            Ok(cgroup) => cgroup,
+            Err(e) => {
+                let e = e.context(format!(
+                    "failed to find cgroup for container ID '{}'",
+                    runtime_id
+                ));
+                // ask the runtime itself in case it uses a cgroup layout we don't know
+                let runtime = match runtime_id.runtime_backend() {
+                    Some(runtime) if runtime.check_required_tools(options).is_ok() => runtime,
+                    _ => return Err(e),
+                };
+                let mut process = match runtime.lookup(&runtime_id.id, options) {
+                    Ok(process) => process,
+                    Err(not_running @ Error::NotRunning(_)) => return Err(not_running),
+                    Err(_) => return Err(e),
+                };
+                process.runtime = self.name();
+                process.name = container.name();
+                process.query = container_id.to_string();
+                return Ok(process);
+            }
+        };
+        let pid = get_cgroup_pid(&cgroup).map_err(|e| {
+            e.context(format!(
+                "failed to get PID from cgroup '{}'",
+                cgroup.display()
+            ))
+        })?;
+        Ok(ContainerProcess::new(
+            self.name(),
+            container.id.id.clone(),
+            container.name(),
+            pid,
+            container_id,
+            &options.proc_root,
+        ))
+    }
+
+    /// Lists the containers of all pods that have a cgroup on this machine.
+    fn list(&self, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        let containers = if self.has_api(options) {
+            match self.api_containers(options) {
+                Ok(containers) => containers,
+                Err(e) if api_unavailable(&e) => local_containers(None, options).map_err(|_| e)?,
+                Err(e) => return Err(e),
+            }
+        } else {
+            local_containers(None, options)?
+        };
+
+        let hierarchy = cgroup_hierarchy(&options.cgroup_root)?;
+        let mut cgroups = vec![];
+        // containers whose cgroup is not where the kubelet usually puts it, by cgroup name
+        let mut unknown_layout = HashMap::new();
+        for (i, container) in containers.iter().enumerate() {
+            let qos_class = container.qos_class.as_deref();
+            match kubelet_cgroup(&hierarchy, &container.id, &container.uid, qos_class) {
+                Some(cgroup) => cgroups.push((i, cgroup)),
+                None => {
+                    for cgroup_name in container.id.cgroup_names() {
+                        unknown_layout.insert(OsString::from(cgroup_name), i);
+                    }
+                }
+            }
+        }
+        if !unknown_layout.is_empty() {
+            let mut found = vec![];
+            collect_cgroups(&hierarchy, &unknown_layout, MAX_SEARCH_DEPTH, &mut found)?;
+            for (cgroup_name, cgroup) in found {
+                cgroups.push((unknown_layout[&cgroup_name], cgroup));
+            }
+        }
+
+        let mut processes = vec![];
+        for (i, cgroup) in cgroups {
+            let pid = match get_cgroup_pid(&cgroup) {
+                Ok(pid) => pid,
+                Err(Error::NotRunning(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            let container = &containers[i];
+            processes.push(ContainerProcess::new(
+                self.name(),
+                container.id.id.clone(),
+                container.name(),
+                pid,
+                container.name(),
+                &options.proc_root,
+            ));
+        }
+        Ok(processes)
     }
 
-    fn check_required_tools(&self) -> Result<()> {
-        if cmd::which("kubectl").is_some() {
+    fn check_required_tools(&self, options: &LookupOptions) -> Result<()> {
+        if self.has_api(options)
+            || !cri::endpoints(options).is_empty()
+            || options.state_path(POD_LOG_DIR).is_dir()
+        {
             Ok(())
         } else {
-            bail!("kubernetes runtime not found: 'kubectl' command is not available")
+            Err(Error::ToolUnavailable(String::from(
+                "kubernetes runtime not found: 'kubectl' command is not available and this is \
+                 not a Kubernetes node",
+            )))
+        }
+    }
+}
+
+/// Objects fetched from the API server.
+enum Query<'a> {
+    /// One object by name, a pod or one of the `CONTROLLERS`.
+    Object { kind: &'a str, name: &'a str },
+    /// The pods matching a label selector, or all pods.
+    Pods { selector: Option<&'a str> },
+}
+
+#[cfg(feature = "kube-api")]
+impl Query<'_> {
+    fn api_path(&self, namespace: &str) -> String {
+        let namespace = http::encode_path_segment(namespace);
+        match self {
+            Query::Object { kind, name } => {
+                // all CONTROLLERS are in the apps group
+                let group = if *kind == "pod" {
+                    "/api/v1"
+                } else {
+                    "/apis/apps/v1"
+                };
+                let name = http::encode_path_segment(name);
+                format!("{}/namespaces/{}/{}s/{}", group, namespace, kind, name)
+            }
+            Query::Pods { selector } => {
+                let mut path = format!("/api/v1/namespaces/{}/pods", namespace);
+                if let Some(selector) = selector {
+                    path.push_str("?labelSelector=");
+                    path.push_str(&http::encode_path_segment(selector));
+                }
+                path
+            }
+        }
+    }
+}
+
+/// Workload controllers that can be given instead of a pod, with the names kubectl accepts for
+/// them.
+const CONTROLLERS: &[(&str, &[&str])] = &[
+    ("deployment", &["deploy", "deployment", "deployments"]),
+    ("statefulset", &["sts", "statefulset", "statefulsets"]),
+    ("daemonset", &["ds", "daemonset", "daemonsets"]),
+];
+
+/// The kinds of containers a pod has, with the field of the pod status that lists them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ContainerKind {
+    Regular,
+    Init,
+    Ephemeral,
+}
+
+impl ContainerKind {
+    const ALL: [ContainerKind; 3] = [
+        ContainerKind::Regular,
+        ContainerKind::Init,
+        ContainerKind::Ephemeral,
+    ];
+
+    fn status_field(self) -> &'static str {
+        match self {
+            ContainerKind::Regular => "containerStatuses",
+            ContainerKind::Init => "initContainerStatuses",
+            ContainerKind::Ephemeral => "ephemeralContainerStatuses",
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            ContainerKind::Regular => "container",
+            ContainerKind::Init => "init container",
+            ContainerKind::Ephemeral => "ephemeral container",
+        }
+    }
+
+    fn statuses(self, pod: &Value) -> &[Value] {
+        pod["status"][self.status_field()]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+/// The pod a user asked for, either by name or through its controller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Target<'a> {
+    Pod(&'a str),
+    Controller { kind: &'static str, name: &'a str },
+}
+
+impl fmt::Display for Target<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Pod(name) => write!(f, "pod '{}'", name),
+            Target::Controller { kind, name } => write!(f, "{} '{}'", kind, name),
         }
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct UserInput<'a> {
+    pub(crate) namespace: Option<&'a str>,
+    pub(crate) target: Target<'a>,
+    pub(crate) container: Option<&'a str>,
+    /// Restricts the search to one kind of containers.
+    pub(crate) kind: Option<ContainerKind>,
+}
+
+fn controller_kind(name: &str) -> Option<&'static str> {
+    CONTROLLERS
+        .iter()
+        .find(|(_, aliases)| aliases.contains(&name))
+        .map(|(kind, _)| *kind)
+}
+
 /// allows the user to prepend the pod name with `custom-namespace/pod-name` to override the
-/// namespace (`default`). By default this will take the first container of the pod. That however
-/// can be overridden by appending it like `namespace/podname/container`.
-pub(crate) fn parse_userinput(container_id: &str) -> Result<(&str, &str, Option<&str>)> {
-    let fields = container_id.splitn(3, '/').collect::<Vec<&str>>();
-    if fields.len() == 1 {
-        return Ok((DEFAULT_NAMESPACE, container_id, None));
-    } else if fields.len() == 2 {
-        return Ok((fields[0], fields[1], None));
-    } else if fields.len() == 3 {
-        return Ok((fields[0], fields[1], Some(fields[2])));
-    }
-    unreachable!();
-}
-
-/// find `containerd://hash` id and return hash.
-/// Potentially vulnerable: passes unchecked user supplied strings to command.
-pub(crate) fn get_containerd_id(
-    namespace: &str,
-    pod_name: &str,
-    container_name: Option<&str>,
-) -> Result<String> {
-    let jsonpath = format!("jsonpath='{{range .items[?(@.metadata.name==\"{}\")].status.containerStatuses[*]}}{{.name}}{{\"\\t\"}}{{.containerID}}{{\"\\n\"}}{{end}}'", pod_name);
-    let result = Command::new("kubectl")
-        .arg("get")
-        .arg("pod")
-        .arg("-o")
-        .arg(jsonpath)
-        .arg("-n")
-        .arg(namespace)
-        .output()
-        .context("failed to execute 'kubectl get pod'")?;
-
-    if !result.status.success() {
-        let stderr = String::from_utf8_lossy(&result.stderr);
-        bail!(
-            "kubectl get pod failed (exit status {}): {}",
-            result.status,
-            stderr
-        );
-    }
-
-    let containers =
-        from_utf8(&result.stdout).context("kubectl response contains non-UTF8 data")?;
-    let containerid = containers.split('\n').find_map(|line| {
-        // line = "containername\tcontainerdid"
-        let cols: Vec<&str> = line.split('\t').collect();
-        if cols.len() != 2 {
-            return None;
-        }
-        if let Some(name) = container_name {
-            // return name-matching containerid
-            if cols[0] == name {
-                return Some(cols[1]);
+/// default namespace. By default this will take the default container of the pod. That however
+/// can be overridden by appending it like `namespace/podname/container`. Instead of the pod a
+/// controller can be named like `namespace/deploy/name`, which takes precedence over a pod that
+/// happens to be called `deploy`.
+pub(crate) fn parse_userinput(container_id: &str) -> Result<UserInput<'_>> {
+    let fields = container_id.split('/').collect::<Vec<&str>>();
+    let (namespace, target, container) = match fields.as_slice() {
+        [pod] => (None, Target::Pod(pod), None),
+        [namespace, pod] => (Some(*namespace), Target::Pod(pod), None),
+        [namespace, kind, name] => match controller_kind(kind) {
+            Some(kind) => (Some(*namespace), Target::Controller { kind, name }, None),
+            None => (Some(*namespace), Target::Pod(kind), Some(*name)),
+        },
+        [namespace, kind, name, container] => match controller_kind(kind) {
+            Some(kind) => (
+                Some(*namespace),
+                Target::Controller { kind, name },
+                Some(*container),
+            ),
+            None => {
+                return Err(Error::NotFound(format!(
+                    "'{}' is not a controller, expected one of deploy, sts or ds",
+                    kind
+                )))
             }
-        } else {
-            // return any containerid
-            return Some(cols[1]);
+        },
+        _ => {
+            return Err(Error::NotFound(String::from(
+                "expected at most namespace/kind/name/container",
+            )))
         }
-        None
-    });
+    };
+    // container names can't contain a colon, so there is no ambiguity
+    let (kind, container) = match container.map(|c| c.split_once(':')) {
+        Some(Some(("init", name))) => (Some(ContainerKind::Init), Some(name)),
+        Some(Some(("ephemeral", name))) => (Some(ContainerKind::Ephemeral), Some(name)),
+        Some(Some((kind, _))) => {
+            return Err(Error::NotFound(format!(
+                "unknown container kind '{}', expected init or ephemeral",
+                kind
+            )))
+        }
+        _ => (None, container),
+    };
+    if fields.iter().any(|field| field.is_empty()) || container == Some("") {
+        return Err(Error::NotFound(String::from("empty name")));
+    }
+    Ok(UserInput {
+        namespace,
+        target,
+        container,
+        kind,
+    })
+}
 
-    let containerid = containerid.ok_or_else(|| {
-        if let Some(name) = container_name {
-            anyhow::anyhow!("no container named '{}' found in pod '{}'", name, pod_name)
+/// A container ID as reported in the pod status, i.e. `containerd://<id>` or `cri-o://<id>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RuntimeId {
+    /// The scheme naming the container runtime.
+    pub(crate) runtime: String,
+    pub(crate) id: String,
+}
+
+impl FromStr for RuntimeId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RuntimeId> {
+        match s.split_once("://") {
+            Some((runtime, id)) if !runtime.is_empty() && !id.is_empty() => Ok(RuntimeId {
+                runtime: runtime.to_string(),
+                id: id.to_string(),
+            }),
+            _ => Err(Error::ParseError(format!(
+                "container ID does not have the expected '<runtime>://<id>' format: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.runtime.is_empty() {
+            write!(f, "{}", self.id)
         } else {
-            anyhow::anyhow!("no containers found in pod '{}'", pod_name)
+            write!(f, "{}://{}", self.runtime, self.id)
         }
-    })?;
+    }
+}
 
-    let containerid = containerid.strip_prefix("containerd://").ok_or_else(|| {
-        anyhow::anyhow!(
-            "container ID does not have expected 'containerd://' prefix: {}",
-            containerid
+impl RuntimeId {
+    /// Names the runtime gives the container's cgroup. The cgroupfs driver uses the bare ID for
+    /// containerd and docker, the systemd driver a scope with a runtime specific prefix.
+    pub(crate) fn cgroup_names(&self) -> Vec<String> {
+        let id = &self.id;
+        match self.runtime.as_str() {
+            "containerd" => vec![id.clone(), format!("cri-containerd-{}.scope", id)],
+            "cri-o" => vec![format!("crio-{}.scope", id), format!("crio-{}", id)],
+            "docker" => vec![id.clone(), format!("docker-{}.scope", id)],
+            // unknown runtime: any of the conventions above
+            "" => vec![
+                id.clone(),
+                format!("cri-containerd-{}.scope", id),
+                format!("crio-{}.scope", id),
+                format!("crio-{}", id),
+                format!("docker-{}.scope", id),
+            ],
+            runtime => vec![id.clone(), format!("{}-{}.scope", runtime, id)],
+        }
+    }
+
+    /// The backend that can resolve this ID directly.
+    fn runtime_backend(&self) -> Option<Box<dyn Container>> {
+        match self.runtime.as_str() {
+            "containerd" | "cri-o" => Some(Box::new(cri::Cri {})),
+            "docker" => Some(Box::new(docker::Docker {})),
+            _ => None,
+        }
+    }
+}
+
+/// The container that is used if the user did not name one: the one named in the
+/// `kubectl.kubernetes.io/default-container` annotation, or else the first of the pod spec.
+fn default_container(pod: &Value) -> Option<&str> {
+    pod["metadata"]["annotations"]["kubectl.kubernetes.io/default-container"]
+        .as_str()
+        .or_else(|| pod["spec"]["containers"][0]["name"].as_str())
+}
+
+/// Explains why a container without a running state has no process.
+fn not_running(pod_name: &str, kind: ContainerKind, status: &Value) -> Error {
+    let name = status["name"].as_str().unwrap_or_default();
+    let state = &status["state"];
+    let terminated = &state["terminated"];
+    if kind == ContainerKind::Init && terminated["exitCode"].as_i64() == Some(0) {
+        return Error::NotRunning(format!(
+            "init container '{}' in pod '{}' has already completed successfully",
+            name, pod_name
+        ));
+    }
+    let detail = if let Some(waiting) = state["waiting"].as_object() {
+        let reason = waiting.get("reason").and_then(Value::as_str);
+        format!("is waiting ({})", reason.unwrap_or("no reason given"))
+    } else if let Some(terminated) = state["terminated"].as_object() {
+        let reason = terminated.get("reason").and_then(Value::as_str);
+        let code = terminated.get("exitCode").and_then(Value::as_i64);
+        format!(
+            "has terminated ({}, exit code {})",
+            reason.unwrap_or("no reason given"),
+            code.map_or_else(|| String::from("unknown"), |c| c.to_string())
         )
-    })?;
-    Ok(String::from(containerid))
+    } else {
+        String::from("has not been started")
+    };
+    Error::NotRunning(format!(
+        "{} '{}' in pod '{}' {}",
+        kind.describe(),
+        name,
+        pod_name,
+        detail
+    ))
 }
 
-pub(crate) fn find_cgroup(containerdid: String) -> Result<PathBuf> {
-    let path = visit_dirs(
-        &PathBuf::from("/sys/fs/cgroup"),
-        &OsString::from(containerdid),
-    )?;
-    Ok(path)
+/// The name the kubelet registers this machine with, unless it was overridden.
+fn local_node_name() -> Result<String> {
+    if let Ok(name) = env::var("NODE_NAME") {
+        return Ok(name);
+    }
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return Err(Error::io(
+            "failed to get hostname",
+            std::io::Error::last_os_error(),
+        ));
+    }
+    // gethostname does not guarantee a terminating NUL if the name was truncated
+    buf[buf.len() - 1] = 0;
+    let hostname = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Ok(hostname.to_string_lossy().to_lowercase())
 }
 
-// one possible implementation of walking a directory from
-// https://doc.rust-lang.org/std/fs/fn.read_dir.html
-fn visit_dirs(dir: &Path, containerdid: &OsString) -> Result<PathBuf> {
-    for entry in std::fs::read_dir(dir)
-        .with_context(|| format!("failed to read directory '{}'", dir.display()))?
+/// Converts a `LabelSelector` to the string syntax of `kubectl --selector`.
+fn label_selector(selector: &Value) -> Option<String> {
+    let mut requirements = vec![];
+    for (key, value) in selector["matchLabels"].as_object().into_iter().flatten() {
+        requirements.push(format!("{}={}", key, value.as_str()?));
+    }
+    for expression in selector["matchExpressions"]
+        .as_array()
+        .into_iter()
+        .flatten()
     {
-        let entry = entry
-            .with_context(|| format!("failed to read entry in directory '{}'", dir.display()))?;
-        if &entry.file_name() == containerdid {
-            return Ok(entry.path());
+        let key = expression["key"].as_str()?;
+        let values: Vec<&str> = expression["values"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        requirements.push(match expression["operator"].as_str()? {
+            "In" => format!("{} in ({})", key, values.join(",")),
+            "NotIn" => format!("{} notin ({})", key, values.join(",")),
+            "Exists" => key.to_string(),
+            "DoesNotExist" => format!("!{}", key),
+            _ => return None,
+        });
+    }
+    // an empty selector would match every pod of the namespace
+    if requirements.is_empty() {
+        None
+    } else {
+        Some(requirements.join(","))
+    }
+}
+
+/// Node names may be fully qualified while the hostname is not, or the other way around. Short
+/// names are only compared if one side is unqualified, `node1.a.example` is not `node1.b.example`.
+fn same_node(node_name: &str, local: &str) -> bool {
+    if node_name.eq_ignore_ascii_case(local) {
+        return true;
+    }
+    let short = |name: &str| name.split('.').next().unwrap_or_default().to_lowercase();
+    (!node_name.contains('.') || !local.contains('.')) && short(node_name) == short(local)
+}
+
+impl Kubernetes {
+    /// Runs `kubectl get` with the configured kubeconfig and context and parses the JSON it
+    /// prints.
+    fn kubectl_get(&self, options: &LookupOptions, args: &[&str]) -> Result<Value> {
+        let kubeconfig = self
+            .options
+            .kubeconfig
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned());
+        let mut kubectl_args = vec!["get", "-o", "json"];
+        if let Some(kubeconfig) = &kubeconfig {
+            kubectl_args.extend_from_slice(&["--kubeconfig", kubeconfig]);
         }
-        let path = entry.path();
-        if path.is_dir() {
-            if let Ok(path) = visit_dirs(&path, containerdid) {
-                return Ok(path);
+        if let Some(context) = &self.options.context {
+            kubectl_args.extend_from_slice(&["--context", context]);
+        }
+        kubectl_args.extend_from_slice(args);
+        let stdout = cmd::run(options, "kubectl", &kubectl_args)?;
+        serde_json::from_slice(&stdout)
+            .map_err(|e| Error::ParseError(format!("invalid JSON from kubectl: {}", e)))
+    }
+
+    /// Whether the API server can be queried, through kubectl or directly.
+    fn has_api(&self, options: &LookupOptions) -> bool {
+        #[cfg(feature = "kube-api")]
+        {
+            if kube_api::is_configured(self.options.kubeconfig.as_deref()) {
+                return true;
             }
         }
+        cmd::which(options, "kubectl").is_some()
     }
-    bail!("cgroup not found in directory tree");
+
+    /// Fetches objects from the API server, directly if the `kube-api` feature is enabled and
+    /// configured, or else through kubectl. Without a namespace, the one of the context is used
+    /// and `Query::Pods` without a selector lists the pods of all namespaces.
+    fn get(
+        &self,
+        options: &LookupOptions,
+        namespace: Option<&str>,
+        query: Query<'_>,
+    ) -> Result<Value> {
+        let all_namespaces = namespace.is_none() && matches!(query, Query::Pods { selector: None });
+        #[cfg(feature = "kube-api")]
+        {
+            let kube_options = &self.options;
+            match kube_api::Client::new(
+                kube_options.kubeconfig.as_deref(),
+                kube_options.context.as_deref(),
+            ) {
+                Ok(Some(client)) => {
+                    if all_namespaces {
+                        return client.get("/api/v1/pods");
+                    }
+                    let namespace = namespace
+                        .or(client.namespace.as_deref())
+                        .unwrap_or(DEFAULT_NAMESPACE);
+                    return client.get(&query.api_path(namespace));
+                }
+                Ok(None) => {}
+                // credential plugins are left to kubectl
+                Err(Error::Unsupported(_)) if cmd::which(options, "kubectl").is_some() => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let mut args = vec![];
+        if let Some(namespace) = namespace {
+            args.extend_from_slice(&["-n", namespace]);
+        } else if all_namespaces {
+            args.push("--all-namespaces");
+        }
+        match query {
+            // `--` keeps a name starting with a dash from being parsed as an option
+            Query::Object { kind, name } => args.extend_from_slice(&[kind, "--", name]),
+            Query::Pods { selector } => {
+                args.push("pods");
+                if let Some(selector) = selector {
+                    args.extend_from_slice(&["-l", selector]);
+                }
+            }
+        }
+        self.kubectl_get(options, &args)
+    }
+
+    fn local_node(&self) -> Result<String> {
+        match &self.options.node_name {
+            Some(local) => Ok(local.clone()),
+            None => local_node_name(),
+        }
+    }
+
+    /// Follows the selector of a controller to the one pod it runs on this node.
+    fn controller_pod(
+        &self,
+        options: &LookupOptions,
+        namespace: Option<&str>,
+        kind: &str,
+        name: &str,
+    ) -> Result<Value> {
+        let controller = self.get(options, namespace, Query::Object { kind, name })?;
+        let selector = label_selector(&controller["spec"]["selector"])
+            .ok_or_else(|| Error::ParseError(format!("{} '{}' has no pod selector", kind, name)))?;
+        let selector = Some(selector.as_str());
+        let pods = self.get(options, namespace, Query::Pods { selector })?;
+        let local = self.local_node()?;
+
+        let mut local_pods: Vec<&Value> = pods["items"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .filter(|pod| {
+                let phase = pod["status"]["phase"].as_str();
+                let node = pod["spec"]["nodeName"].as_str();
+                phase != Some("Succeeded")
+                    && phase != Some("Failed")
+                    && pod["metadata"]["deletionTimestamp"].is_null()
+                    && node.is_some_and(|node| same_node(node, &local))
+            })
+            .collect();
+
+        if local_pods.len() > 1 {
+            return Err(Error::Ambiguous {
+                message: format!(
+                    "{} '{}' has {} pods on this node ({})",
+                    kind,
+                    name,
+                    local_pods.len(),
+                    local
+                ),
+                candidates: local_pods
+                    .iter()
+                    .map(|pod| {
+                        format!(
+                            "{}/{}",
+                            pod["metadata"]["namespace"].as_str().unwrap_or_default(),
+                            pod["metadata"]["name"].as_str().unwrap_or_default()
+                        )
+                    })
+                    .collect(),
+            });
+        }
+        local_pods.pop().cloned().ok_or_else(|| {
+            Error::NotFound(format!(
+                "{} '{}' has no pod on this node ({})",
+                kind, name, local
+            ))
+        })
+    }
+
+    /// Running containers of the pods on this node.
+    fn api_containers(&self, options: &LookupOptions) -> Result<Vec<PodContainer>> {
+        let namespace = self.options.namespace.as_deref();
+        let pods = self.get(options, namespace, Query::Pods { selector: None })?;
+        let pods = pods["items"]
+            .as_array()
+            .ok_or_else(|| Error::ParseError(String::from("no list of pods returned")))?;
+        let local = self.local_node()?;
+
+        let mut containers = vec![];
+        // pods scheduled on other nodes have no cgroup here
+        let local_pods = pods.iter().filter(|pod| {
+            pod["spec"]["nodeName"]
+                .as_str()
+                .is_some_and(|n| same_node(n, &local))
+        });
+        for pod in local_pods {
+            let statuses = ContainerKind::ALL
+                .iter()
+                .flat_map(|kind| kind.statuses(pod));
+            for status in statuses {
+                // containers that have not been started yet have no ID
+                if !status["state"]["running"].is_object() {
+                    continue;
+                }
+                let id = match status["containerID"].as_str().map(str::parse::<RuntimeId>) {
+                    Some(Ok(id)) => id,
+                    _ => continue,
+                };
+                containers.push(PodContainer {
+                    namespace: pod["metadata"]["namespace"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    pod: pod["metadata"]["name"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    container: status["name"].as_str().unwrap_or_default().to_string(),
+                    id,
+                    uid: pod["metadata"]["uid"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    qos_class: pod["status"]["qosClass"].as_str().map(String::from),
+                });
+            }
+        }
+        Ok(containers)
+    }
+
+    /// Fails if the pod is scheduled on another node, its containers can't be found here.
+    fn check_node(&self, pod: &Value, pod_name: &str) -> Result<()> {
+        let node_name = match pod["spec"]["nodeName"].as_str() {
+            Some(node_name) => node_name,
+            None => {
+                return Err(Error::NotRunning(format!(
+                    "pod '{}' has not been scheduled on a node yet",
+                    pod_name
+                )))
+            }
+        };
+        let local = self.local_node()?;
+        if same_node(node_name, &local) {
+            Ok(())
+        } else {
+            Err(Error::NotFound(format!(
+                "pod '{}' is scheduled on node {}, not on this node ({})",
+                pod_name, node_name, local
+            )))
+        }
+    }
+
+    /// find the `runtime://hash` id of a container of the pod.
+    pub(crate) fn get_container_id(
+        &self,
+        options: &LookupOptions,
+        namespace: Option<&str>,
+        target: &Target<'_>,
+        container_name: Option<&str>,
+        kind: Option<ContainerKind>,
+    ) -> Result<PodContainer> {
+        let pod = match target {
+            Target::Pod(pod_name) => self.get(
+                options,
+                namespace,
+                Query::Object {
+                    kind: "pod",
+                    name: pod_name,
+                },
+            )?,
+            Target::Controller { kind, name } => {
+                self.controller_pod(options, namespace, kind, name)?
+            }
+        };
+        let pod_name = pod["metadata"]["name"].as_str().unwrap_or_default();
+
+        let phase = pod["status"]["phase"].as_str().unwrap_or("Unknown");
+        if phase == "Succeeded" || phase == "Failed" {
+            return Err(Error::NotRunning(format!(
+                "pod '{}' has completed (phase {})",
+                pod_name, phase
+            )));
+        }
+        self.check_node(&pod, pod_name)?;
+
+        let name = match container_name {
+            Some(name) => name,
+            None if kind.is_some() => {
+                return Err(Error::NotFound(String::from(
+                    "a container kind requires a container name",
+                )))
+            }
+            None => default_container(&pod).ok_or_else(|| {
+                Error::NotFound(format!("no containers found in pod '{}'", pod_name))
+            })?,
+        };
+        // names are unique across all kinds of containers of a pod
+        let kinds = match kind {
+            Some(kind) => vec![kind],
+            None => ContainerKind::ALL.to_vec(),
+        };
+        let found = kinds.iter().find_map(|kind| {
+            kind.statuses(&pod)
+                .iter()
+                .find(|s| s["name"].as_str() == Some(name))
+                .map(|status| (*kind, status))
+        });
+        let (kind, status) = match found {
+            Some(found) => found,
+            None if phase == "Pending" => {
+                return Err(Error::NotRunning(format!(
+                    "pod '{}' is pending, container '{}' has not been created yet",
+                    pod_name, name
+                )))
+            }
+            None => {
+                return Err(Error::NotFound(format!(
+                    "no {} named '{}' found in pod '{}'",
+                    kind.unwrap_or(ContainerKind::Regular).describe(),
+                    name,
+                    pod_name
+                )))
+            }
+        };
+
+        let id = match status["containerID"].as_str() {
+            Some(id) if status["state"]["running"].is_object() => id.parse()?,
+            _ => return Err(not_running(pod_name, kind, status)),
+        };
+        Ok(PodContainer {
+            namespace: pod["metadata"]["namespace"]
+                .as_str()
+                .or(namespace)
+                .unwrap_or(DEFAULT_NAMESPACE)
+                .to_string(),
+            pod: pod_name.to_string(),
+            container: name.to_string(),
+            id,
+            uid: pod["metadata"]["uid"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            qos_class: pod["status"]["qosClass"].as_str().map(String::from),
+        })
+    }
+}
+
+/// Where the kubelet keeps container logs, in `<namespace>_<pod>_<uid>/<container>/<restarts>.log`.
+const POD_LOG_DIR: &str = "/var/log/pods";
+
+/// Holds symlinks named `<pod>_<namespace>_<container>-<id>.log` to the logs in `POD_LOG_DIR`.
+const CONTAINER_LOG_DIR: &str = "/var/log/containers";
+
+/// Whether `id` looks like the ID containerd, CRI-O and docker give containers.
+fn is_container_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// The ID of the latest instance of a container, from the log symlinks of the kubelet.
+/// `container_log_dir` is the host path of the log directory of the container.
+fn container_log_id(
+    log_links: &[(String, PathBuf)],
+    container_log_dir: &Path,
+    prefix: &str,
+) -> Option<String> {
+    log_links
+        .iter()
+        .filter_map(|(name, target)| {
+            // the prefix of container `app` is also a prefix of the links of `app-sidecar`
+            let id = name.strip_prefix(prefix)?.strip_suffix(".log")?;
+            if !is_container_id(id) || target.parent() != Some(container_log_dir) {
+                return None;
+            }
+            // older instances of a pod with the same name have their own log directory
+            let restarts: u64 = target.file_stem()?.to_str()?.parse().ok()?;
+            Some((restarts, id))
+        })
+        .max()
+        .map(|(_, id)| id.to_string())
+}
+
+fn read_dir_names(dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => {
+            return Err(Error::io(
+                format!("failed to read directory '{}'", dir.display()),
+                e,
+            ))
+        }
+    };
+    let mut names = vec![];
+    for entry in entries {
+        let entry = entry
+            .map_err(|e| Error::io(format!("failed to read directory '{}'", dir.display()), e))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    Ok(names)
+}
+
+/// Containers of the pods on this node according to the log directories of the kubelet. The
+/// runtime is unknown, so `RuntimeId::runtime` is left empty.
+fn pod_log_containers(
+    pod: Option<(&str, &str)>,
+    options: &LookupOptions,
+) -> Result<Vec<PodContainer>> {
+    let log_dir = options.state_path(CONTAINER_LOG_DIR);
+    let mut log_links = vec![];
+    for name in read_dir_names(&log_dir)? {
+        if let Ok(target) = fs::read_link(log_dir.join(&name)) {
+            log_links.push((name, target));
+        }
+    }
+
+    let mut containers = vec![];
+    let pod_log_root = options.state_path(POD_LOG_DIR);
+    for pod_dir in read_dir_names(&pod_log_root)? {
+        // namespace and pod names can't contain '_', UIDs neither
+        let fields: Vec<&str> = pod_dir.split('_').collect();
+        let (namespace, pod_name, uid) = match fields.as_slice() {
+            [namespace, pod_name, uid] => (*namespace, *pod_name, *uid),
+            _ => continue,
+        };
+        if pod.is_some_and(|pod| pod != (namespace, pod_name)) {
+            continue;
+        }
+        let pod_log_dir = pod_log_root.join(&pod_dir);
+        for container in read_dir_names(&pod_log_dir)? {
+            let prefix = format!("{}_{}_{}-", pod_name, namespace, container);
+            // the symlinks point to the host path of the log
+            let host_log_dir = Path::new(POD_LOG_DIR).join(&pod_dir).join(&container);
+            let id = match container_log_id(&log_links, &host_log_dir, &prefix) {
+                Some(id) => id,
+                None => continue,
+            };
+            containers.push(PodContainer {
+                namespace: namespace.to_string(),
+                pod: pod_name.to_string(),
+                container,
+                id: RuntimeId {
+                    runtime: String::new(),
+                    id,
+                },
+                uid: uid.to_string(),
+                qos_class: None,
+            });
+        }
+    }
+    Ok(containers)
+}
+
+/// Containers of the pods on this node, from the CRI runtime if it is reachable or else from the
+/// logs of the kubelet.
+fn local_containers(
+    pod: Option<(&str, &str)>,
+    options: &LookupOptions,
+) -> Result<Vec<PodContainer>> {
+    if cri::endpoints(options).is_empty() {
+        return pod_log_containers(pod, options);
+    }
+    let containers = match cri::kubernetes_containers(pod, options) {
+        Ok(containers) => containers,
+        // none of the runtimes answered, e.g. all of them are stopped
+        Err(e) if cri::runtime_unavailable(&e) => return pod_log_containers(pod, options),
+        Err(e) => return Err(e),
+    };
+    Ok(containers
+        .into_iter()
+        .map(|c| PodContainer {
+            namespace: c.namespace,
+            pod: c.pod,
+            container: c.container,
+            id: RuntimeId {
+                runtime: c.runtime,
+                id: c.id,
+            },
+            uid: c.pod_uid,
+            qos_class: None,
+        })
+        .collect())
+}
+
+/// Whether kubectl failed to talk to the API server, e.g. because it is down or there are no
+/// credentials on this node, or the kubeconfig needs a credential plugin and kubectl is missing.
+fn api_unavailable(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::DaemonUnreachable(_)
+            | Error::PermissionDenied(_)
+            | Error::CommandFailed(_)
+            | Error::Unsupported(_)
+    )
+}
+
+/// Resolves a pod container without kubectl, from the state of the kubelet and the runtime.
+fn local_container_id(
+    namespace: &str,
+    target: &Target<'_>,
+    container_name: Option<&str>,
+    options: &LookupOptions,
+) -> Result<PodContainer> {
+    let pod_name = match target {
+        Target::Pod(pod_name) => *pod_name,
+        Target::Controller { kind, .. } => {
+            return Err(Error::Unsupported(format!(
+                "resolving a {} requires kubectl",
+                kind
+            )))
+        }
+    };
+    let mut candidates = local_containers(Some((namespace, pod_name)), options)?;
+    if let Some(name) = container_name {
+        candidates.retain(|c| c.container == name);
+    }
+    // log directories of earlier instances of the pod may still exist
+    if candidates.len() > 1 {
+        if let Ok(hierarchy) = cgroup_hierarchy(&options.cgroup_root) {
+            candidates.retain(|c| kubelet_cgroup(&hierarchy, &c.id, &c.uid, None).is_some());
+        }
+    }
+
+    if candidates.len() > 1 {
+        return Err(Error::Ambiguous {
+            message: match container_name {
+                Some(_) => format!("pod '{}' has several matching containers", pod_name),
+                // the default container is only known to the API server
+                None => format!(
+                    "pod '{}' has several containers, the container name is required without \
+                     kubectl",
+                    pod_name
+                ),
+            },
+            candidates: candidates
+                .iter()
+                .map(|c| format!("{} ({})", c.name(), c.id.id))
+                .collect(),
+        });
+    }
+    candidates.pop().ok_or_else(|| match container_name {
+        Some(name) => Error::NotFound(format!(
+            "no running container '{}' of pod '{}' found on this node",
+            name, pod_name
+        )),
+        None => Error::NotFound(format!(
+            "no running container of pod '{}' found on this node",
+            pod_name
+        )),
+    })
+}
+
+/// cgroup v1 hierarchies the kubelet creates pod cgroups in, the first mounted one is used.
+const V1_HIERARCHIES: &[&str] = &["pids", "memory", "cpu,cpuacct", "cpu", "systemd"];
+
+/// How deep the fallback search descends below the hierarchy root. Container cgroups are at
+/// depth 3 or 4 with the default kubelet settings, this leaves room for a custom `--cgroup-root`.
+const MAX_SEARCH_DEPTH: usize = 6;
+
+/// The hierarchy to look for pod cgroups in: the root of the unified hierarchy with cgroup v2, or
+/// one of the controller hierarchies with cgroup v1.
+fn cgroup_hierarchy(cgroup_root: &Path) -> Result<PathBuf> {
+    if cgroup_root.join("cgroup.controllers").exists() {
+        return Ok(cgroup_root.to_path_buf());
+    }
+    V1_HIERARCHIES
+        .iter()
+        .map(|hierarchy| cgroup_root.join(hierarchy))
+        .find(|path| path.join("cgroup.procs").exists())
+        .ok_or_else(|| {
+            Error::NotFound(format!(
+                "no cgroup hierarchy found in '{}'",
+                cgroup_root.display()
+            ))
+        })
+}
+
+/// Directories the kubelet creates for a pod, relative to the hierarchy root, for the systemd and
+/// the cgroupfs cgroup driver. All QoS classes are tried if the class is unknown.
+fn pod_cgroup_dirs(uid: &str, qos_class: Option<&str>) -> Vec<PathBuf> {
+    let classes: &[&str] = match qos_class {
+        Some(class) => &[class][..],
+        None => &["Guaranteed", "Burstable", "BestEffort"],
+    };
+    let systemd_uid = uid.replace('-', "_");
+    let mut dirs = vec![];
+    for class in classes {
+        // guaranteed pods are placed directly below kubepods
+        if class.eq_ignore_ascii_case("Guaranteed") {
+            dirs.push(PathBuf::from(format!(
+                "kubepods.slice/kubepods-pod{}.slice",
+                systemd_uid
+            )));
+            dirs.push(PathBuf::from(format!("kubepods/pod{}", uid)));
+        } else {
+            let qos = class.to_lowercase();
+            dirs.push(PathBuf::from(format!(
+                "kubepods.slice/kubepods-{qos}.slice/kubepods-{qos}-pod{}.slice",
+                systemd_uid,
+                qos = qos
+            )));
+            dirs.push(PathBuf::from(format!("kubepods/{}/pod{}", qos, uid)));
+        }
+    }
+    dirs
+}
+
+/// The cgroup of a container in the pod directory the kubelet created.
+fn kubelet_cgroup(
+    hierarchy: &Path,
+    id: &RuntimeId,
+    pod_uid: &str,
+    qos_class: Option<&str>,
+) -> Option<PathBuf> {
+    let names = id.cgroup_names();
+    pod_cgroup_dirs(pod_uid, qos_class)
+        .iter()
+        .flat_map(|dir| names.iter().map(move |name| hierarchy.join(dir).join(name)))
+        .find(|path| path.is_dir())
+}
+
+/// The cgroup of a container. If the pod UID is known, the cgroup is looked up where the kubelet
+/// puts it, otherwise or if that fails the hierarchy is searched up to `MAX_SEARCH_DEPTH`.
+pub(crate) fn find_cgroup(
+    cgroup_root: &Path,
+    id: &RuntimeId,
+    pod_uid: Option<&str>,
+    qos_class: Option<&str>,
+) -> Result<PathBuf> {
+    let hierarchy = cgroup_hierarchy(cgroup_root)?;
+    if let Some(cgroup) = pod_uid.and_then(|uid| kubelet_cgroup(&hierarchy, id, uid, qos_class)) {
+        return Ok(cgroup);
+    }
+
+    let names: HashMap<OsString, ()> = id
+        .cgroup_names()
+        .into_iter()
+        .map(|name| (name.into(), ()))
+        .collect();
+    let mut found = vec![];
+    collect_cgroups(&hierarchy, &names, MAX_SEARCH_DEPTH, &mut found)?;
+    match found.pop() {
+        Some((_, path)) => Ok(path),
+        None => Err(Error::NotFound(format!(
+            "cgroup not found in '{}'",
+            hierarchy.display()
+        ))),
+    }
+}
+
+/// Collects the cgroups named after any key of `cgroup_names` up to `depth` levels below `dir`.
+fn collect_cgroups<T>(
+    dir: &Path,
+    cgroup_names: &HashMap<OsString, T>,
+    depth: usize,
+    found: &mut Vec<(OsString, PathBuf)>,
+) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // the cgroup was removed while we were looking
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(Error::io(
+                format!("failed to read directory '{}'", dir.display()),
+                e,
+            ))
+        }
+    };
+    for entry in entries {
+        let entry = entry.map_err(|e| {
+            Error::io(
+                format!("failed to read entry in directory '{}'", dir.display()),
+                e,
+            )
+        })?;
+        // cgroups are directories, the control files are not interesting
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        let name = entry.file_name();
+        if cgroup_names.contains_key(&name) {
+            found.push((name, entry.path()));
+        } else if depth > 1 {
+            collect_cgroups(&entry.path(), cgroup_names, depth - 1, found)?;
+        }
+    }
+    Ok(())
 }
 
 /// return any pid part of this cgroup
 pub(crate) fn get_cgroup_pid(cgroup: &Path) -> Result<libc::pid_t> {
     let path = cgroup.join("cgroup.procs");
-    let bytes = fs::read(&path)
-        .with_context(|| format!("failed to read cgroup.procs file at '{}'", path.display()))?;
-    let pids = String::from_utf8(bytes).context("cgroup.procs contains non-UTF8 data")?;
+    let bytes = fs::read(&path).map_err(|e| {
+        Error::io(
+            format!("failed to read cgroup.procs file at '{}'", path.display()),
+            e,
+        )
+    })?;
+    let pids = String::from_utf8(bytes)
+        .map_err(|e| Error::ParseError(format!("cgroup.procs contains non-UTF8 data: {}", e)))?;
     let pids = pids.splitn(2, '\n').collect::<Vec<&str>>()[0]; // first line
-    let pid: u64 = u64::from_str(pids)
-        .with_context(|| format!("invalid PID value '{}' in cgroup.procs", pids))?;
+    if pids.is_empty() {
+        return Err(Error::NotRunning(format!(
+            "no process left in cgroup '{}'",
+            cgroup.display()
+        )));
+    }
+    let pid: u64 = u64::from_str(pids).map_err(|e| {
+        Error::ParseError(format!(
+            "invalid PID value '{}' in cgroup.procs: {}",
+            pids, e
+        ))
+    })?;
     Ok(pid as libc::pid_t)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_names() {
+        assert!(same_node("node1", "node1"));
+        assert!(same_node("Node1.example.com", "node1.EXAMPLE.com"));
+        assert!(same_node("node1.example.com", "node1"));
+        assert!(same_node("node1", "node1.example.com"));
+        assert!(!same_node("node1.a.example.com", "node1.b.example.com"));
+        assert!(!same_node("node1", "node10"));
+    }
+}
@@ -1,24 +1,169 @@
-use anyhow::bail;
 use libc::pid_t;
+use std::env;
 use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+pub use crate::cmd::{CommandRunner, SystemRunner};
+pub use crate::kubernetes::KubernetesOptions;
+pub use crate::nspawn::{lookup_machine, lookup_machine_with_options, Machine};
+pub use crate::pidfd::ProcessHandle;
+pub use crate::replay::ReplayRunner;
 use crate::result::Result;
+pub use crate::result::{Error, LookupError, Operation};
 
 mod cmd;
 mod command;
 mod containerd;
+mod cri;
+#[cfg(feature = "cri-grpc")]
+mod cri_grpc;
+#[cfg(not(feature = "cri-grpc"))]
+mod crictl;
+mod dbus;
 mod docker;
+#[cfg(feature = "cri-grpc")]
+mod grpc;
+#[cfg(feature = "cri-grpc")]
+mod hpack;
+mod http;
+#[cfg(feature = "kube-api")]
+mod kube_api;
 mod kubernetes;
 mod lxc;
 mod lxd;
 mod nspawn;
+mod oci;
+mod pidfd;
 mod podman;
 mod process_id;
+#[cfg(feature = "cri-grpc")]
+mod protobuf;
+mod replay;
 mod result;
+mod rootless;
+
+/// A container resolved by one of the supported runtimes.
+///
+/// Only the runtimes construct it, new fields may be added.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ContainerProcess {
+    /// Name of the runtime that matched, as listed in `AVAILABLE_CONTAINER_TYPES`.
+    pub runtime: &'static str,
+    /// Canonical container ID as reported by the runtime.
+    pub id: String,
+    /// Human readable name of the container, falls back to the ID if the runtime has none.
+    pub name: String,
+    /// PID of the container's leader process.
+    pub pid: pid_t,
+    /// The user supplied string the container was resolved from.
+    pub query: String,
+    /// Start time of the leader process in clock ticks since boot, recorded when the runtime
+    /// reported the PID. `None` if the process was already gone.
+    pub start_time: Option<u64>,
+}
+
+impl ContainerProcess {
+    /// Describes the container led by `pid` and records the start time of that process, so the
+    /// backends construct it right after reading the PID from the runtime.
+    pub(crate) fn new(
+        runtime: &'static str,
+        id: impl Into<String>,
+        name: impl Into<String>,
+        pid: pid_t,
+        query: impl Into<String>,
+        proc_root: &Path,
+    ) -> ContainerProcess {
+        ContainerProcess {
+            runtime,
+            id: id.into(),
+            name: name.into(),
+            pid,
+            query: query.into(),
+            start_time: pidfd::process_start_time(proc_root, pid).ok(),
+        }
+    }
+
+    /// Opens a race-free handle on the leader process.
+    ///
+    /// Fails with `Error::NotRunning` if the process exited since the lookup, even if its PID was
+    /// reused by another process in the meantime.
+    pub fn open(&self) -> Result<ProcessHandle> {
+        self.open_with_options(&LookupOptions::default())
+    }
+
+    /// Like `open`, but reads the process start time from `options.proc_root`.
+    pub fn open_with_options(&self, options: &LookupOptions) -> Result<ProcessHandle> {
+        let start_time = self.start_time.ok_or_else(|| {
+            Error::NotRunning(format!(
+                "process {} exited before its start time could be recorded",
+                self.pid
+            ))
+        })?;
+        ProcessHandle::open(&options.proc_root, self.pid, start_time)
+    }
+}
+
+/// Where the backends find the state of the host and how they run its tools.
+///
+/// The defaults describe the host the lookup runs on. A tool running in a privileged container
+/// with the host's filesystems mounted elsewhere, or a test against a fixture tree, points the
+/// roots there instead. Paths given explicitly in the environment, such as `DOCKER_HOST` or
+/// `CONTAINER_RUNTIME_ENDPOINT`, are used as they are.
+#[derive(Clone, Debug)]
+pub struct LookupOptions {
+    /// procfs of the PID namespace the returned PIDs refer to, `$CNTR_PROC` or `/proc` by default.
+    pub proc_root: PathBuf,
+    /// Mount point of the cgroup filesystem, `/sys/fs/cgroup` by default.
+    pub cgroup_root: PathBuf,
+    /// Root that the runtime state directories, sockets and logs below `/run` and `/var` are
+    /// resolved against, `/` by default.
+    pub state_root: PathBuf,
+    /// `$XDG_RUNTIME_DIR` of the current session, where the rootless runtimes of the current user
+    /// keep their sockets and state. Resolved against `state_root`, taken from the environment by
+    /// default.
+    pub runtime_dir: Option<PathBuf>,
+    /// Roots of OCI runtimes started with a custom `--root`, searched before the default roots
+    /// and resolved against `state_root`. Taken from `$CNTR_OCI_ROOT`, separated by `:`, by
+    /// default.
+    pub oci_roots: Vec<PathBuf>,
+    /// Runs the command line tools of the runtimes, `SystemRunner` by default.
+    pub runner: Arc<dyn CommandRunner>,
+}
+
+impl Default for LookupOptions {
+    fn default() -> Self {
+        LookupOptions {
+            proc_root: env::var_os("CNTR_PROC")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("/proc")),
+            cgroup_root: PathBuf::from("/sys/fs/cgroup"),
+            state_root: PathBuf::from("/"),
+            runtime_dir: env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from),
+            oci_roots: env::var_os("CNTR_OCI_ROOT")
+                .map(|roots| env::split_paths(&roots).collect())
+                .unwrap_or_default(),
+            runner: Arc::new(SystemRunner),
+        }
+    }
+}
+
+impl LookupOptions {
+    /// Resolves an absolute host path like `/run/containerd` against `state_root`.
+    pub(crate) fn state_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        self.state_root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+}
 
 pub trait Container: Debug {
-    fn lookup(&self, id: &str) -> Result<pid_t>;
-    fn check_required_tools(&self) -> Result<()>;
+    /// Name of the runtime, as listed in `AVAILABLE_CONTAINER_TYPES`.
+    fn name(&self) -> &'static str;
+    fn lookup(&self, id: &str, options: &LookupOptions) -> Result<ContainerProcess>;
+    /// Returns all running containers of this runtime.
+    fn list(&self, options: &LookupOptions) -> Result<Vec<ContainerProcess>>;
+    fn check_required_tools(&self, options: &LookupOptions) -> Result<()>;
 }
 
 pub const AVAILABLE_CONTAINER_TYPES: &[&str] = &[
@@ -31,9 +176,11 @@ pub const AVAILABLE_CONTAINER_TYPES: &[&str] = &[
     "command",
     "containerd",
     "kubernetes",
+    "oci",
+    "cri",
 ];
 
-fn default_order() -> Vec<Box<dyn Container>> {
+fn default_order(options: &LookupOptions) -> Vec<Box<dyn Container>> {
     let containers: Vec<Box<dyn Container>> = vec![
         Box::new(process_id::ProcessId {}),
         Box::new(podman::Podman {}),
@@ -42,11 +189,13 @@ fn default_order() -> Vec<Box<dyn Container>> {
         Box::new(lxc::Lxc {}),
         Box::new(lxd::Lxd {}),
         Box::new(containerd::Containerd {}),
-        Box::new(kubernetes::Kubernetes {}),
+        Box::new(kubernetes::Kubernetes::default()),
+        Box::new(oci::Oci {}),
+        Box::new(cri::Cri {}),
     ];
     containers
         .into_iter()
-        .filter(|c| c.check_required_tools().is_ok())
+        .filter(|c| c.check_required_tools(options).is_ok())
         .collect()
 }
 
@@ -60,34 +209,164 @@ pub fn lookup_container_type(name: &str) -> Option<Box<dyn Container>> {
         "lxd" => Box::new(lxd::Lxd {}),
         "containerd" => Box::new(containerd::Containerd {}),
         "command" => Box::new(command::Command {}),
-        "kubernetes" => Box::new(kubernetes::Kubernetes {}),
+        "kubernetes" => Box::new(kubernetes::Kubernetes::default()),
+        "oci" => Box::new(oci::Oci {}),
+        "cri" => Box::new(cri::Cri {}),
         _ => return None,
     })
 }
 
+/// Returns the `kubernetes` container type with explicit cluster, namespace and node settings.
+pub fn kubernetes_container(options: KubernetesOptions) -> Box<dyn Container> {
+    Box::new(kubernetes::Kubernetes { options })
+}
+
+/// Resolves `container_id` with the first of `container_types` that knows it, or with all
+/// available runtimes if `container_types` is empty.
+///
+/// A runtime that finds several matching containers ends the search with `Error::Ambiguous`.
 pub fn lookup_container_pid(
     container_id: &str,
     container_types: &[Box<dyn Container>],
-) -> Result<pid_t> {
-    for c in container_types {
-        c.check_required_tools()?;
+) -> std::result::Result<ContainerProcess, LookupError> {
+    lookup_container_pid_with_options(container_id, container_types, &LookupOptions::default())
+}
+
+/// Like `lookup_container_pid`, with the host paths taken from `options`.
+pub fn lookup_container_pid_with_options(
+    container_id: &str,
+    container_types: &[Box<dyn Container>],
+    options: &LookupOptions,
+) -> std::result::Result<ContainerProcess, LookupError> {
+    let mut errors = vec![];
+    let fallback: Vec<Box<dyn Container>> = default_order(options);
+    let types = if container_types.is_empty() {
+        fallback.iter().collect::<Vec<_>>()
+    } else {
+        // explicitly requested runtimes report missing tools instead of being skipped
+        container_types
+            .iter()
+            .filter(|c| match c.check_required_tools(options) {
+                Ok(()) => true,
+                Err(e) => {
+                    errors.push((c.name(), e));
+                    false
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for t in types {
+        match t.lookup(container_id, options) {
+            Ok(process) => return Ok(process),
+            // a later runtime must not pick one of the candidates behind the user's back
+            Err(e @ Error::Ambiguous { .. }) => {
+                errors.push((t.name(), e));
+                break;
+            }
+            Err(e) => errors.push((t.name(), e)),
+        };
     }
-    let fallback: Vec<Box<dyn Container>> = default_order();
+
+    Err(LookupError {
+        operation: Operation::Lookup {
+            query: container_id.to_string(),
+        },
+        errors,
+    })
+}
+
+/// Looks up every id with `container`, skipping containers that stopped since they were listed.
+pub(crate) fn lookup_all<'a>(
+    container: &dyn Container,
+    ids: impl Iterator<Item = &'a str>,
+    options: &LookupOptions,
+) -> Result<Vec<ContainerProcess>> {
+    let mut containers = vec![];
+    for id in ids {
+        match container.lookup(id, options) {
+            Ok(process) => containers.push(process),
+            Err(Error::NotFound(_)) | Err(Error::NotRunning(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(containers)
+}
+
+/// Lists the running containers of all `container_types`, or of all available runtimes if
+/// `container_types` is empty.
+///
+/// Runtimes that fail are skipped as long as at least one runtime could be queried. A container
+/// that several runtimes list is returned once, as listed by the first of them.
+pub fn list_containers(
+    container_types: &[Box<dyn Container>],
+) -> std::result::Result<Vec<ContainerProcess>, LookupError> {
+    list_containers_with_options(container_types, &LookupOptions::default())
+}
+
+/// Like `list_containers`, with the host paths taken from `options`.
+pub fn list_containers_with_options(
+    container_types: &[Box<dyn Container>],
+    options: &LookupOptions,
+) -> std::result::Result<Vec<ContainerProcess>, LookupError> {
+    let fallback: Vec<Box<dyn Container>> = default_order(options);
     let types = if container_types.is_empty() {
         fallback.as_slice()
     } else {
         container_types
     };
 
-    let mut message = String::from("failed to find container - tried the following runtimes:");
+    let mut errors = vec![];
+    let mut containers: Vec<ContainerProcess> = vec![];
     for t in types {
-        match t.lookup(container_id) {
-            Ok(pid) => return Ok(pid),
-            Err(e) => {
-                message += &format!("\n  - {:?}: {}", t, e);
+        match t
+            .check_required_tools(options)
+            .and_then(|_| t.list(options))
+        {
+            // a container can be seen by several runtimes, i.e. a pod container by kubernetes,
+            // cri and oci; the first and most specific one is kept
+            Ok(list) => {
+                for process in list {
+                    if !containers
+                        .iter()
+                        .any(|c| c.pid == process.pid && c.start_time == process.start_time)
+                    {
+                        containers.push(process);
+                    }
+                }
             }
-        };
+            Err(e) => errors.push((t.name(), e)),
+        }
     }
 
-    bail!("{}", message)
+    if !types.is_empty() && errors.len() == types.len() {
+        return Err(LookupError {
+            operation: Operation::List,
+            errors,
+        });
+    }
+    Ok(containers)
+}
+
+/// Like `lookup_container_pid`, but returns a handle that stays valid if the PID is recycled.
+pub fn lookup_container_pidfd(
+    container_id: &str,
+    container_types: &[Box<dyn Container>],
+) -> std::result::Result<ProcessHandle, LookupError> {
+    lookup_container_pidfd_with_options(container_id, container_types, &LookupOptions::default())
+}
+
+/// Like `lookup_container_pidfd`, with the host paths taken from `options`.
+pub fn lookup_container_pidfd_with_options(
+    container_id: &str,
+    container_types: &[Box<dyn Container>],
+    options: &LookupOptions,
+) -> std::result::Result<ProcessHandle, LookupError> {
+    let process = lookup_container_pid_with_options(container_id, container_types, options)?;
+    process.open_with_options(options).map_err(|e| LookupError {
+        operation: Operation::Lookup {
+            query: container_id.to_string(),
+        },
+        errors: vec![(process.runtime, e)],
+    })
 }
@@ -1,45 +1,65 @@
-use anyhow::{bail, Context};
 use libc::pid_t;
-use std::process::Command;
 
 use crate::cmd;
-use crate::result::Result;
-use crate::Container;
+use crate::result::{Error, Result};
+use crate::{lookup_all, Container, ContainerProcess, LookupOptions};
 
 #[derive(Clone, Debug)]
 pub(crate) struct Lxc {}
 
 impl Container for Lxc {
-    fn lookup(&self, container_id: &str) -> Result<pid_t> {
-        let output = Command::new("lxc-info")
-            .args(&["--no-humanize", "--pid", "--name", container_id])
-            .output()
-            .context("failed to execute 'lxc-info'")?;
+    fn name(&self) -> &'static str {
+        "lxc"
+    }
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        let stdout = cmd::run(
+            options,
+            "lxc-info",
+            &["--no-humanize", "--pid", "--name", container_id],
+        )?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!(
-                "lxc-info command failed (exit status {}): {}",
-                output.status,
-                stderr.trim_start()
-            );
+        let pid = String::from_utf8_lossy(&stdout);
+        let pid = pid.trim();
+        // lxc-info prints nothing if the container is defined but stopped
+        if pid.is_empty() {
+            return Err(Error::NotRunning(format!(
+                "lxc container '{}' is not running",
+                container_id
+            )));
         }
 
-        let pid = String::from_utf8_lossy(&output.stdout);
-
-        pid.trim_start().parse::<pid_t>().with_context(|| {
-            format!(
-                "invalid PID '{}' from lxc-info for container '{}'",
-                pid.trim(),
-                container_id
-            )
-        })
+        let pid = pid.parse::<pid_t>().map_err(|e| {
+            Error::ParseError(format!(
+                "invalid PID '{}' from lxc-info for container '{}': {}",
+                pid, container_id, e
+            ))
+        })?;
+        // lxc containers are only addressed by their name
+        Ok(ContainerProcess::new(
+            self.name(),
+            container_id,
+            container_id,
+            pid,
+            container_id,
+            &options.proc_root,
+        ))
+    }
+    fn list(&self, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        let stdout = cmd::run(options, "lxc-ls", &["--running", "--line"])?;
+        let stdout = String::from_utf8_lossy(&stdout);
+        lookup_all(
+            self,
+            stdout.lines().map(str::trim).filter(|l| !l.is_empty()),
+            options,
+        )
     }
-    fn check_required_tools(&self) -> Result<()> {
-        if cmd::which("lxc-info").is_some() {
+    fn check_required_tools(&self, options: &LookupOptions) -> Result<()> {
+        if cmd::which(options, "lxc-info").is_some() {
             Ok(())
         } else {
-            bail!("LXC runtime not found: 'lxc-info' command is not available")
+            Err(Error::ToolUnavailable(String::from(
+                "LXC runtime not found: 'lxc-info' command is not available",
+            )))
         }
     }
 }
@@ -1,61 +1,325 @@
-use anyhow::{bail, Context};
+//! Resolves LXD and Incus instances.
+//!
+//! Possible container_id inputs:
+//!
+//! - `name` for an instance in the default project
+//! - `project/name` for an instance in another project
+
 use libc::pid_t;
-use std::process::Command;
+use serde_json::Value;
+use std::env;
+use std::path::{Path, PathBuf};
 
-use crate::cmd;
-use crate::result::Result;
-use crate::Container;
+use crate::result::{Error, Result};
+use crate::{cmd, http};
+use crate::{Container, ContainerProcess, LookupOptions};
 
 #[derive(Clone, Debug)]
 pub(crate) struct Lxd {}
 
+const SOCKETS: &[&str] = &[
+    "/var/snap/lxd/common/lxd/unix.socket",
+    "/var/lib/lxd/unix.socket",
+    "/var/lib/incus/unix.socket",
+];
+
+/// Sockets of the LXD and Incus daemons that exist on this machine, in the order they are tried.
+fn api_sockets(options: &LookupOptions) -> Vec<PathBuf> {
+    let mut candidates = vec![];
+    for var in &["LXD_DIR", "INCUS_DIR"] {
+        if let Some(dir) = env::var_os(var) {
+            candidates.push(PathBuf::from(dir).join("unix.socket"));
+        }
+    }
+    candidates.extend(SOCKETS.iter().map(|socket| options.state_path(socket)));
+
+    let mut sockets: Vec<PathBuf> = vec![];
+    for path in candidates {
+        if path.exists() && !sockets.contains(&path) {
+            sockets.push(path);
+        }
+    }
+    sockets
+}
+
+/// Splits `project/name` into its parts, instance names cannot contain slashes.
+fn parse_userinput(container_id: &str) -> (Option<&str>, &str) {
+    match container_id.split_once('/') {
+        Some((project, name)) => (Some(project), name),
+        None => (None, container_id),
+    }
+}
+
+/// Fetches `path` and returns the `metadata` of the response.
+fn api_get(socket: &Path, path: &str) -> Result<Value> {
+    let response = http::get(socket, path)?;
+    match response.status {
+        200 => {
+            let mut body = response.json()?;
+            Ok(body["metadata"].take())
+        }
+        404 => Err(Error::NotFound(format!(
+            "GET {} on '{}': {}",
+            path,
+            socket.display(),
+            response.error_message()
+        ))),
+        401 | 403 => Err(Error::PermissionDenied(format!(
+            "GET {} on '{}' was denied: {}",
+            path,
+            socket.display(),
+            response.error_message()
+        ))),
+        status => Err(Error::CommandFailed(format!(
+            "GET {} on '{}' failed with status {}: {}",
+            path,
+            socket.display(),
+            status,
+            response.error_message()
+        ))),
+    }
+}
+
+fn check_instance(instance_type: Option<&str>, container_id: &str) -> Result<()> {
+    match instance_type {
+        Some("virtual-machine") => Err(Error::Unsupported(format!(
+            "lxd instance '{}' is a virtual machine, not a container",
+            container_id
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn lookup_api(socket: &Path, container_id: &str, proc_root: &Path) -> Result<ContainerProcess> {
+    let (project, name) = parse_userinput(container_id);
+    let query = project
+        .map(|p| format!("?project={}", http::encode_path_segment(p)))
+        .unwrap_or_default();
+    let instance_path = format!("/1.0/instances/{}", http::encode_path_segment(name));
+
+    let instance = api_get(socket, &format!("{}{}", instance_path, query))?;
+    check_instance(instance["type"].as_str(), container_id)?;
+
+    let state = api_get(socket, &format!("{}/state{}", instance_path, query))?;
+    let status = state["status"].as_str().unwrap_or_default();
+    if status != "Running" {
+        return Err(Error::NotRunning(format!(
+            "lxd container '{}' is not running (status: {})",
+            container_id, status
+        )));
+    }
+    let pid = state["pid"].as_i64().ok_or_else(|| {
+        Error::ParseError(format!(
+            "no 'pid' field in lxd state of container '{}'",
+            container_id
+        ))
+    })?;
+
+    let name = instance["name"].as_str().unwrap_or(name);
+    Ok(ContainerProcess::new(
+        "lxd",
+        name,
+        name,
+        pid as pid_t,
+        container_id,
+        proc_root,
+    ))
+}
+
+fn list_api(socket: &Path, proc_root: &Path) -> Result<Vec<ContainerProcess>> {
+    // recursion=2 includes the state of every instance
+    let path = "/1.0/instances?recursion=2&all-projects=true";
+    let instances = api_get(socket, path)?;
+    let instances = instances.as_array().ok_or_else(|| {
+        Error::ParseError(format!("expected a list of instances from GET {}", path))
+    })?;
+
+    let mut processes = vec![];
+    for instance in instances {
+        if instance["type"].as_str() != Some("container")
+            || instance["status"].as_str() != Some("Running")
+        {
+            continue;
+        }
+        let (name, pid) = match (instance["name"].as_str(), instance["state"]["pid"].as_i64()) {
+            (Some(name), Some(pid)) => (name, pid),
+            _ => {
+                return Err(Error::ParseError(format!(
+                    "instance without 'name' or 'state.pid' in response of GET {}",
+                    path
+                )))
+            }
+        };
+        let query = match instance["project"].as_str() {
+            Some(project) if project != "default" => format!("{}/{}", project, name),
+            _ => name.to_string(),
+        };
+        processes.push(ContainerProcess::new(
+            "lxd",
+            name,
+            name,
+            pid as pid_t,
+            query,
+            proc_root,
+        ));
+    }
+    Ok(processes)
+}
+
 impl Container for Lxd {
-    fn lookup(&self, container_id: &str) -> Result<pid_t> {
-        let output = Command::new("lxc")
-            .args(&["info", container_id])
-            .output()
-            .context("failed to execute 'lxc info'")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!(
-                "lxc info command failed (exit status {}): {}",
-                output.status,
-                stderr.trim_end()
-            );
-        }
-
-        let lines = output.stdout.split(|&c| c == b'\n');
-        let mut rows = lines.map(|line| {
-            let cols: Vec<&[u8]> = line.splitn(2, |&c| c == b':').collect();
-            cols
-        });
-
-        if let Some(pid_row) = rows.find(|cols| cols[0] == b"Pid") {
+    fn name(&self) -> &'static str {
+        "lxd"
+    }
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        let sockets = api_sockets(options);
+        if !sockets.is_empty() {
+            // LXD and Incus may be installed side by side
+            let mut errors = vec![];
+            for socket in sockets {
+                match lookup_api(&socket, container_id, &options.proc_root) {
+                    Err(e @ Error::NotFound(_)) | Err(e @ Error::DaemonUnreachable(_)) => {
+                        errors.push(e)
+                    }
+                    res => return res,
+                }
+            }
+            let not_found = errors.iter().position(|e| matches!(e, Error::NotFound(_)));
+            return Err(errors.swap_remove(not_found.unwrap_or(0)));
+        }
+
+        let (project, name) = parse_userinput(container_id);
+        let mut args = vec!["info", name];
+        if let Some(project) = project {
+            args.extend(&["--project", project]);
+        }
+        let stdout = cmd::run(options, "lxc", &args)?;
+
+        let lines = stdout.split(|&c| c == b'\n');
+        let rows: Vec<Vec<&[u8]>> = lines
+            .map(|line| {
+                let cols: Vec<&[u8]> = line.splitn(2, |&c| c == b':').collect();
+                cols
+            })
+            .collect();
+        // depending on the release, keys are `Pid`/`Status: Running` or `PID`/`Status: RUNNING`
+        let field = |key: &[u8]| {
+            rows.iter()
+                .find(|cols| cols[0].eq_ignore_ascii_case(key) && cols.len() == 2)
+                .map(|cols| String::from_utf8_lossy(cols[1]).trim().to_string())
+        };
+
+        check_instance(field(b"Type").as_deref(), container_id)?;
+
+        let pid = if let Some(pid_row) = rows
+            .iter()
+            .find(|cols| cols[0].eq_ignore_ascii_case(b"Pid"))
+        {
             if pid_row.len() != 2 {
-                bail!("unexpected format in 'Pid' field from lxc info");
+                return Err(Error::ParseError(String::from(
+                    "unexpected format in 'Pid' field from lxc info",
+                )));
             }
             let pid = String::from_utf8_lossy(pid_row[1]);
 
-            pid.trim_start().parse::<pid_t>().with_context(|| {
-                format!(
-                    "invalid PID '{}' from lxd for container '{}'",
+            pid.trim().parse::<pid_t>().map_err(|e| {
+                Error::ParseError(format!(
+                    "invalid PID '{}' from lxd for container '{}': {}",
                     pid.trim(),
-                    container_id
-                )
-            })
+                    container_id,
+                    e
+                ))
+            })?
         } else {
-            bail!(
-                "no 'Pid' field found in lxd info output for container '{}'",
-                container_id
-            )
+            return Err(match field(b"Status") {
+                Some(status) if !status.eq_ignore_ascii_case("running") => {
+                    Error::NotRunning(format!(
+                        "lxd container '{}' is not running (status: {})",
+                        container_id, status
+                    ))
+                }
+                _ => Error::ParseError(format!(
+                    "no 'Pid' field found in lxd info output for container '{}'",
+                    container_id
+                )),
+            });
+        };
+
+        let name = field(b"Name").unwrap_or_else(|| container_id.to_string());
+
+        Ok(ContainerProcess::new(
+            self.name(),
+            name.clone(),
+            name,
+            pid,
+            container_id,
+            &options.proc_root,
+        ))
+    }
+    /// Lists the running containers of every daemon socket, or of `lxc list` without one. Sockets
+    /// that fail, i.e. one left behind by an uninstalled LXD next to the snap, are skipped as long
+    /// as any of them could be listed.
+    fn list(&self, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        let sockets = api_sockets(options);
+        if !sockets.is_empty() {
+            let mut processes = vec![];
+            let mut errors = vec![];
+            let mut answered = false;
+            for socket in sockets {
+                match list_api(&socket, &options.proc_root) {
+                    Ok(mut found) => {
+                        answered = true;
+                        processes.append(&mut found);
+                    }
+                    Err(e) => errors.push(e),
+                }
+            }
+            return match errors.into_iter().next() {
+                Some(e) if !answered => Err(e),
+                _ => Ok(processes),
+            };
+        }
+
+        // $ lxc list --format csv --columns nspt
+        // foo,RUNNING,1234,CONTAINER
+        // bar,STOPPED,,CONTAINER
+        // vm,RUNNING,5678,VIRTUAL-MACHINE
+        // ci,RUNNING,4388,CONTAINER (EPHEMERAL)
+        let stdout = cmd::run(
+            options,
+            "lxc",
+            &["list", "--format", "csv", "--columns", "nspt"],
+        )?;
+        let stdout = String::from_utf8_lossy(&stdout);
+        let mut containers = vec![];
+        for line in stdout.lines() {
+            let cols = line.split(',').collect::<Vec<_>>();
+            if cols.len() != 4 || cols[1] != "RUNNING" || !cols[3].starts_with("CONTAINER") {
+                continue;
+            }
+            let pid = cols[2].parse::<pid_t>().map_err(|e| {
+                Error::ParseError(format!(
+                    "invalid PID '{}' from lxd for container '{}': {}",
+                    cols[2], cols[0], e
+                ))
+            })?;
+            containers.push(ContainerProcess::new(
+                self.name(),
+                cols[0],
+                cols[0],
+                pid,
+                cols[0],
+                &options.proc_root,
+            ));
         }
+        Ok(containers)
     }
-    fn check_required_tools(&self) -> Result<()> {
-        if cmd::which("lxc").is_some() {
+    fn check_required_tools(&self, options: &LookupOptions) -> Result<()> {
+        if !api_sockets(options).is_empty() || cmd::which(options, "lxc").is_some() {
             Ok(())
         } else {
-            bail!("LXD runtime not found: 'lxc' command is not available")
+            Err(Error::ToolUnavailable(String::from(
+                "LXD runtime not found: no daemon socket and 'lxc' command is not available",
+            )))
         }
     }
 }
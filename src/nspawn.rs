@@ -1,53 +1,273 @@
-use anyhow::{bail, Context};
 use libc::pid_t;
-use std::process::Command;
+use std::path::Path;
 
-use crate::cmd;
-use crate::result::Result;
-use crate::Container;
+use crate::dbus::{self, Connection, Value};
+use crate::result::{Error, Result};
+use crate::{cmd, lookup_all, Container, ContainerProcess, LookupOptions};
 
 #[derive(Clone, Debug)]
 pub(crate) struct Nspawn {}
 
-impl Container for Nspawn {
-    fn lookup(&self, container_id: &str) -> Result<pid_t> {
-        let output = Command::new("machinectl")
-            .args(&["show", "--property=Leader", container_id])
-            .output()
-            .context("failed to execute 'machinectl show'")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!(
-                "machinectl show command failed (exit status {}): {}",
-                output.status,
-                stderr.trim_end()
-            );
+const MACHINED: &str = "org.freedesktop.machine1";
+const MACHINED_PATH: &str = "/org/freedesktop/machine1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.machine1.Manager";
+const MACHINE_INTERFACE: &str = "org.freedesktop.machine1.Machine";
+
+/// A machine registered with systemd-machined.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Machine {
+    pub name: String,
+    /// 128-bit machine ID in hex, all zeros if the machine did not register one.
+    pub id: String,
+    /// `container` or `vm`
+    pub class: String,
+    /// PID of the machine's leader process, i.e. the init process of a container.
+    pub leader: pid_t,
+    /// Root directory of a container, empty for virtual machines.
+    pub root_directory: String,
+    /// systemd scope or service unit the machine runs in.
+    pub unit: String,
+}
+
+/// Queries systemd-machined on the system bus of this host for the machine `name`.
+pub fn lookup_machine(name: &str) -> Result<Machine> {
+    lookup_machine_with_options(name, &LookupOptions::default())
+}
+
+/// Like `lookup_machine`, with the system bus socket resolved against `options.state_root`.
+pub fn lookup_machine_with_options(name: &str, options: &LookupOptions) -> Result<Machine> {
+    let mut conn = Connection::system(options)?;
+    get_machine(&mut conn, name)
+}
+
+/// Virtual machines registered with machined have a hypervisor as leader, not an init process.
+fn check_class(class: &str, container_id: &str) -> Result<()> {
+    match class {
+        "vm" => Err(Error::Unsupported(format!(
+            "machine '{}' is a virtual machine, not a container",
+            container_id
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn get_machine(conn: &mut Connection, name: &str) -> Result<Machine> {
+    let reply = conn.call(
+        MACHINED,
+        MACHINED_PATH,
+        MANAGER_INTERFACE,
+        "GetMachine",
+        &[name],
+    )?;
+    let path = reply.first().and_then(Value::as_str).ok_or_else(|| {
+        Error::ParseError(String::from("GetMachine did not return an object path"))
+    })?;
+    machine_properties(conn, path)
+}
+
+fn machine_properties(conn: &mut Connection, path: &str) -> Result<Machine> {
+    let reply = conn.call(
+        MACHINED,
+        path,
+        "org.freedesktop.DBus.Properties",
+        "GetAll",
+        &[MACHINE_INTERFACE],
+    )?;
+    let properties = reply.first().ok_or_else(|| {
+        Error::ParseError(format!("no properties returned for machine '{}'", path))
+    })?;
+    let string = |key: &str| {
+        properties
+            .get(key)
+            .and_then(Value::as_str)
+            .map(String::from)
+            .ok_or_else(|| {
+                Error::ParseError(format!("machine '{}' has no '{}' property", path, key))
+            })
+    };
+    let leader = properties
+        .get("Leader")
+        .and_then(Value::as_u32)
+        .ok_or_else(|| Error::ParseError(format!("machine '{}' has no 'Leader' property", path)))?;
+    let id = properties
+        .get("Id")
+        .and_then(Value::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|b| match b {
+            Value::Byte(b) => Some(format!("{:02x}", b)),
+            _ => None,
+        })
+        .collect();
+
+    Ok(Machine {
+        name: string("Name")?,
+        id,
+        class: string("Class")?,
+        leader: leader as pid_t,
+        root_directory: string("RootDirectory").unwrap_or_default(),
+        unit: string("Unit").unwrap_or_default(),
+    })
+}
+
+/// machined is queried over D-Bus if the system bus is available, otherwise `machinectl` is used.
+fn use_dbus(options: &LookupOptions) -> bool {
+    dbus::system_bus_path(options).is_some_and(|path| path.exists())
+}
+
+fn machine_process(machine: Machine, container_id: &str, proc_root: &Path) -> ContainerProcess {
+    ContainerProcess::new(
+        "nspawn",
+        machine.name.clone(),
+        machine.name,
+        machine.leader,
+        container_id,
+        proc_root,
+    )
+}
+
+fn list_dbus(options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+    let mut conn = Connection::system(options)?;
+    let reply = conn.call(
+        MACHINED,
+        MACHINED_PATH,
+        MANAGER_INTERFACE,
+        "ListMachines",
+        &[],
+    )?;
+    // a(ssso): name, class, service, object path
+    let machines = reply.first().and_then(Value::as_array).unwrap_or_default();
+    let mut containers = vec![];
+    for machine in machines {
+        let fields = machine.as_array().unwrap_or_default();
+        if let (Some("container"), Some(Value::Str(path))) =
+            (fields.get(1).and_then(Value::as_str), fields.get(3))
+        {
+            match machine_properties(&mut conn, path) {
+                Ok(machine) => {
+                    let name = machine.name.clone();
+                    containers.push(machine_process(machine, &name, &options.proc_root));
+                }
+                // the machine terminated after it was listed
+                Err(Error::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
         }
+    }
+    Ok(containers)
+}
 
-        let fields: Vec<&[u8]> = output.stdout.splitn(2, |c| *c == b'=').collect();
-        if fields.len() != 2 {
-            bail!(
-                "unexpected output format from machinectl show for container '{}'",
-                container_id
-            );
+impl Container for Nspawn {
+    fn name(&self) -> &'static str {
+        "nspawn"
+    }
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        if use_dbus(options) {
+            let mut conn = Connection::system(options)?;
+            let machine = get_machine(&mut conn, container_id)?;
+            check_class(&machine.class, container_id)?;
+            return Ok(machine_process(machine, container_id, &options.proc_root));
         }
 
-        let pid = String::from_utf8_lossy(fields[1]);
+        let stdout = cmd::run(
+            options,
+            "machinectl",
+            &[
+                "show",
+                "--property=Name",
+                "--property=Leader",
+                "--property=Class",
+                container_id,
+            ],
+        )?;
 
-        pid.trim_end().parse::<pid_t>().with_context(|| {
-            format!(
-                "invalid PID '{}' from machinectl for container '{}'",
-                pid.trim(),
-                container_id
-            )
-        })
+        // $ machinectl show --property=Name --property=Leader --property=Class foo
+        // Name=foo
+        // Leader=1234
+        // Class=container
+        let stdout = String::from_utf8_lossy(&stdout);
+        let property = |key: &str| {
+            stdout.lines().find_map(|line| {
+                let fields: Vec<&str> = line.splitn(2, '=').collect();
+                if fields.len() == 2 && fields[0] == key {
+                    Some(fields[1].trim_end())
+                } else {
+                    None
+                }
+            })
+        };
+
+        check_class(property("Class").unwrap_or_default(), container_id)?;
+
+        let pid = match property("Leader") {
+            Some(pid) => pid.parse::<pid_t>().map_err(|e| {
+                Error::ParseError(format!(
+                    "invalid PID '{}' from machinectl for container '{}': {}",
+                    pid, container_id, e
+                ))
+            })?,
+            None => {
+                return Err(Error::ParseError(format!(
+                    "unexpected output format from machinectl show for container '{}'",
+                    container_id
+                )))
+            }
+        };
+        let name = property("Name").unwrap_or(container_id).to_string();
+
+        Ok(ContainerProcess::new(
+            self.name(),
+            name.clone(),
+            name,
+            pid,
+            container_id,
+            &options.proc_root,
+        ))
+    }
+    fn list(&self, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        if use_dbus(options) {
+            return list_dbus(options);
+        }
+
+        // $ machinectl list --no-legend
+        // foo container systemd-nspawn nixos 23.05 -
+        // bar vm        libvirt-qemu   -     -     -
+        let stdout = cmd::run(options, "machinectl", &["list", "--no-legend"])?;
+        let stdout = String::from_utf8_lossy(&stdout);
+        let machines = stdout.lines().filter_map(|line| {
+            let cols = line.split_whitespace().collect::<Vec<_>>();
+            match cols.as_slice() {
+                [name, "container", ..] => Some(*name),
+                _ => None,
+            }
+        });
+        lookup_all(self, machines, options)
     }
-    fn check_required_tools(&self) -> Result<()> {
-        if cmd::which("machinectl").is_some() {
+    fn check_required_tools(&self, options: &LookupOptions) -> Result<()> {
+        if use_dbus(options) {
+            // the bus activates machined if it is installed, other failures are left to lookup
+            let ping = Connection::system(options).and_then(|mut conn| {
+                conn.call(
+                    MACHINED,
+                    MACHINED_PATH,
+                    "org.freedesktop.DBus.Peer",
+                    "Ping",
+                    &[],
+                )
+            });
+            match ping {
+                Err(e @ Error::ToolUnavailable(_)) => {
+                    Err(e.context("systemd-nspawn runtime not found"))
+                }
+                _ => Ok(()),
+            }
+        } else if cmd::which(options, "machinectl").is_some() {
             Ok(())
         } else {
-            bail!("systemd-nspawn runtime not found: 'machinectl' command is not available")
+            Err(Error::ToolUnavailable(String::from(
+                "systemd-nspawn runtime not found: no system bus and 'machinectl' command is not available",
+            )))
         }
     }
 }
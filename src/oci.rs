@@ -0,0 +1,404 @@
+//! Finds containers started by a plain OCI runtime (runc, crun, youki, runsc) by reading the
+//! state files these runtimes keep in their root directory, i.e. `/run/runc/<id>/state.json` or
+//! `/run/runsc/<id>_sandbox:<sandbox id>.state`.
+//!
+//! Additional root directories (i.e. a custom `runc --root`) are taken from
+//! `LookupOptions::oci_roots`, which defaults to the `CNTR_OCI_ROOT` environment variable,
+//! separated by `:`.
+
+use libc::pid_t;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::pidfd::process_start_time;
+use crate::result::{Error, Result};
+use crate::rootless;
+use crate::{Container, ContainerProcess, LookupOptions};
+
+#[derive(Clone, Debug)]
+pub(crate) struct Oci {}
+
+/// Default `--root` of the runtimes when running as root. The roots docker and containerd use
+/// for their runc shims are left to the backends of those runtimes, which know the names of the
+/// containers; they can be added through `LookupOptions::oci_roots`.
+const DEFAULT_ROOTS: &[&str] = &["/run/runc", "/run/crun", "/run/youki", "/run/runsc"];
+
+/// State files of the runtimes that keep a directory per container: runc and youki write
+/// `state.json`, crun `status`.
+const STATE_FILES: &[&str] = &["state.json", "status"];
+
+/// runsc keeps the state of all containers in its root, as `<id>_sandbox:<sandbox id>.state`.
+const RUNSC_SANDBOX_INFIX: &str = "_sandbox:";
+const RUNSC_STATE_SUFFIX: &str = ".state";
+
+/// `Status` of a runsc container that executes its process.
+const RUNSC_RUNNING: u64 = 3;
+
+fn roots(options: &LookupOptions) -> Vec<PathBuf> {
+    let mut candidates: Vec<PathBuf> = options
+        .oci_roots
+        .iter()
+        .map(|root| options.state_path(root))
+        .collect();
+    candidates.extend(DEFAULT_ROOTS.iter().map(|root| options.state_path(root)));
+    // rootless runtimes keep their state in the user's runtime directory
+    if let Some(runtime_dir) = rootless::session_runtime_dir(options) {
+        for runtime in &["runc", "crun", "youki", "runsc"] {
+            candidates.push(runtime_dir.join(runtime));
+        }
+    }
+
+    let mut roots: Vec<PathBuf> = vec![];
+    for root in candidates {
+        if root.is_dir() && !roots.contains(&root) {
+            roots.push(root);
+        }
+    }
+    roots
+}
+
+/// Reads a number that older runc versions encoded as string.
+fn as_u64(value: &Value) -> Option<u64> {
+    value
+        .as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+}
+
+/// The ID of the container whose runsc state file is `file_name`.
+fn runsc_container_id(file_name: &str) -> Option<&str> {
+    let name = file_name.strip_suffix(RUNSC_STATE_SUFFIX)?;
+    name.find(RUNSC_SANDBOX_INFIX).map(|end| &name[..end])
+}
+
+/// State files of container `id` below `root`, in the order they are tried.
+fn state_files(root: &Path, id: &str) -> Result<Vec<PathBuf>> {
+    let dir = root.join(id);
+    let mut files: Vec<PathBuf> = STATE_FILES.iter().map(|file| dir.join(file)).collect();
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(Error::io(format!("failed to read '{}'", root.display()), e)),
+    };
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| Error::io(format!("failed to read '{}'", root.display()), e))?;
+        let name = entry.file_name();
+        if runsc_container_id(&name.to_string_lossy()) == Some(id) {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+/// IDs of the containers with a state below `root`.
+fn container_ids(root: &Path) -> Result<Vec<String>> {
+    let entries = fs::read_dir(root)
+        .map_err(|e| Error::io(format!("failed to read '{}'", root.display()), e))?;
+    let mut ids = vec![];
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| Error::io(format!("failed to read '{}'", root.display()), e))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let id = match runsc_container_id(&name) {
+            Some(id) => id.to_string(),
+            // runsc also keeps lock files in its root
+            None if !name.contains(RUNSC_SANDBOX_INFIX) => name,
+            None => continue,
+        };
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+/// How many seconds after the recorded creation time the container process may have started.
+/// A process that started later reuses the PID of the container process.
+const CREATE_TOLERANCE: u64 = 10;
+
+/// Seconds since the epoch of an RFC 3339 timestamp such as `2024-05-02T09:16:40.123Z`.
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = timestamp.get(range)?;
+        if digits.bytes().all(|c| c.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+    let bytes = timestamp.as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' {
+        return None;
+    }
+    if !matches!(bytes[10], b'T' | b't' | b' ') || bytes[16] != b':' {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    let mut rest = &timestamp[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        rest = &fraction[digits..];
+    }
+    let offset = match rest.as_bytes() {
+        [b'Z'] | [b'z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let hours: i64 = rest.get(1..3)?.parse().ok()?;
+            let minutes: i64 = rest.get(4..6)?.parse().ok()?;
+            let offset = (hours * 60 + minutes) * 60;
+            if *sign == b'+' {
+                offset
+            } else {
+                -offset
+            }
+        }
+        _ => return None,
+    };
+
+    // days since the epoch of the proleptic Gregorian calendar date
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + hour * 3_600 + minute * 60 + second - offset;
+    if seconds < 0 {
+        None
+    } else {
+        Some(seconds as u64)
+    }
+}
+
+/// Boot time in seconds since the epoch, from the `btime` line of `<proc_root>/stat`.
+fn boot_time(proc_root: &Path) -> Result<u64> {
+    let path = proc_root.join("stat");
+    let stat = fs::read_to_string(&path)
+        .map_err(|e| Error::io(format!("failed to read '{}'", path.display()), e))?;
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|btime| btime.trim().parse().ok())
+        .ok_or_else(|| Error::ParseError(format!("no boot time in '{}'", path.display())))
+}
+
+/// Checks that process `pid`, started `start` clock ticks after boot, started when the container
+/// was created at `created` and not after its PID became free again.
+fn check_created(proc_root: &Path, id: &str, pid: pid_t, start: u64, created: u64) -> Result<()> {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    let ticks = if ticks > 0 { ticks as u64 } else { 100 };
+    let started = boot_time(proc_root)? + start / ticks;
+    if started > created + CREATE_TOLERANCE {
+        return Err(Error::NotRunning(format!(
+            "OCI container '{}' is not running (PID {} was reused)",
+            id, pid
+        )));
+    }
+    Ok(())
+}
+
+/// Reads the state of container `id` below `root`.
+fn read_state(root: &Path, id: &str, proc_root: &Path) -> Result<ContainerProcess> {
+    let mut state = None;
+    for path in state_files(root, id)? {
+        match fs::read(&path) {
+            Ok(content) => {
+                let json: Value = serde_json::from_slice(&content).map_err(|e| {
+                    Error::ParseError(format!("invalid JSON in '{}': {}", path.display(), e))
+                })?;
+                state = Some((path, json));
+                break;
+            }
+            // a file in the root that is not a container directory
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    || e.raw_os_error() == Some(libc::ENOTDIR) => {}
+            Err(e) => return Err(Error::io(format!("failed to read '{}'", path.display()), e)),
+        }
+    }
+    let (path, state) = state.ok_or_else(|| {
+        Error::NotFound(format!(
+            "no OCI container '{}' found in '{}'",
+            id,
+            root.display()
+        ))
+    })?;
+
+    // youki records the status as string, runsc as number, runc and crun not at all
+    let running = match &state["status"] {
+        Value::Null => true,
+        Value::String(status) => status == "running",
+        status => status.as_u64() == Some(RUNSC_RUNNING),
+    };
+    if !running {
+        return Err(Error::NotRunning(format!(
+            "OCI container '{}' is not running (status {})",
+            id, state["status"]
+        )));
+    }
+
+    // runc: init_process_pid, youki and crun: pid, runsc: the PID of the sandbox
+    let pid = state["init_process_pid"]
+        .as_i64()
+        .or_else(|| state["pid"].as_i64())
+        .or_else(|| state["sandbox"]["pid"].as_i64())
+        .ok_or_else(|| Error::ParseError(format!("no init process PID in '{}'", path.display())))?;
+    if pid <= 0 {
+        return Err(Error::NotRunning(format!(
+            "OCI container '{}' is not running",
+            id
+        )));
+    }
+    let pid = pid as pid_t;
+
+    let current_start = match process_start_time(proc_root, pid) {
+        Ok(start) => start,
+        Err(Error::NotFound(_)) => {
+            return Err(Error::NotRunning(format!(
+                "OCI container '{}' is not running (process {} has exited)",
+                id, pid
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+    // runc: init_process_start, crun: process-start-time, youki and runsc only record when the
+    // container was created
+    let start_time =
+        as_u64(&state["init_process_start"]).or_else(|| as_u64(&state["process-start-time"]));
+    if let Some(start_time) = start_time {
+        if start_time != current_start {
+            return Err(Error::NotRunning(format!(
+                "OCI container '{}' is not running (PID {} was reused)",
+                id, pid
+            )));
+        }
+    } else {
+        let created = state["created"]
+            .as_str()
+            .or_else(|| state["createdAt"].as_str())
+            .and_then(parse_timestamp)
+            .ok_or_else(|| {
+                Error::ParseError(format!(
+                    "neither start time nor creation time in '{}'",
+                    path.display()
+                ))
+            })?;
+        check_created(proc_root, id, pid, current_start, created)?;
+    }
+
+    Ok(ContainerProcess {
+        runtime: "oci",
+        id: id.to_string(),
+        name: id.to_string(),
+        pid,
+        query: id.to_string(),
+        start_time: Some(current_start),
+    })
+}
+
+impl Container for Oci {
+    fn name(&self) -> &'static str {
+        "oci"
+    }
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        // container IDs never contain slashes, don't let them escape the root directory
+        if container_id.is_empty() || container_id.contains('/') || container_id == ".." {
+            return Err(Error::NotFound(format!(
+                "invalid OCI container id '{}'",
+                container_id
+            )));
+        }
+
+        let mut found = vec![];
+        let mut not_running = None;
+        for root in roots(options) {
+            match read_state(&root, container_id, &options.proc_root) {
+                Ok(process) => found.push((root, process)),
+                Err(Error::NotFound(_)) => {}
+                Err(e @ Error::NotRunning(_)) => not_running = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if found.len() > 1 {
+            return Err(Error::Ambiguous {
+                message: format!(
+                    "OCI container '{}' is running in several runtime roots",
+                    container_id
+                ),
+                candidates: found
+                    .iter()
+                    .map(|(root, p)| format!("{} (PID {})", root.join(&p.id).display(), p.pid))
+                    .collect(),
+            });
+        }
+        match found.pop() {
+            Some((_, process)) => Ok(process),
+            None => Err(not_running.unwrap_or_else(|| {
+                Error::NotFound(format!("no OCI container '{}' found", container_id))
+            })),
+        }
+    }
+    fn list(&self, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        let mut processes = vec![];
+        for root in roots(options) {
+            for id in container_ids(&root)? {
+                match read_state(&root, &id, &options.proc_root) {
+                    Ok(process) => processes.push(process),
+                    Err(Error::NotFound(_)) | Err(Error::NotRunning(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(processes)
+    }
+    fn check_required_tools(&self, options: &LookupOptions) -> Result<()> {
+        if roots(options).is_empty() {
+            Err(Error::ToolUnavailable(String::from(
+                "OCI runtime not found: no runc, crun, youki or runsc state directory exists",
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        for (timestamp, seconds) in &[
+            ("1970-01-01T00:00:00Z", 0),
+            ("2024-05-02T09:16:40Z", 1_714_641_400),
+            ("2024-05-02T09:16:40.123456789Z", 1_714_641_400),
+            ("2024-05-02T11:16:40+02:00", 1_714_641_400),
+            ("2024-05-02T04:46:40.5-04:30", 1_714_641_400),
+            ("2000-02-29T23:59:59Z", 951_868_799),
+        ] {
+            assert_eq!(parse_timestamp(timestamp), Some(*seconds), "{}", timestamp);
+        }
+        for timestamp in &[
+            "",
+            "2024-05-02",
+            "2024-05-02T09:16:40",
+            "2024-13-02T09:16:40Z",
+            "2024-05-02T09:16:40+0200",
+            "1969-12-31T23:59:59Z",
+            "2024-05-02T09:16:4\u{e9}Z",
+        ] {
+            assert_eq!(parse_timestamp(timestamp), None, "{}", timestamp);
+        }
+    }
+}
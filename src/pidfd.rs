@@ -0,0 +1,133 @@
+use libc::pid_t;
+use std::fs;
+use std::io;
+use std::os::unix::io::{AsFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use crate::result::{Error, Result};
+
+/// A handle on a resolved container process that cannot silently refer to a recycled PID.
+///
+/// On kernels with `pidfd_open` (Linux 5.3+) this holds a pidfd. On older kernels, or if seccomp
+/// forbids the syscall, only the PID is kept and `validate` has to be used to check that it still
+/// refers to the same process.
+#[derive(Debug)]
+pub struct ProcessHandle {
+    pid: pid_t,
+    start_time: u64,
+    pidfd: Option<OwnedFd>,
+    proc_root: PathBuf,
+}
+
+impl ProcessHandle {
+    /// Opens a handle for `pid` and checks that the process was started at `start_time`.
+    pub(crate) fn open(proc_root: &Path, pid: pid_t, start_time: u64) -> Result<ProcessHandle> {
+        // pidfd_open resolves the PID in our own namespace, which has to be the one it was read from
+        if !is_own_namespace(proc_root) {
+            return Err(Error::Unsupported(format!(
+                "PID {} from '{}' belongs to another PID namespace, it cannot be opened safely",
+                pid,
+                proc_root.display()
+            )));
+        }
+        let pidfd = match pidfd_open(pid) {
+            Ok(fd) => Some(fd),
+            // ENOSYS before Linux 5.3, EPERM from seccomp profiles that don't know the syscall
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) => None,
+            Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {
+                return Err(Error::NotRunning(format!("process {} has exited", pid)))
+            }
+            Err(e) => return Err(Error::io(format!("pidfd_open({}) failed", pid), e)),
+        };
+        let handle = ProcessHandle {
+            pid,
+            start_time,
+            pidfd,
+            proc_root: proc_root.to_path_buf(),
+        };
+        // The pidfd pins whatever process had this PID when it was opened. If the start time still
+        // matches afterwards, the pidfd refers to the process that was resolved.
+        handle.validate()?;
+        Ok(handle)
+    }
+
+    /// PID of the process, as seen from the proc filesystem used for the lookup.
+    pub fn pid(&self) -> pid_t {
+        self.pid
+    }
+
+    /// Start time of the process in clock ticks since boot, as found in `/proc/<pid>/stat`.
+    pub fn start_time(&self) -> u64 {
+        self.start_time
+    }
+
+    /// The pidfd for the process, `None` if `pidfd_open` is not supported or not permitted.
+    pub fn pidfd(&self) -> Option<BorrowedFd<'_>> {
+        self.pidfd.as_ref().map(|fd| fd.as_fd())
+    }
+
+    /// Consumes the handle and returns the pidfd, if one was opened.
+    pub fn into_pidfd(self) -> Option<OwnedFd> {
+        self.pidfd
+    }
+
+    /// Checks that the PID still refers to the process that was resolved.
+    pub fn validate(&self) -> Result<()> {
+        match process_start_time(&self.proc_root, self.pid) {
+            Ok(start_time) if start_time == self.start_time => Ok(()),
+            Ok(_) => Err(Error::NotRunning(format!(
+                "process {} has exited and its PID was reused",
+                self.pid
+            ))),
+            Err(Error::NotFound(_)) => Err(Error::NotRunning(format!(
+                "process {} has exited",
+                self.pid
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Whether `proc_root` shows the PID namespace of this process, i.e. `<proc_root>/self` is us.
+fn is_own_namespace(proc_root: &Path) -> bool {
+    fs::read_link(proc_root.join("self"))
+        .ok()
+        .and_then(|pid| pid.to_str()?.parse::<pid_t>().ok())
+        == Some(unsafe { libc::getpid() })
+}
+
+fn pidfd_open(pid: pid_t) -> io::Result<OwnedFd> {
+    let res = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pidfd_open returned a new file descriptor that nobody else owns
+    Ok(unsafe { OwnedFd::from_raw_fd(res as RawFd) })
+}
+
+/// Reads the start time (field 22) from `<proc_root>/<pid>/stat`.
+pub(crate) fn process_start_time(proc_root: &Path, pid: pid_t) -> Result<u64> {
+    let path = proc_root.join(pid.to_string()).join("stat");
+    let stat = fs::read_to_string(&path).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            Error::NotFound(format!("no process with PID {} found", pid))
+        } else {
+            Error::io(format!("failed to read '{}'", path.display()), e)
+        }
+    })?;
+    // the command name in field 2 may contain spaces and parentheses, so split after the last ')'
+    let fields = stat
+        .rfind(')')
+        .map(|idx| stat[idx + 1..].split_whitespace().collect::<Vec<_>>())
+        .unwrap_or_default();
+    // fields[0] is field 3 (state)
+    fields
+        .get(22 - 3)
+        .and_then(|field| field.parse::<u64>().ok())
+        .ok_or_else(|| {
+            Error::ParseError(format!(
+                "unexpected format of '{}': no start time found",
+                path.display()
+            ))
+        })
+}
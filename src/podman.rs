@@ -1,29 +1,309 @@
-use anyhow::bail;
+use libc::pid_t;
+use serde_json::Value;
+use std::env;
+use std::path::{Path, PathBuf};
 
-use crate::cmd;
-use crate::docker::parse_docker_output;
-use crate::result::Result;
-use crate::Container;
+use crate::docker::{
+    api_get, list_docker_containers, parse_docker_output, parse_inspect_json, parse_inspect_line,
+    INSPECT_FORMAT,
+};
+use crate::result::{Error, Result};
+use crate::rootless::{self, User};
+use crate::{cmd, http};
+use crate::{Container, ContainerProcess, LookupOptions};
 
 #[derive(Clone, Debug)]
 pub(crate) struct Podman {}
 
-impl Container for Podman {
-    fn lookup(&self, container_id: &str) -> Result<libc::pid_t> {
+/// Prefix of the libpod REST API, newer podman versions still serve older API versions.
+const API_PREFIX: &str = "/v3.0.0/libpod";
+
+const ROOTFUL_SOCKET: &str = "/run/podman/podman.sock";
+
+/// Sockets of the podman API service that exist on this machine, in the order they are tried.
+fn api_sockets(options: &LookupOptions) -> Vec<PathBuf> {
+    let mut candidates = vec![];
+    if let Some(host) = env::var("CONTAINER_HOST")
+        .ok()
+        .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+    {
+        candidates.push(host);
+    }
+    if let Some(runtime_dir) = rootless::session_runtime_dir(options) {
+        candidates.push(runtime_dir.join("podman/podman.sock"));
+    }
+    candidates.push(options.state_path(ROOTFUL_SOCKET));
+
+    let mut sockets: Vec<PathBuf> = vec![];
+    for path in candidates {
+        if path.exists() && !sockets.contains(&path) {
+            sockets.push(path);
+        }
+    }
+    sockets
+}
+
+fn lookup_container(
+    socket: &Path,
+    container_id: &str,
+    proc_root: &Path,
+) -> Result<ContainerProcess> {
+    let path = format!(
+        "{}/containers/{}/json",
+        API_PREFIX,
+        http::encode_path_segment(container_id)
+    );
+    let inspect = api_get(socket, &path, Some(container_id))?;
+    parse_inspect_json("podman", &inspect, container_id, proc_root)
+}
+
+/// Resolves a pod to the process of its infra container.
+fn lookup_pod(socket: &Path, pod_id: &str, proc_root: &Path) -> Result<ContainerProcess> {
+    let path = format!(
+        "{}/pods/{}/json",
+        API_PREFIX,
+        http::encode_path_segment(pod_id)
+    );
+    let pod = api_get(socket, &path, Some(pod_id))?;
+    let infra_id = match pod["InfraContainerID"].as_str() {
+        Some(id) if !id.is_empty() => id,
+        _ => {
+            return Err(Error::NotFound(format!(
+                "pod '{}' has no infra container",
+                pod_id
+            )))
+        }
+    };
+    let infra = lookup_container(socket, infra_id, proc_root).map_err(|e| match e {
+        Error::NotRunning(_) => Error::NotRunning(format!("pod '{}' is not running", pod_id)),
+        e => e.context(format!(
+            "failed to inspect infra container of pod '{}'",
+            pod_id
+        )),
+    })?;
+
+    Ok(ContainerProcess {
+        id: pod["Id"].as_str().unwrap_or(&infra.id).to_string(),
+        name: pod["Name"].as_str().unwrap_or(pod_id).to_string(),
+        query: pod_id.to_string(),
+        ..infra
+    })
+}
+
+fn lookup_api(socket: &Path, container_id: &str, proc_root: &Path) -> Result<ContainerProcess> {
+    match lookup_container(socket, container_id, proc_root) {
+        Err(Error::NotFound(_)) => {
+            lookup_pod(socket, container_id, proc_root).map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound(format!(
+                    "no container or pod named '{}' found via '{}'",
+                    container_id,
+                    socket.display()
+                )),
+                e => e,
+            })
+        }
+        res => res,
+    }
+}
+
+fn list_api(socket: &Path, proc_root: &Path) -> Result<Vec<ContainerProcess>> {
+    let path = format!("{}/containers/json", API_PREFIX);
+    let containers = api_get(socket, &path, None)?;
+    let containers = containers.as_array().ok_or_else(|| {
+        Error::ParseError(format!("expected a list of containers from GET {}", path))
+    })?;
+    let running = containers
+        .iter()
+        .filter(|c| c["State"].as_str() == Some("running"));
+
+    let mut processes = vec![];
+    for c in running {
+        let (id, pid) = match (c["Id"].as_str(), c["Pid"].as_i64()) {
+            (Some(id), Some(pid)) => (id, pid),
+            _ => {
+                return Err(Error::ParseError(format!(
+                    "container without 'Id' or 'Pid' in response of GET {}",
+                    path
+                )))
+            }
+        };
+        let name = c["Names"]
+            .as_array()
+            .and_then(|names| names.first())
+            .and_then(Value::as_str)
+            .unwrap_or(id);
+        processes.push(ContainerProcess::new(
+            "podman",
+            id,
+            name,
+            pid as pid_t,
+            id,
+            proc_root,
+        ));
+    }
+    Ok(processes)
+}
+
+/// Resolves a container or pod of the podman services of the current user and of root.
+fn lookup_system(container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+    let sockets = api_sockets(options);
+    if sockets.is_empty() {
         let cmd = vec![
             "podman",
             "inspect",
             "--format",
-            "{{.State.Running}};{{.State.Pid}}",
+            INSPECT_FORMAT,
             container_id,
         ];
-        parse_docker_output(cmd.as_slice(), container_id)
+        return parse_docker_output(options, "podman", cmd.as_slice(), container_id);
+    }
+
+    // rootless and rootful podman have separate container stores, so ask all of them
+    let mut errors = vec![];
+    for socket in sockets {
+        match lookup_api(&socket, container_id, &options.proc_root) {
+            Err(e @ Error::NotFound(_)) | Err(e @ Error::DaemonUnreachable(_)) => errors.push(e),
+            res => return res,
+        }
+    }
+    // a missing container is more relevant than a service that is not running
+    let not_found = errors.iter().position(|e| matches!(e, Error::NotFound(_)));
+    Err(errors.swap_remove(not_found.unwrap_or(0)))
+}
+
+/// Socket of the API service of a rootless user.
+fn user_socket(user: &User, options: &LookupOptions) -> PathBuf {
+    user.runtime_dir(options).join("podman/podman.sock")
+}
+
+/// Whether `lookup_user` and `list_user` can reach the containers of `user`, through the API
+/// service or the container storage.
+fn has_containers(user: &User, options: &LookupOptions) -> bool {
+    user_socket(user, options).exists() || user.has_storage(options)
+}
+
+/// Resolves `container_id` in the container store of `user`, through the user's API service or
+/// by running podman as that user.
+fn lookup_user(
+    user: &User,
+    container_id: &str,
+    query: &str,
+    options: &LookupOptions,
+) -> Result<ContainerProcess> {
+    let socket = user_socket(user, options);
+    let mut process = if socket.exists() {
+        lookup_api(&socket, container_id, &options.proc_root)?
+    } else if user.has_storage(options) {
+        let stdout = user.run(
+            options,
+            "podman",
+            &["inspect", "--format", INSPECT_FORMAT, container_id],
+        )?;
+        parse_inspect_line(
+            "podman",
+            String::from_utf8_lossy(&stdout).trim_end(),
+            container_id,
+            &options.proc_root,
+        )?
+    } else {
+        return Err(Error::NotFound(format!(
+            "user '{}' has no podman containers",
+            user.name
+        )));
+    };
+    process.query = query.to_string();
+    Ok(process)
+}
+
+fn list_user(user: &User, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+    let socket = user_socket(user, options);
+    if socket.exists() {
+        list_api(&socket, &options.proc_root)
+    } else if user.has_storage(options) {
+        list_docker_containers(
+            "podman",
+            &|args| user.run(options, "podman", args),
+            &options.proc_root,
+        )
+    } else {
+        Ok(vec![])
+    }
+}
+
+impl Container for Podman {
+    fn name(&self) -> &'static str {
+        "podman"
+    }
+    /// Accepts a container or pod name or ID, optionally prefixed with `user@` to search the
+    /// rootless containers of that user.
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        if let (Some(user), name) = rootless::split_user(container_id) {
+            return lookup_user(&User::by_name(user)?, name, container_id, options);
+        }
+        match lookup_system(container_id, options) {
+            Err(e @ Error::NotFound(_))
+            | Err(e @ Error::DaemonUnreachable(_))
+            | Err(e @ Error::ToolUnavailable(_)) => {
+                rootless::lookup_other_users(options, e, container_id, |user| {
+                    lookup_user(user, container_id, container_id, options)
+                })
+            }
+            res => res,
+        }
+    }
+    /// Lists the containers of the system service or CLI and of other users. Sockets and users
+    /// that fail are skipped as long as any of them could be listed.
+    fn list(&self, options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        let mut results = vec![];
+        let sockets = api_sockets(options);
+        if !sockets.is_empty() {
+            for socket in sockets {
+                results.push(list_api(&socket, &options.proc_root));
+            }
+        } else if cmd::which(options, "podman").is_some() {
+            results.push(list_docker_containers(
+                self.name(),
+                &|args| cmd::run(options, "podman", args),
+                &options.proc_root,
+            ));
+        } else {
+            // only the rootless containers of other users are left
+            self.check_required_tools(options)?;
+        }
+        let (users, mut user_errors) =
+            rootless::list_other_users(options, |user| list_user(user, options));
+
+        let mut answered = !users.is_empty();
+        let mut processes = vec![];
+        let mut errors = vec![];
+        for result in results {
+            match result {
+                Ok(mut found) => {
+                    answered = true;
+                    processes.append(&mut found);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        errors.append(&mut user_errors);
+        processes.extend(users);
+        match errors.into_iter().next() {
+            Some(e) if !answered => Err(e),
+            _ => Ok(processes),
+        }
     }
-    fn check_required_tools(&self) -> Result<()> {
-        if cmd::which("podman").is_some() {
+    fn check_required_tools(&self, options: &LookupOptions) -> Result<()> {
+        let rootless = || {
+            rootless::other_users(options)
+                .is_ok_and(|users| users.iter().any(|user| has_containers(user, options)))
+        };
+        if !api_sockets(options).is_empty() || cmd::which(options, "podman").is_some() || rootless()
+        {
             Ok(())
         } else {
-            bail!("podman runtime not found: 'podman' command is not available")
+            Err(Error::ToolUnavailable(String::from(
+                "podman runtime not found: no API socket and 'podman' command is not available",
+            )))
         }
     }
 }
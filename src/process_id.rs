@@ -1,40 +1,58 @@
-use anyhow::{bail, Context};
 use libc::pid_t;
-use std::env;
-use std::ffi::OsString;
 use std::fs;
 use std::io::ErrorKind;
-use std::path::PathBuf;
 
-use crate::result::Result;
-use crate::Container;
+use crate::result::{Error, Result};
+use crate::{Container, ContainerProcess, LookupOptions};
 
 #[derive(Clone, Debug)]
 pub(crate) struct ProcessId {}
 
-/// TODO make this configureable?
-fn get_path() -> PathBuf {
-    PathBuf::from(&env::var_os("CNTR_PROC").unwrap_or_else(|| OsString::from("/proc")))
-}
-
 impl Container for ProcessId {
-    fn lookup(&self, container_id: &str) -> Result<pid_t> {
-        let pid = container_id
-            .parse::<pid_t>()
-            .with_context(|| format!("'{}' is not a valid PID (process ID)", container_id))?;
+    fn name(&self) -> &'static str {
+        "process_id"
+    }
+    fn lookup(&self, container_id: &str, options: &LookupOptions) -> Result<ContainerProcess> {
+        let pid = container_id.parse::<pid_t>().map_err(|e| {
+            Error::ParseError(format!(
+                "'{}' is not a valid PID (process ID): {}",
+                container_id, e
+            ))
+        })?;
 
-        match fs::metadata(get_path().join(pid.to_string())) {
+        let proc_dir = options.proc_root.join(pid.to_string());
+        match fs::metadata(&proc_dir) {
             Err(e) => {
                 if e.kind() == ErrorKind::NotFound {
-                    bail!("no process with PID {} found", pid)
+                    Err(Error::NotFound(format!(
+                        "no process with PID {} found",
+                        pid
+                    )))
                 } else {
-                    Err(e).with_context(|| format!("failed to lookup process {}", pid))?
+                    Err(Error::io(format!("failed to lookup process {}", pid), e))
                 }
             }
-            Ok(_) => Ok(pid),
+            Ok(_) => {
+                // the process may exit in the meantime, so the name is best effort
+                let name = fs::read_to_string(proc_dir.join("comm"))
+                    .map(|comm| comm.trim_end().to_string())
+                    .unwrap_or_else(|_| pid.to_string());
+                Ok(ContainerProcess::new(
+                    self.name(),
+                    pid.to_string(),
+                    name,
+                    pid,
+                    container_id,
+                    &options.proc_root,
+                ))
+            }
         }
     }
-    fn check_required_tools(&self) -> Result<()> {
+    fn list(&self, _options: &LookupOptions) -> Result<Vec<ContainerProcess>> {
+        // PIDs are not containers, so there is nothing to enumerate
+        Ok(vec![])
+    }
+    fn check_required_tools(&self, _options: &LookupOptions) -> Result<()> {
         Ok(())
     }
 }
@@ -0,0 +1,297 @@
+//! Minimal protocol buffers encoding, enough to build and read the CRI messages.
+//!
+//! Messages are decoded into their raw fields, callers pick the field numbers they know and ignore
+//! the rest, as required for forward compatibility.
+
+use std::collections::HashMap;
+
+use crate::result::{Error, Result};
+
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LENGTH_DELIMITED: u8 = 2;
+const FIXED32: u8 = 5;
+
+/// Builds a message by appending fields.
+#[derive(Default)]
+pub(crate) struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    pub(crate) fn string(mut self, field: u32, value: &str) -> Encoder {
+        self.bytes(field, value.as_bytes());
+        self
+    }
+
+    pub(crate) fn bool(self, field: u32, value: bool) -> Encoder {
+        self.varint(field, value as u64)
+    }
+
+    /// An integer or enum field.
+    pub(crate) fn varint(mut self, field: u32, value: u64) -> Encoder {
+        self.key(field, VARINT);
+        self.write_varint(value);
+        self
+    }
+
+    pub(crate) fn message(mut self, field: u32, message: Encoder) -> Encoder {
+        self.bytes(field, &message.buf);
+        self
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.write_varint(u64::from(field) << 3 | u64::from(wire_type));
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.key(field, LENGTH_DELIMITED);
+        self.write_varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// The fields of a decoded message.
+#[derive(Debug)]
+pub(crate) struct Message<'a> {
+    fields: Vec<(u32, Field<'a>)>,
+}
+
+fn invalid() -> Error {
+    Error::ParseError(String::from("invalid protobuf message"))
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or_else(invalid)?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid())
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(invalid());
+    }
+    let (head, rest) = data.split_at(len);
+    *data = rest;
+    Ok(head)
+}
+
+impl<'a> Message<'a> {
+    pub(crate) fn decode(mut data: &'a [u8]) -> Result<Message<'a>> {
+        let mut fields = vec![];
+        while !data.is_empty() {
+            let key = read_varint(&mut data)?;
+            let number = (key >> 3) as u32;
+            let field = match (key & 7) as u8 {
+                VARINT => Field::Varint(read_varint(&mut data)?),
+                FIXED64 => {
+                    take(&mut data, 8)?;
+                    Field::Fixed
+                }
+                LENGTH_DELIMITED => {
+                    let len = read_varint(&mut data)? as usize;
+                    Field::Bytes(take(&mut data, len)?)
+                }
+                FIXED32 => {
+                    take(&mut data, 4)?;
+                    Field::Fixed
+                }
+                _ => return Err(invalid()),
+            };
+            fields.push((number, field));
+        }
+        Ok(Message { fields })
+    }
+
+    /// The last occurrence of a scalar field wins.
+    fn last(&self, number: u32) -> Option<Field<'a>> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == number)
+            .map(|(_, f)| *f)
+    }
+
+    fn all_bytes(&self, number: u32) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.fields.iter().filter_map(move |(n, f)| match f {
+            Field::Bytes(b) if *n == number => Some(*b),
+            _ => None,
+        })
+    }
+
+    /// A string field, empty if it is not set.
+    pub(crate) fn string(&self, number: u32) -> Result<String> {
+        match self.last(number) {
+            Some(Field::Bytes(b)) => String::from_utf8(b.to_vec()).map_err(|_| invalid()),
+            Some(_) => Err(invalid()),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// An integer, bool or enum field, zero if it is not set.
+    pub(crate) fn varint(&self, number: u32) -> Result<u64> {
+        match self.last(number) {
+            Some(Field::Varint(v)) => Ok(v),
+            Some(_) => Err(invalid()),
+            None => Ok(0),
+        }
+    }
+
+    /// An embedded message, empty if it is not set.
+    pub(crate) fn message(&self, number: u32) -> Result<Message<'a>> {
+        match self.last(number) {
+            Some(Field::Bytes(b)) => Message::decode(b),
+            Some(_) => Err(invalid()),
+            None => Ok(Message { fields: vec![] }),
+        }
+    }
+
+    /// All elements of a repeated message field.
+    pub(crate) fn messages(&self, number: u32) -> Result<Vec<Message<'a>>> {
+        self.all_bytes(number).map(Message::decode).collect()
+    }
+
+    /// A `map<string, string>` field.
+    pub(crate) fn string_map(&self, number: u32) -> Result<HashMap<String, String>> {
+        let mut map = HashMap::new();
+        for entry in self.messages(number)? {
+            map.insert(entry.string(1)?, entry.string(2)?);
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let labels = [("io.kubernetes.pod.name", "web"), ("tier", "")];
+        let mut metadata = Encoder::new().string(1, "nginx").varint(2, 3);
+        for (key, value) in &labels {
+            metadata = metadata.message(4, Encoder::new().string(1, key).string(2, value));
+        }
+        let encoded = Encoder::new()
+            .string(1, "3f9c2d7e")
+            .message(2, metadata)
+            .varint(3, u64::MAX)
+            .bool(4, true)
+            .message(5, Encoder::new().string(1, "a"))
+            .message(5, Encoder::new().string(1, "b"))
+            .finish();
+
+        let message = Message::decode(&encoded).unwrap();
+        assert_eq!(message.string(1).unwrap(), "3f9c2d7e");
+        assert_eq!(message.varint(3).unwrap(), u64::MAX);
+        assert_eq!(message.varint(4).unwrap(), 1);
+        let metadata = message.message(2).unwrap();
+        assert_eq!(metadata.string(1).unwrap(), "nginx");
+        assert_eq!(metadata.varint(2).unwrap(), 3);
+        let map = metadata.string_map(4).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["io.kubernetes.pod.name"], "web");
+        assert_eq!(map["tier"], "");
+        let repeated: Vec<String> = message
+            .messages(5)
+            .unwrap()
+            .iter()
+            .map(|m| m.string(1).unwrap())
+            .collect();
+        assert_eq!(repeated, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn unset_fields_have_defaults() {
+        let message = Message::decode(&[]).unwrap();
+        assert_eq!(message.string(1).unwrap(), "");
+        assert_eq!(message.varint(2).unwrap(), 0);
+        assert!(message
+            .message(3)
+            .unwrap()
+            .string_map(1)
+            .unwrap()
+            .is_empty());
+        assert!(message.messages(4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn last_scalar_wins() {
+        let encoded = Encoder::new().varint(1, 1).varint(1, 2).finish();
+        assert_eq!(Message::decode(&encoded).unwrap().varint(1).unwrap(), 2);
+    }
+
+    #[test]
+    fn skips_unknown_fields() {
+        // field 2 as fixed64, field 3 as fixed32, then field 1 as a string
+        let mut encoded = vec![0x11, 1, 2, 3, 4, 5, 6, 7, 8, 0x1d, 1, 2, 3, 4];
+        encoded.extend_from_slice(&Encoder::new().string(1, "ok").finish());
+        assert_eq!(Message::decode(&encoded).unwrap().string(1).unwrap(), "ok");
+    }
+
+    #[test]
+    fn invalid_messages() {
+        for data in &[
+            // varint without its last byte
+            &[0x08, 0x80][..],
+            // varint longer than 64 bits
+            &[
+                0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+            ],
+            // length beyond the end of the message
+            &[0x0a, 0x05, b'a'],
+            // truncated fixed32
+            &[0x0d, 1, 2],
+            // group wire types are not supported
+            &[0x0b],
+        ] {
+            let res = Message::decode(data);
+            assert!(
+                matches!(res, Err(Error::ParseError(_))),
+                "{:?}: {:?}",
+                data,
+                res
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_wire_type() {
+        let message = Encoder::new().varint(1, 1).string(2, "a").finish();
+        let message = Message::decode(&message).unwrap();
+        assert!(message.string(1).is_err());
+        assert!(message.varint(2).is_err());
+        // the string is no valid message
+        assert!(message.message(2).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Serves recorded command output instead of running the tools, so the parsing of the backends
+//! can be exercised without the runtimes installed.
+//!
+//! A recording is a JSON file with the command line, the exit status and what the tool printed:
+//!
+//! ```json
+//! {
+//!   "command": ["lxc-info", "--no-humanize", "--pid", "--name", "web"],
+//!   "status": 0,
+//!   "stdout": "1234\n",
+//!   "stderr": ""
+//! }
+//! ```
+
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+
+use crate::cmd::CommandRunner;
+use crate::result::{Error, Result};
+
+#[derive(Clone, Debug)]
+struct Recording {
+    command: Vec<String>,
+    status: i32,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+/// Replays recorded invocations of command line tools.
+///
+/// Only commands that were recorded with exactly the same arguments can be run. A program counts
+/// as installed if at least one of its invocations was recorded.
+#[derive(Clone, Debug, Default)]
+pub struct ReplayRunner {
+    recordings: Vec<Recording>,
+}
+
+impl ReplayRunner {
+    pub fn new() -> ReplayRunner {
+        ReplayRunner::default()
+    }
+
+    /// Loads all `*.json` recordings from `dir`.
+    pub fn load(dir: impl AsRef<Path>) -> Result<ReplayRunner> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir)
+            .map_err(|e| Error::io(format!("failed to read '{}'", dir.display()), e))?;
+        let mut paths = vec![];
+        for entry in entries {
+            let entry =
+                entry.map_err(|e| Error::io(format!("failed to read '{}'", dir.display()), e))?;
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut runner = ReplayRunner::new();
+        for path in paths {
+            runner.load_file(&path)?;
+        }
+        Ok(runner)
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<()> {
+        let content = fs::read(path)
+            .map_err(|e| Error::io(format!("failed to read '{}'", path.display()), e))?;
+        let json: Value = serde_json::from_slice(&content).map_err(|e| {
+            Error::ParseError(format!("invalid JSON in '{}': {}", path.display(), e))
+        })?;
+        let invalid = |what: &str| {
+            Error::ParseError(format!(
+                "recording '{}' has no valid {}",
+                path.display(),
+                what
+            ))
+        };
+        let command = json["command"]
+            .as_array()
+            .and_then(|args| args.iter().map(|a| a.as_str()).collect::<Option<Vec<_>>>())
+            .filter(|args| !args.is_empty())
+            .ok_or_else(|| invalid("'command'"))?;
+        let status = match &json["status"] {
+            Value::Null => 0,
+            status => status.as_i64().ok_or_else(|| invalid("'status'"))? as i32,
+        };
+        let text = |key: &str| match &json[key] {
+            Value::Null => Ok(""),
+            value => value.as_str().ok_or_else(|| invalid(&format!("'{}'", key))),
+        };
+        self.record(&command, status, text("stdout")?, text("stderr")?);
+        Ok(())
+    }
+
+    /// Records that `command` exits with `status` after printing `stdout` and `stderr`.
+    pub fn record(
+        &mut self,
+        command: &[&str],
+        status: i32,
+        stdout: impl Into<Vec<u8>>,
+        stderr: impl Into<Vec<u8>>,
+    ) -> &mut ReplayRunner {
+        self.recordings.push(Recording {
+            command: command.iter().map(|arg| arg.to_string()).collect(),
+            status,
+            stdout: stdout.into(),
+            stderr: stderr.into(),
+        });
+        self
+    }
+
+    fn knows(&self, program: &str) -> bool {
+        self.recordings.iter().any(|r| r.command[0] == program)
+    }
+}
+
+impl CommandRunner for ReplayRunner {
+    fn output(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        let recording = self.recordings.iter().find(|r| {
+            r.command[0] == program
+                && r.command.len() == args.len() + 1
+                && r.command[1..].iter().zip(args).all(|(a, b)| a == b)
+        });
+        match recording {
+            Some(recording) => Ok(Output {
+                // wait status as returned by waitpid(2)
+                status: ExitStatus::from_raw((recording.status & 0xff) << 8),
+                stdout: recording.stdout.clone(),
+                stderr: recording.stderr.clone(),
+            }),
+            None if self.knows(program) => Err(io::Error::other(format!(
+                "no recording of '{} {}'",
+                program,
+                args.join(" ")
+            ))),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn which(&self, program: &str) -> Option<PathBuf> {
+        if self.knows(program) {
+            Some(Path::new("/usr/bin").join(program))
+        } else {
+            None
+        }
+    }
+}
@@ -1 +1,155 @@
-pub(crate) type Result<T> = anyhow::Result<T>;
+use std::error;
+use std::fmt;
+use std::io;
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+/// Reasons why a container runtime failed to resolve a container.
+///
+/// More kinds may be added as new runtimes are supported.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The runtime does not know a container with this name.
+    NotFound(String),
+    /// The container exists, but it has no running process.
+    NotRunning(String),
+    /// The command line tool required by the runtime is not installed.
+    ToolUnavailable(String),
+    /// The tool is installed, but its daemon could not be reached.
+    DaemonUnreachable(String),
+    /// Insufficient privileges to query the runtime or to inspect the process.
+    PermissionDenied(String),
+    /// The name matches more than one container.
+    Ambiguous {
+        message: String,
+        candidates: Vec<String>,
+    },
+    /// The runtime returned output that could not be understood.
+    ParseError(String),
+    /// The name refers to something that has no container process, i.e. a virtual machine.
+    Unsupported(String),
+    /// A runtime command failed for a reason that could not be classified.
+    CommandFailed(String),
+    /// Reading runtime or process state failed.
+    Io { message: String, source: io::Error },
+}
+
+impl Error {
+    /// Wraps an I/O error, permission errors are reported as `PermissionDenied`.
+    pub(crate) fn io(message: impl Into<String>, source: io::Error) -> Error {
+        let message = message.into();
+        if source.kind() == io::ErrorKind::PermissionDenied {
+            Error::PermissionDenied(format!("{}: {}", message, source))
+        } else {
+            Error::Io { message, source }
+        }
+    }
+
+    /// Prefixes the message with `context` while keeping the kind of the error.
+    pub(crate) fn context(self, context: impl fmt::Display) -> Error {
+        let prefix = |msg: String| format!("{}: {}", context, msg);
+        match self {
+            Error::NotFound(msg) => Error::NotFound(prefix(msg)),
+            Error::NotRunning(msg) => Error::NotRunning(prefix(msg)),
+            Error::ToolUnavailable(msg) => Error::ToolUnavailable(prefix(msg)),
+            Error::DaemonUnreachable(msg) => Error::DaemonUnreachable(prefix(msg)),
+            Error::PermissionDenied(msg) => Error::PermissionDenied(prefix(msg)),
+            Error::Ambiguous {
+                message,
+                candidates,
+            } => Error::Ambiguous {
+                message: prefix(message),
+                candidates,
+            },
+            Error::ParseError(msg) => Error::ParseError(prefix(msg)),
+            Error::Unsupported(msg) => Error::Unsupported(prefix(msg)),
+            Error::CommandFailed(msg) => Error::CommandFailed(prefix(msg)),
+            Error::Io { message, source } => Error::Io {
+                message: prefix(message),
+                source,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(msg)
+            | Error::NotRunning(msg)
+            | Error::ToolUnavailable(msg)
+            | Error::DaemonUnreachable(msg)
+            | Error::PermissionDenied(msg)
+            | Error::ParseError(msg)
+            | Error::Unsupported(msg)
+            | Error::CommandFailed(msg) => write!(f, "{}", msg),
+            Error::Ambiguous {
+                message,
+                candidates,
+            } => {
+                write!(f, "{}", message)?;
+                for candidate in candidates {
+                    write!(f, "\n    - {}", candidate)?;
+                }
+                Ok(())
+            }
+            Error::Io { message, source } => write!(f, "{}: {}", message, source),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// What the caller asked for when a `LookupError` was returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+    /// `lookup_container_pid` or `lookup_container_pidfd` with the user supplied query.
+    Lookup { query: String },
+    /// `list_containers`.
+    List,
+}
+
+/// Returned by `lookup_container_pid` if none of the runtimes could resolve the container, and
+/// by `list_containers` if none of the runtimes could be queried.
+#[derive(Debug)]
+pub struct LookupError {
+    /// The lookup or listing that failed.
+    pub operation: Operation,
+    /// The error of every runtime that was tried, in the order they were tried.
+    pub errors: Vec<(&'static str, Error)>,
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.operation {
+            Operation::List => write!(f, "failed to list containers")?,
+            Operation::Lookup { query } if self.errors.is_empty() => {
+                return write!(
+                    f,
+                    "failed to find container '{}' - no container runtime is available",
+                    query
+                );
+            }
+            Operation::Lookup { query } => write!(
+                f,
+                "failed to find container '{}' - tried the following runtimes:",
+                query
+            )?,
+        }
+        for (runtime, err) in &self.errors {
+            write!(f, "\n  - {}: {}", runtime, err)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for LookupError {}
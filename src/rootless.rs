@@ -0,0 +1,338 @@
+//! Rootless podman and docker keep the containers of every user separate from the system ones.
+//! When running as root the backends also search the runtimes of other users, and `user@name`
+//! selects the user explicitly.
+
+use libc::{c_char, uid_t};
+use std::ffi::{CStr, CString, OsStr};
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr;
+
+use crate::cmd;
+use crate::result::{Error, Result};
+use crate::{ContainerProcess, LookupOptions};
+
+/// Parent of the `$XDG_RUNTIME_DIR` of user sessions.
+const RUNTIME_DIRS: &str = "/run/user";
+
+/// Container storage of rootless podman, relative to the home directory.
+const STORAGE_DIR: &str = ".local/share/containers";
+
+/// Local user database, read as a file instead of through NSS.
+const PASSWD: &str = "/etc/passwd";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct User {
+    pub(crate) name: String,
+    pub(crate) uid: uid_t,
+    pub(crate) home: PathBuf,
+}
+
+fn from_passwd(pw: &libc::passwd) -> User {
+    let field = |ptr: *const c_char| {
+        if ptr.is_null() {
+            &[][..]
+        } else {
+            unsafe { CStr::from_ptr(ptr) }.to_bytes()
+        }
+    };
+    User {
+        name: String::from_utf8_lossy(field(pw.pw_name)).into_owned(),
+        uid: pw.pw_uid,
+        home: PathBuf::from(OsStr::from_bytes(field(pw.pw_dir))),
+    }
+}
+
+/// Looks up a passwd entry with `getpwnam_r` or `getpwuid_r`.
+fn getpw(
+    lookup: impl Fn(*mut libc::passwd, *mut c_char, usize, *mut *mut libc::passwd) -> i32,
+) -> io::Result<Option<User>> {
+    let mut buf = vec![0 as c_char; 1024];
+    loop {
+        let mut pw: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let res = lookup(&mut pw, buf.as_mut_ptr(), buf.len(), &mut result);
+        if res == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if res != 0 {
+            return Err(io::Error::from_raw_os_error(res));
+        }
+        return Ok((!result.is_null()).then(|| from_passwd(&pw)));
+    }
+}
+
+impl User {
+    /// Finds a user by name or numeric UID.
+    pub(crate) fn by_name(name: &str) -> Result<User> {
+        let user = match name.parse::<uid_t>() {
+            Ok(uid) => User::by_uid(uid),
+            Err(_) => {
+                let cname = CString::new(name)
+                    .map_err(|_| Error::NotFound(format!("invalid user name '{}'", name)))?;
+                getpw(|pw, buf, len, result| unsafe {
+                    libc::getpwnam_r(cname.as_ptr(), pw, buf, len, result)
+                })
+            }
+        };
+        user.map_err(|e| Error::io(format!("failed to look up user '{}'", name), e))?
+            .ok_or_else(|| Error::NotFound(format!("no such user '{}'", name)))
+    }
+
+    fn by_uid(uid: uid_t) -> io::Result<Option<User>> {
+        getpw(|pw, buf, len, result| unsafe { libc::getpwuid_r(uid, pw, buf, len, result) })
+    }
+
+    /// `$XDG_RUNTIME_DIR` of the user's session, where the rootless sockets are.
+    pub(crate) fn runtime_dir(&self, options: &LookupOptions) -> PathBuf {
+        options.state_path(RUNTIME_DIRS).join(self.uid.to_string())
+    }
+
+    /// Whether rootless podman has stored containers for this user.
+    pub(crate) fn has_storage(&self, options: &LookupOptions) -> bool {
+        options.state_path(&self.home).join(STORAGE_DIR).is_dir()
+    }
+
+    /// Runs `program` as this user with the environment rootless runtimes expect.
+    pub(crate) fn run(
+        &self,
+        options: &LookupOptions,
+        program: &str,
+        args: &[&str],
+    ) -> Result<Vec<u8>> {
+        if unsafe { libc::geteuid() } == self.uid {
+            return cmd::run(options, program, args);
+        }
+        let runtime_dir = format!("XDG_RUNTIME_DIR={}", self.runtime_dir(options).display());
+        let home = format!("HOME={}", self.home.display());
+        let mut runuser_args = vec!["-u", &self.name, "--", "env", &home];
+        // rootless podman falls back to a directory in /tmp without a session
+        if self.runtime_dir(options).is_dir() {
+            runuser_args.push(&runtime_dir);
+        }
+        runuser_args.push(program);
+        runuser_args.extend_from_slice(args);
+        cmd::run(options, "runuser", &runuser_args)
+    }
+}
+
+/// `$XDG_RUNTIME_DIR` of the current session below the state root, where the rootless runtimes
+/// of the current user keep their sockets and state.
+pub(crate) fn session_runtime_dir(options: &LookupOptions) -> Option<PathBuf> {
+    options
+        .runtime_dir
+        .as_ref()
+        .map(|dir| options.state_path(dir))
+}
+
+/// Splits `user@name` into the user and the container name, container names never contain `@`.
+pub(crate) fn split_user(query: &str) -> (Option<&str>, &str) {
+    match query.split_once('@') {
+        Some((user, name)) if !user.is_empty() => (Some(user), name),
+        _ => (None, query),
+    }
+}
+
+/// Users other than root whose rootless runtimes may have containers: those with a session
+/// runtime directory, where the runtimes keep sockets and state, and the local users with container
+/// storage in their home, who may have containers without a session. Only the UIDs of the runtime
+/// directories are resolved through NSS, enumerating the passwd database that way may mean a
+/// round trip to LDAP for every user. Empty unless running as root, other users' runtimes are not
+/// accessible otherwise.
+pub(crate) fn other_users(options: &LookupOptions) -> Result<Vec<User>> {
+    if unsafe { libc::geteuid() } != 0 {
+        return Ok(vec![]);
+    }
+    let mut users = users_with_storage(options)?;
+    for uid in session_uids(options)? {
+        if users.iter().any(|user| user.uid == uid) {
+            continue;
+        }
+        // the directory of a deleted user may still exist
+        if let Ok(Some(user)) = User::by_uid(uid) {
+            users.push(user);
+        }
+    }
+    users.sort_by_key(|user| user.uid);
+    Ok(users)
+}
+
+/// UIDs other than root with a directory below `/run/user`.
+fn session_uids(options: &LookupOptions) -> Result<Vec<uid_t>> {
+    let runtime_dirs = options.state_path(RUNTIME_DIRS);
+    let entries = match runtime_dirs.read_dir() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => {
+            return Err(Error::io(
+                format!("failed to read '{}'", runtime_dirs.display()),
+                e,
+            ))
+        }
+    };
+    let mut uids = vec![];
+    for entry in entries {
+        let entry = entry
+            .map_err(|e| Error::io(format!("failed to read '{}'", runtime_dirs.display()), e))?;
+        match entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<uid_t>().ok())
+        {
+            Some(uid) if uid != 0 => uids.push(uid),
+            _ => {}
+        }
+    }
+    Ok(uids)
+}
+
+/// Users of `/etc/passwd` other than root with rootless container storage in their home.
+fn users_with_storage(options: &LookupOptions) -> Result<Vec<User>> {
+    let passwd = options.state_path(PASSWD);
+    let content = match fs::read(&passwd) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => {
+            return Err(Error::io(
+                format!("failed to read '{}'", passwd.display()),
+                e,
+            ))
+        }
+    };
+    Ok(parse_passwd(&content)
+        .into_iter()
+        .filter(|user| user.uid != 0 && user.has_storage(options))
+        .collect())
+}
+
+/// Parses the `name:password:uid:gid:gecos:home:shell` lines of a passwd file, skipping the ones
+/// that are malformed or refer to NIS (`+` and `-` entries).
+fn parse_passwd(content: &[u8]) -> Vec<User> {
+    content
+        .split(|&b| b == b'\n')
+        .filter_map(|line| {
+            let fields: Vec<&[u8]> = line.split(|&b| b == b':').collect();
+            match fields.as_slice() {
+                [name, _, uid, _, _, home, ..]
+                    if !name.is_empty() && !name.starts_with(b"+") && !name.starts_with(b"-") =>
+                {
+                    Some(User {
+                        name: String::from_utf8_lossy(name).into_owned(),
+                        uid: std::str::from_utf8(uid).ok()?.parse().ok()?,
+                        home: PathBuf::from(OsStr::from_bytes(home)),
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Errors that mean the user has no such container or no runtime running.
+fn is_missing(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::NotFound(_) | Error::DaemonUnreachable(_) | Error::ToolUnavailable(_)
+    )
+}
+
+/// Resolves `name` in the rootless runtimes of other users, after the system runtime failed
+/// with `err`. Fails as ambiguous if several users have a matching container.
+pub(crate) fn lookup_other_users(
+    options: &LookupOptions,
+    err: Error,
+    name: &str,
+    lookup: impl Fn(&User) -> Result<ContainerProcess>,
+) -> Result<ContainerProcess> {
+    let mut found = vec![];
+    let mut not_running = None;
+    for user in other_users(options)? {
+        match lookup(&user) {
+            Ok(process) => found.push((user, process)),
+            Err(e @ Error::NotRunning(_)) => {
+                not_running.get_or_insert(e);
+            }
+            Err(e) if is_missing(&e) => {}
+            Err(e) => return Err(e.context(format!("user '{}'", user.name))),
+        }
+    }
+
+    if found.len() > 1 {
+        return Err(Error::Ambiguous {
+            message: format!("containers of several users match '{}'", name),
+            candidates: found
+                .iter()
+                .map(|(user, process)| format!("{}@{}", user.name, process.name))
+                .collect(),
+        });
+    }
+    match found.pop() {
+        Some((_, process)) => Ok(process),
+        None => Err(not_running.unwrap_or(err)),
+    }
+}
+
+/// Lists the containers of other users' rootless runtimes, with `user@` prefixed queries.
+///
+/// Users whose runtime fails are skipped, their errors are returned alongside the containers of
+/// the other users.
+pub(crate) fn list_other_users(
+    options: &LookupOptions,
+    list: impl Fn(&User) -> Result<Vec<ContainerProcess>>,
+) -> (Vec<ContainerProcess>, Vec<Error>) {
+    let users = match other_users(options) {
+        Ok(users) => users,
+        Err(e) => return (vec![], vec![e]),
+    };
+    let mut processes = vec![];
+    let mut errors = vec![];
+    for user in users {
+        match list(&user) {
+            Ok(user_processes) => {
+                processes.extend(user_processes.into_iter().map(|mut process| {
+                    process.query = format!("{}@{}", user.name, process.query);
+                    process
+                }))
+            }
+            Err(e) if is_missing(&e) => {}
+            Err(e) => {
+                errors.push(e.context(format!("failed to list containers of user '{}'", user.name)))
+            }
+        }
+    }
+    (processes, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwd_entries() {
+        let passwd = b"root:x:0:0:root:/root:/bin/bash\n\
+            alice:x:1000:1000:Alice,,,:/home/alice:/bin/bash\n\
+            broken:x:none:0::/:/bin/false\n\
+            +@netgroup::::::\n\
+            \n";
+        let users = parse_passwd(passwd);
+        assert_eq!(
+            users,
+            vec![
+                User {
+                    name: String::from("root"),
+                    uid: 0,
+                    home: PathBuf::from("/root"),
+                },
+                User {
+                    name: String::from("alice"),
+                    uid: 1000,
+                    home: PathBuf::from("/home/alice"),
+                },
+            ]
+        );
+    }
+}
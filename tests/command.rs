@@ -0,0 +1,65 @@
+mod common;
+
+use common::{fixture, list, lookup, replay};
+use container_pid::{lookup_container_type, Error, LookupOptions};
+
+fn options() -> LookupOptions {
+    LookupOptions {
+        proc_root: fixture("command/proc"),
+        ..replay("command")
+    }
+}
+
+#[test]
+fn lookup_single_match() {
+    let command = lookup_container_type("command").unwrap();
+    let found = lookup(&*command, "/etc/my-app.conf", &options()).unwrap();
+    assert_eq!(
+        found,
+        (300, String::from("supervisord -c /etc/my-app.conf"))
+    );
+}
+
+#[test]
+fn lookup_prefers_topmost_process() {
+    let command = lookup_container_type("command").unwrap();
+    let found = lookup(&*command, "--id 1", &options()).unwrap();
+    assert_eq!(found, (301, String::from("my-app-worker --id 1")));
+}
+
+#[test]
+fn lookup_unrelated_matches() {
+    let command = lookup_container_type("command").unwrap();
+    match lookup(&*command, "my-app", &options()) {
+        Err(Error::Ambiguous { candidates, .. }) => assert_eq!(
+            candidates,
+            vec![
+                "supervisord -c /etc/my-app.conf (PID 300)",
+                "less /var/log/my-app.log (PID 400)",
+            ]
+        ),
+        res => panic!("expected ambiguous match: {:?}", res),
+    }
+}
+
+#[test]
+fn lookup_no_match() {
+    let command = lookup_container_type("command").unwrap();
+    let res = lookup(&*command, "nginx", &options());
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+#[test]
+fn lookup_empty_query() {
+    let command = lookup_container_type("command").unwrap();
+    for query in &["", " ", "\t\n"] {
+        let res = lookup(&*command, query, &options());
+        assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+    }
+}
+
+#[test]
+fn list_nothing() {
+    let command = lookup_container_type("command").unwrap();
+    assert_eq!(list(&*command, &options()), vec![]);
+}
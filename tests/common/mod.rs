@@ -0,0 +1,102 @@
+// every test crate includes this module but only uses some of the helpers
+#![allow(dead_code)]
+
+use container_pid::{Container, ContainerProcess, Error, LookupOptions, ReplayRunner};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Once};
+
+/// Variables that would point the backends at the daemons and files of the machine running the
+/// tests.
+const HOST_VARIABLES: &[&str] = &[
+    "CNTR_OCI_ROOT",
+    "CNTR_PROC",
+    "CONTAINERD_NAMESPACE",
+    "CONTAINER_HOST",
+    "CONTAINER_RUNTIME_ENDPOINT",
+    "DBUS_SYSTEM_BUS_ADDRESS",
+    "DOCKER_HOST",
+    "INCUS_DIR",
+    "KUBERNETES_SERVICE_HOST",
+    "LXD_DIR",
+    "NODE_NAME",
+    "XDG_RUNTIME_DIR",
+];
+
+pub fn fixture(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(path)
+}
+
+/// The recordings in `tests/fixtures/<tool>` that were made with a release of the tool, i.e. the
+/// directories named by its version.
+pub fn versions(tool: &str) -> Vec<String> {
+    let mut versions: Vec<String> = std::fs::read_dir(fixture(tool))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(|c: char| c.is_ascii_digit()))
+        .collect();
+    assert!(!versions.is_empty(), "no recordings of {}", tool);
+    versions.sort();
+    versions
+}
+
+/// Removes the variables that point at the host running the tests, once per test binary.
+fn isolate() {
+    static ISOLATE: Once = Once::new();
+    ISOLATE.call_once(|| {
+        for var in HOST_VARIABLES {
+            env::remove_var(var);
+        }
+        // an empty list of kubeconfig files, ~/.kube/config is not read
+        env::set_var("KUBECONFIG", "");
+    });
+}
+
+/// Options that replay the recordings in `tests/fixtures/<recordings>` and don't see any
+/// socket, state directory or process of this machine.
+pub fn replay(recordings: &str) -> LookupOptions {
+    LookupOptions {
+        runner: Arc::new(ReplayRunner::load(fixture(recordings)).unwrap()),
+        ..host("no-such-host")
+    }
+}
+
+/// Options that read the state directories, cgroups and processes of the host tree in
+/// `tests/fixtures/<root>` and have no command line tools installed.
+pub fn host(root: &str) -> LookupOptions {
+    isolate();
+    let host = fixture(root);
+    LookupOptions {
+        proc_root: host.join("proc"),
+        cgroup_root: host.join("sys/fs/cgroup"),
+        state_root: host,
+        runtime_dir: None,
+        oci_roots: vec![],
+        runner: Arc::new(ReplayRunner::new()),
+    }
+}
+
+/// Looks up `query` with `container` and returns the PID and name of the container.
+pub fn lookup(
+    container: &dyn Container,
+    query: &str,
+    options: &LookupOptions,
+) -> Result<(i32, String), Error> {
+    container
+        .lookup(query, options)
+        .map(|process| (process.pid, process.name))
+}
+
+/// The listed containers as `(query, pid)`, sorted.
+pub fn list(container: &dyn Container, options: &LookupOptions) -> Vec<(String, i32)> {
+    let mut processes: Vec<(String, i32)> = container
+        .list(options)
+        .unwrap()
+        .into_iter()
+        .map(|ContainerProcess { query, pid, .. }| (query, pid))
+        .collect();
+    processes.sort();
+    processes
+}
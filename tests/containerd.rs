@@ -0,0 +1,110 @@
+mod common;
+
+use common::{host, list, lookup, replay};
+use container_pid::{lookup_container_type, Error};
+
+/// Hand-written `ctr` output of a host with tasks in the `default` and `k8s.io` namespaces.
+const RECORDINGS: &str = "containerd/ctr";
+
+const K8S_TASK: &str = "5c5f4ff58d8e38112f93e5fc91c7a1ec4e51f4f5847b5166e57ebbe958438a38";
+
+#[test]
+fn lookup_task() {
+    let containerd = lookup_container_type("containerd").unwrap();
+    let options = replay(RECORDINGS);
+    let found = lookup(&*containerd, "default/redis", &options).unwrap();
+    assert_eq!(found, (7712, String::from("redis")));
+    // searched in all namespaces
+    let found = lookup(&*containerd, K8S_TASK, &options).unwrap();
+    assert_eq!(found.0, 8101);
+}
+
+#[test]
+fn lookup_task_in_several_namespaces() {
+    let containerd = lookup_container_type("containerd").unwrap();
+    let options = replay(RECORDINGS);
+    match lookup(&*containerd, "redis", &options) {
+        Err(Error::Ambiguous { candidates, .. }) => {
+            assert_eq!(candidates, vec!["default/redis", "k8s.io/redis"])
+        }
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn lookup_stopped_and_missing_tasks() {
+    let containerd = lookup_container_type("containerd").unwrap();
+    let options = replay(RECORDINGS);
+    let res = lookup(&*containerd, "backup", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+    let res = lookup(&*containerd, "nope", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+#[test]
+fn list_tasks() {
+    let containerd = lookup_container_type("containerd").unwrap();
+    let options = replay(RECORDINGS);
+    assert_eq!(
+        list(&*containerd, &options),
+        vec![
+            (String::from("default/redis"), 7712),
+            (format!("k8s.io/{}", K8S_TASK), 8101),
+            (String::from("k8s.io/redis"), 8344),
+        ]
+    );
+}
+
+#[test]
+fn daemon_not_running() {
+    let containerd = lookup_container_type("containerd").unwrap();
+    // hand-written as well, `ctr` on a host where containerd is stopped
+    let options = replay("containerd/down");
+    let res = lookup(&*containerd, "redis", &options);
+    assert!(matches!(res, Err(Error::DaemonUnreachable(_))), "{:?}", res);
+}
+
+const HOST: &str = "containerd/host";
+const HOST_K8S_TASK: &str = "3f5b84cd1eae1a90a1fc742161cfc9b0f3de05fc7979e7657def633e200c0789";
+
+#[test]
+fn lookup_task_state() {
+    let containerd = lookup_container_type("containerd").unwrap();
+    let options = host(HOST);
+    containerd.check_required_tools(&options).unwrap();
+    let found = lookup(&*containerd, "default/web", &options).unwrap();
+    assert_eq!(found, (4242, String::from("web")));
+    // the namespaces are read from the state directory
+    let found = lookup(&*containerd, HOST_K8S_TASK, &options).unwrap();
+    assert_eq!(found, (4444, String::from("app")));
+}
+
+#[test]
+fn lookup_task_state_not_running() {
+    let containerd = lookup_container_type("containerd").unwrap();
+    let options = host(HOST);
+    // PID reused by a process in another cgroup
+    let res = lookup(&*containerd, "default/stale", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+    // created, but the shim has not written init.pid
+    let res = lookup(&*containerd, "created", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+    let res = lookup(&*containerd, "default/nope", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+    // the task ID must not escape the state directory
+    let res = lookup(&*containerd, "default/..", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+#[test]
+fn list_task_state() {
+    let containerd = lookup_container_type("containerd").unwrap();
+    let options = host(HOST);
+    assert_eq!(
+        list(&*containerd, &options),
+        vec![
+            (String::from("default/web"), 4242),
+            (format!("k8s.io/{}", HOST_K8S_TASK), 4444),
+        ]
+    );
+}
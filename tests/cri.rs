@@ -0,0 +1,146 @@
+// crictl is only run without the native client
+#![cfg(not(feature = "cri-grpc"))]
+
+mod common;
+
+use common::{fixture, host, list, lookup};
+use container_pid::{lookup_container_type, Error, LookupOptions, ReplayRunner};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::sync::Arc;
+
+const NGINX: &str = "5e0c5c3a2f8b9d1e4f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1d2e3f4a5b6c7d8e";
+const STAGING_NGINX: &str = "2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d3e";
+const SANDBOX: &str = "8f1c2d3e4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1d";
+
+/// Hand-written `crictl` output of the same pods on containerd and on CRI-O, which differ in the
+/// verbose info of the status.
+const RUNTIMES: &[&str] = &["containerd", "cri-o"];
+
+/// Replays the crictl output of `runtime`. The runtime socket it refers to is created in a fresh
+/// state root, and the `--runtime-endpoint` is pointed at it.
+fn crictl(runtime: &str) -> LookupOptions {
+    let root = env::temp_dir().join(format!(
+        "container-pid-crictl-{}-{}",
+        std::process::id(),
+        runtime
+    ));
+    let mut runner = ReplayRunner::new();
+    for entry in fs::read_dir(fixture(&format!("crictl/{}", runtime))).unwrap() {
+        let recording: Value =
+            serde_json::from_slice(&fs::read(entry.unwrap().path()).unwrap()).unwrap();
+        let mut command: Vec<String> = recording["command"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|arg| arg.as_str().unwrap().to_string())
+            .collect();
+        let socket = root.join(command[2].trim_start_matches("unix:///"));
+        fs::create_dir_all(socket.parent().unwrap()).unwrap();
+        fs::write(&socket, "").unwrap();
+        command[2] = format!("unix://{}", socket.display());
+
+        let command: Vec<&str> = command.iter().map(String::as_str).collect();
+        let text = |key: &str| recording[key].as_str().unwrap().to_string();
+        runner.record(
+            &command,
+            recording["status"].as_i64().unwrap() as i32,
+            text("stdout"),
+            text("stderr"),
+        );
+    }
+    LookupOptions {
+        state_root: root,
+        runner: Arc::new(runner),
+        ..host("no-such-host")
+    }
+}
+
+#[test]
+fn lookup_by_id() {
+    let cri = lookup_container_type("cri").unwrap();
+    for runtime in RUNTIMES {
+        let options = crictl(runtime);
+        cri.check_required_tools(&options).unwrap();
+        assert_eq!(
+            lookup(&*cri, NGINX, &options).unwrap(),
+            (4711, String::from("default/web/nginx")),
+            "crictl on {}",
+            runtime
+        );
+        // the pause container of the pod sandbox
+        assert_eq!(
+            lookup(&*cri, SANDBOX, &options).unwrap(),
+            (4690, String::from("default/web")),
+            "crictl on {}",
+            runtime
+        );
+        let res = lookup(&*cri, "0123456789ab", &options);
+        assert!(
+            matches!(res, Err(Error::NotFound(_))),
+            "crictl on {}: {:?}",
+            runtime,
+            res
+        );
+    }
+}
+
+#[test]
+fn lookup_by_name() {
+    let cri = lookup_container_type("cri").unwrap();
+    for runtime in RUNTIMES {
+        let options = crictl(runtime);
+        assert_eq!(
+            lookup(&*cri, "staging/web/nginx", &options).unwrap(),
+            (5120, String::from("staging/web/nginx")),
+            "crictl on {}",
+            runtime
+        );
+        let res = lookup(&*cri, "default/web/init-db", &options);
+        assert!(
+            matches!(res, Err(Error::NotRunning(_))),
+            "crictl on {}: {:?}",
+            runtime,
+            res
+        );
+        match lookup(&*cri, "web/nginx", &options) {
+            Err(Error::Ambiguous { candidates, .. }) => assert_eq!(
+                candidates,
+                vec![
+                    format!("default/web/nginx ({})", NGINX),
+                    format!("staging/web/nginx ({})", STAGING_NGINX),
+                ]
+            ),
+            res => panic!("crictl on {}: {:?}", runtime, res),
+        }
+    }
+}
+
+#[test]
+fn list_containers() {
+    let cri = lookup_container_type("cri").unwrap();
+    for runtime in RUNTIMES {
+        let options = crictl(runtime);
+        assert_eq!(
+            list(&*cri, &options),
+            vec![
+                (String::from(STAGING_NGINX), 5120),
+                (String::from(NGINX), 4711),
+            ],
+            "crictl on {}",
+            runtime
+        );
+    }
+}
+
+#[test]
+fn without_crictl() {
+    let cri = lookup_container_type("cri").unwrap();
+    let options = LookupOptions {
+        runner: Arc::new(ReplayRunner::new()),
+        ..crictl("cri-o")
+    };
+    let res = cri.check_required_tools(&options);
+    assert!(matches!(res, Err(Error::ToolUnavailable(_))), "{:?}", res);
+}
@@ -0,0 +1,306 @@
+mod common;
+
+use common::{fixture, host, list, lookup, replay, versions};
+use container_pid::{lookup_container_type, Error, LookupOptions};
+use serde_json::Value;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const WEB: &str = "3fad9eaa93eed1459630ea13ab29974faedbd1adf9c5f5ef1c1d2f5092111e98";
+const BLOG: &str = "5eee5668f99f25842895b62ffb0434615b580f9b3c2e1477461ac2916250a799";
+const API_1: &str = "8d043ca8a27eef30f2f28a611bca0e08ce6cb2f7eb5a2028574f724ccb740126";
+const API_2: &str = "8d416d82ef8710d51cd76c7f3cbbf8cee04075fc2f7649aeeeaf1425b063b331";
+
+#[test]
+fn lookup_by_name_and_id() {
+    let docker = lookup_container_type("docker").unwrap();
+    for version in versions("docker") {
+        let options = replay(&format!("docker/{}", version));
+        let web = (4311, String::from("web"));
+        assert_eq!(
+            lookup(&*docker, "web", &options).unwrap(),
+            web,
+            "docker {}",
+            version
+        );
+        assert_eq!(
+            lookup(&*docker, WEB, &options).unwrap(),
+            web,
+            "docker {}",
+            version
+        );
+        // `3f` also matches a stopped container
+        assert_eq!(
+            lookup(&*docker, "3f", &options).unwrap(),
+            web,
+            "docker {}",
+            version
+        );
+    }
+}
+
+#[test]
+fn lookup_ambiguous_prefix() {
+    let docker = lookup_container_type("docker").unwrap();
+    for version in versions("docker") {
+        let options = replay(&format!("docker/{}", version));
+        let res = lookup(&*docker, "8d", &options);
+        assert!(
+            matches!(res, Err(Error::Ambiguous { .. })),
+            "docker {}: {:?}",
+            version,
+            res
+        );
+    }
+}
+
+#[test]
+fn lookup_compose_service() {
+    let docker = lookup_container_type("docker").unwrap();
+    for version in versions("docker") {
+        let options = replay(&format!("docker/{}", version));
+        match lookup(&*docker, "shop/api", &options) {
+            Err(Error::Ambiguous { candidates, .. }) => assert_eq!(
+                candidates,
+                vec![
+                    format!("shop-api-1 ({})", &API_1[..12]),
+                    format!("shop-api-2 ({})", &API_2[..12]),
+                ]
+            ),
+            res => panic!("docker {}: {:?}", version, res),
+        }
+        let found = lookup(&*docker, "shop/api/2", &options).unwrap();
+        assert_eq!(
+            found,
+            (5188, String::from("shop-api-2")),
+            "docker {}",
+            version
+        );
+        // Compose v1 named the containers with underscores
+        let found = lookup(&*docker, "blog/web", &options).unwrap();
+        assert_eq!(
+            found,
+            (6240, String::from("blog_web_1")),
+            "docker {}",
+            version
+        );
+    }
+}
+
+#[test]
+fn lookup_stopped_and_missing_containers() {
+    let docker = lookup_container_type("docker").unwrap();
+    for version in versions("docker") {
+        let options = replay(&format!("docker/{}", version));
+        let res = lookup(&*docker, "db", &options);
+        assert!(
+            matches!(res, Err(Error::NotRunning(_))),
+            "docker {}: {:?}",
+            version,
+            res
+        );
+        let res = lookup(&*docker, "nope", &options);
+        assert!(
+            matches!(res, Err(Error::NotFound(_))),
+            "docker {}: {:?}",
+            version,
+            res
+        );
+    }
+}
+
+#[test]
+fn list_running_containers() {
+    let docker = lookup_container_type("docker").unwrap();
+    for version in versions("docker") {
+        let options = replay(&format!("docker/{}", version));
+        assert_eq!(
+            list(&*docker, &options),
+            vec![
+                (String::from(WEB), 4311),
+                (String::from(BLOG), 6240),
+                (String::from(API_1), 5102),
+                (String::from(API_2), 5188),
+            ],
+            "docker {}",
+            version
+        );
+    }
+}
+
+#[test]
+fn daemon_not_running() {
+    let docker = lookup_container_type("docker").unwrap();
+    // recorded from the docker 28.2 client without a daemon
+    let options = replay("docker/down");
+    let res = lookup(&*docker, "web", &options);
+    assert!(matches!(res, Err(Error::DaemonUnreachable(_))), "{:?}", res);
+    let res = lookup(&*docker, "3f", &options);
+    assert!(matches!(res, Err(Error::DaemonUnreachable(_))), "{:?}", res);
+}
+
+/// A state root of its own for a test that binds `/var/run/docker.sock`.
+fn state_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!(
+        "container-pid-docker-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("var/run")).unwrap();
+    root
+}
+
+/// Answers `GET /containers/json` and `GET /containers/{id or name}/json` from the documents in
+/// `tests/fixtures/docker/api`, until the test binary exits. Inspecting `failing` is answered
+/// with an internal server error. Returns the options and the paths requested so far.
+fn serve_engine_api(
+    name: &str,
+    failing: Option<&'static str>,
+) -> (LookupOptions, Arc<Mutex<Vec<String>>>) {
+    let read = |file: &str| -> Value {
+        serde_json::from_slice(&fs::read(fixture("docker/api").join(file)).unwrap()).unwrap()
+    };
+    let containers = read("containers.json");
+    let inspect = read("inspect.json");
+
+    let root = state_root(name);
+    let listener = UnixListener::bind(root.join("var/run/docker.sock")).unwrap();
+    let requests = Arc::new(Mutex::new(vec![]));
+    let requested = Arc::clone(&requests);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                assert!(
+                    n > 0,
+                    "client closed the connection before the request ended"
+                );
+                request.extend_from_slice(&buf[..n]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            let path = request
+                .strip_prefix("GET ")
+                .and_then(|rest| rest.split(' ').next())
+                .unwrap();
+            requested.lock().unwrap().push(path.to_string());
+            let id = path
+                .strip_prefix("/containers/")
+                .and_then(|rest| rest.strip_suffix("/json"));
+            let (status, body) = match (path, id) {
+                ("/containers/json?all=1", _) => ("200 OK", containers.clone()),
+                (_, Some(id)) if Some(id) == failing => (
+                    "500 Internal Server Error",
+                    serde_json::json!({ "message": "container state is corrupt" }),
+                ),
+                (_, Some(id)) => match inspect
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .find(|c| c["Id"] == id || c["Name"] == format!("/{}", id))
+                {
+                    Some(container) => ("200 OK", container.clone()),
+                    None => (
+                        "404 Not Found",
+                        serde_json::json!({ "message": format!("No such container: {}", id) }),
+                    ),
+                },
+                _ => panic!("unexpected request {}", path),
+            };
+            let body = body.to_string();
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+    let options = LookupOptions {
+        state_root: root,
+        ..host("no-such-host")
+    };
+    (options, requests)
+}
+
+#[test]
+fn lookup_over_api() {
+    let docker = lookup_container_type("docker").unwrap();
+    let (options, requests) = serve_engine_api("lookup", None);
+    let web = (4311, String::from("web"));
+    assert_eq!(lookup(&*docker, "web", &options).unwrap(), web);
+    assert_eq!(lookup(&*docker, WEB, &options).unwrap(), web);
+    // names and full IDs are inspected without listing the containers
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec![
+            String::from("/containers/web/json"),
+            format!("/containers/{}/json", WEB)
+        ]
+    );
+    assert_eq!(lookup(&*docker, "3f", &options).unwrap(), web);
+    let found = lookup(&*docker, "shop/api/2", &options).unwrap();
+    assert_eq!(found, (5188, String::from("shop-api-2")));
+    let res = lookup(&*docker, "8d", &options);
+    assert!(matches!(res, Err(Error::Ambiguous { .. })), "{:?}", res);
+    let res = lookup(&*docker, "db", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+    let res = lookup(&*docker, "nope", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+#[test]
+fn list_over_api() {
+    let docker = lookup_container_type("docker").unwrap();
+    let (options, _) = serve_engine_api("list", None);
+    assert_eq!(
+        list(&*docker, &options),
+        vec![
+            (String::from(WEB), 4311),
+            (String::from(BLOG), 6240),
+            (String::from(API_1), 5102),
+            (String::from(API_2), 5188),
+        ]
+    );
+}
+
+#[test]
+fn list_skips_failing_container() {
+    let docker = lookup_container_type("docker").unwrap();
+    let (options, _) = serve_engine_api("failing", Some(BLOG));
+    assert_eq!(
+        list(&*docker, &options),
+        vec![
+            (String::from(WEB), 4311),
+            (String::from(API_1), 5102),
+            (String::from(API_2), 5188),
+        ]
+    );
+    let res = lookup(&*docker, BLOG, &options);
+    assert!(matches!(res, Err(Error::CommandFailed(_))), "{:?}", res);
+}
+
+#[test]
+fn stale_api_socket() {
+    let docker = lookup_container_type("docker").unwrap();
+    let root = state_root("stale");
+    // the socket file outlives the daemon that bound it
+    drop(UnixListener::bind(root.join("var/run/docker.sock")).unwrap());
+    let options = LookupOptions {
+        state_root: root,
+        ..host("no-such-host")
+    };
+    let res = lookup(&*docker, "web", &options);
+    assert!(matches!(res, Err(Error::DaemonUnreachable(_))), "{:?}", res);
+    let res = lookup(&*docker, "3f", &options);
+    assert!(matches!(res, Err(Error::DaemonUnreachable(_))), "{:?}", res);
+}
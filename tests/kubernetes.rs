@@ -0,0 +1,172 @@
+mod common;
+
+use common::{fixture, host, list, lookup, replay, versions};
+use container_pid::{kubernetes_container, Container, Error, KubernetesOptions, LookupOptions};
+
+/// kubectl recordings of a cluster, seen from `node1` whose kubelet cgroups are in the fixtures.
+fn node1(version: &str) -> (Box<dyn Container>, LookupOptions) {
+    let kubernetes = kubernetes_container(KubernetesOptions {
+        node_name: Some(String::from("node1")),
+        ..KubernetesOptions::default()
+    });
+    let mut options = replay(&format!("kubernetes/{}", version));
+    options.cgroup_root = fixture("kubernetes/cgroup/v2-systemd");
+    (kubernetes, options)
+}
+
+/// Pod cgroups as the kubelet creates them with cgroup v1 and v2, with the systemd and the
+/// cgroupfs driver. The api container is missing from the v1 cgroupfs tree.
+const CGROUP_LAYOUTS: &[&str] = &["v1-cgroupfs", "v1-systemd", "v2-cgroupfs", "v2-systemd"];
+
+#[test]
+fn lookup_pod() {
+    for version in versions("kubernetes") {
+        let (kubernetes, options) = node1(&version);
+        let found = lookup(&*kubernetes, "default/web", &options).unwrap();
+        assert_eq!(
+            found,
+            (4711, String::from("default/web/nginx")),
+            "kubectl {}",
+            version
+        );
+        let found = lookup(&*kubernetes, "default/web/nginx", &options).unwrap();
+        assert_eq!(found.0, 4711, "kubectl {}", version);
+    }
+}
+
+#[test]
+fn lookup_deployment_pod_on_this_node() {
+    for version in versions("kubernetes") {
+        let (kubernetes, options) = node1(&version);
+        let found = lookup(&*kubernetes, "default/deploy/api", &options).unwrap();
+        assert_eq!(
+            found,
+            (5230, String::from("default/api-7d9c8b6f5-x2x4q/api")),
+            "kubectl {}",
+            version
+        );
+    }
+}
+
+#[test]
+fn lookup_missing_pod_and_container() {
+    for version in versions("kubernetes") {
+        let (kubernetes, options) = node1(&version);
+        let res = lookup(&*kubernetes, "default/nope", &options);
+        assert!(
+            matches!(res, Err(Error::NotFound(_))),
+            "kubectl {}: {:?}",
+            version,
+            res
+        );
+        let res = lookup(&*kubernetes, "default/web/redis", &options);
+        assert!(
+            matches!(res, Err(Error::NotFound(_))),
+            "kubectl {}: {:?}",
+            version,
+            res
+        );
+    }
+}
+
+#[test]
+fn list_pods_on_this_node() {
+    for version in versions("kubernetes") {
+        let (kubernetes, options) = node1(&version);
+        assert_eq!(
+            list(&*kubernetes, &options),
+            vec![
+                (String::from("default/api-7d9c8b6f5-x2x4q/api"), 5230),
+                (String::from("default/web/nginx"), 4711),
+            ],
+            "kubectl {}",
+            version
+        );
+    }
+}
+
+#[test]
+fn lookup_cgroup_layouts() {
+    for layout in CGROUP_LAYOUTS {
+        let (kubernetes, mut options) = node1("1.32");
+        options.cgroup_root = fixture(&format!("kubernetes/cgroup/{}", layout));
+        let found = lookup(&*kubernetes, "default/web/nginx", &options).unwrap();
+        assert_eq!(
+            found,
+            (4711, String::from("default/web/nginx")),
+            "{}",
+            layout
+        );
+    }
+}
+
+#[test]
+fn lookup_container_without_cgroup() {
+    let (kubernetes, mut options) = node1("1.32");
+    options.cgroup_root = fixture("kubernetes/cgroup/v1-cgroupfs");
+    let res = lookup(&*kubernetes, "default/deploy/api", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+/// A node without kubectl: containers are found from the log directories of the kubelet.
+fn offline() -> (Box<dyn Container>, LookupOptions) {
+    let kubernetes = kubernetes_container(KubernetesOptions::default());
+    (kubernetes, host("kubernetes/host"))
+}
+
+#[test]
+fn lookup_offline() {
+    let (kubernetes, options) = offline();
+    let found = lookup(&*kubernetes, "default/web/nginx", &options).unwrap();
+    assert_eq!(found, (4711, String::from("default/web/nginx")));
+    let found = lookup(&*kubernetes, "default/web/nginx-exporter", &options).unwrap();
+    assert_eq!(found, (4802, String::from("default/web/nginx-exporter")));
+}
+
+#[test]
+fn lookup_offline_needs_container_name() {
+    let (kubernetes, options) = offline();
+    let res = lookup(&*kubernetes, "default/web", &options);
+    assert!(matches!(res, Err(Error::Ambiguous { .. })), "{:?}", res);
+}
+
+#[test]
+fn lookup_offline_not_found() {
+    let (kubernetes, options) = offline();
+    let res = lookup(&*kubernetes, "default/web/redis", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+    let res = lookup(&*kubernetes, "kube-system/web/nginx", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+#[cfg(feature = "kube-api")]
+#[test]
+fn lookup_offline_with_credential_plugin() {
+    // the kubeconfig needs a credential plugin, which only kubectl runs
+    let kubernetes = kubernetes_container(KubernetesOptions {
+        kubeconfig: Some(fixture("kubernetes/exec-plugin/kubeconfig")),
+        ..KubernetesOptions::default()
+    });
+    let options = host("kubernetes/host");
+    let found = lookup(&*kubernetes, "default/web/nginx", &options).unwrap();
+    assert_eq!(found, (4711, String::from("default/web/nginx")));
+    assert_eq!(
+        list(&*kubernetes, &options),
+        vec![
+            (String::from("default/web/nginx"), 4711),
+            (String::from("default/web/nginx-exporter"), 4802),
+        ]
+    );
+}
+
+#[test]
+fn list_offline() {
+    let (kubernetes, options) = offline();
+    assert_eq!(
+        list(&*kubernetes, &options),
+        vec![
+            (String::from("default/web/nginx"), 4711),
+            (String::from("default/web/nginx-exporter"), 4802),
+        ]
+    );
+}
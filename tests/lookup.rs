@@ -0,0 +1,82 @@
+mod common;
+
+use common::{fixture, host, replay};
+use container_pid::{
+    list_containers_with_options, lookup_container_pid_with_options, lookup_container_type,
+    Container, ContainerProcess, Error, LookupOptions, Operation, ReplayRunner,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A runtime that knows every container, but must not be asked.
+#[derive(Debug)]
+struct Unasked;
+
+impl Container for Unasked {
+    fn name(&self) -> &'static str {
+        "unasked"
+    }
+
+    fn lookup(&self, id: &str, _: &LookupOptions) -> Result<ContainerProcess, Error> {
+        panic!("'{}' was looked up after an ambiguous match", id);
+    }
+
+    fn list(&self, _: &LookupOptions) -> Result<Vec<ContainerProcess>, Error> {
+        Ok(vec![])
+    }
+
+    fn check_required_tools(&self, _: &LookupOptions) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn ambiguous_match_ends_the_lookup() {
+    let types = vec![lookup_container_type("docker").unwrap(), Box::new(Unasked)];
+    let options = replay("docker/28.2");
+    let err = lookup_container_pid_with_options("8d", &types, &options).unwrap_err();
+    assert_eq!(
+        err.operation,
+        Operation::Lookup {
+            query: String::from("8d")
+        }
+    );
+    match err.errors.as_slice() {
+        [("docker", Error::Ambiguous { candidates, .. })] => assert_eq!(candidates.len(), 2),
+        errors => panic!("{:?}", errors),
+    }
+}
+
+#[test]
+fn empty_query_is_reported_as_lookup() {
+    let types = vec![lookup_container_type("docker").unwrap()];
+    let options = host("no-such-host");
+    let err = lookup_container_pid_with_options("", &types, &options).unwrap_err();
+    assert!(
+        err.to_string().starts_with("failed to find container ''"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn list_each_container_once() {
+    let types = vec![
+        lookup_container_type("docker").unwrap(),
+        lookup_container_type("oci").unwrap(),
+    ];
+    // the web container of dockerd, whose runc state is also searched by the oci backend
+    let options = LookupOptions {
+        oci_roots: vec![PathBuf::from("/run/docker/runtime-runc/moby")],
+        runner: Arc::new(ReplayRunner::load(fixture("docker/28.2")).unwrap()),
+        ..host("oci/managed")
+    };
+    let containers = list_containers_with_options(&types, &options).unwrap();
+    let web: Vec<(&str, &str)> = containers
+        .iter()
+        .filter(|c| c.pid == 4311)
+        .map(|c| (c.runtime, c.name.as_str()))
+        .collect();
+    assert_eq!(web, vec![("docker", "web")]);
+    assert_eq!(containers.len(), 4);
+}
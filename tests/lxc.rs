@@ -0,0 +1,54 @@
+mod common;
+
+use common::{list, lookup, replay};
+use container_pid::{lookup_container_type, Error, ReplayRunner};
+use std::sync::Arc;
+
+/// Hand-written `lxc-info` and `lxc-ls` output of a host with two running containers and a
+/// stopped one.
+const RECORDINGS: &str = "lxc/cli";
+
+#[test]
+fn lookup_running_container() {
+    let lxc = lookup_container_type("lxc").unwrap();
+    let options = replay(RECORDINGS);
+    let found = lookup(&*lxc, "web", &options).unwrap();
+    assert_eq!(found, (2841, String::from("web")));
+}
+
+#[test]
+fn lookup_stopped_container() {
+    let lxc = lookup_container_type("lxc").unwrap();
+    let options = replay(RECORDINGS);
+    let res = lookup(&*lxc, "db", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+}
+
+#[test]
+fn lookup_missing_container() {
+    let lxc = lookup_container_type("lxc").unwrap();
+    let options = replay(RECORDINGS);
+    let res = lookup(&*lxc, "nope", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+#[test]
+fn list_running_containers() {
+    let lxc = lookup_container_type("lxc").unwrap();
+    let options = replay(RECORDINGS);
+    assert_eq!(
+        list(&*lxc, &options),
+        vec![(String::from("proxy"), 3017), (String::from("web"), 2841)]
+    );
+}
+
+#[test]
+fn missing_tools() {
+    let lxc = lookup_container_type("lxc").unwrap();
+    let mut options = replay(RECORDINGS);
+    options.runner = Arc::new(ReplayRunner::new());
+    assert!(matches!(
+        lxc.check_required_tools(&options),
+        Err(Error::ToolUnavailable(_))
+    ));
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{host, list, lookup, replay};
+use container_pid::{lookup_container_type, Error, LookupOptions};
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixListener;
+use std::thread;
+
+/// Hand-written `lxc info` and `lxc list` output of an LXD host with containers and a virtual
+/// machine.
+const RECORDINGS: &str = "lxd/cli";
+
+#[test]
+fn lookup_running_container() {
+    let lxd = lookup_container_type("lxd").unwrap();
+    let options = replay(RECORDINGS);
+    let found = lookup(&*lxd, "web", &options).unwrap();
+    assert_eq!(found, (4120, String::from("web")));
+}
+
+#[test]
+fn lookup_uppercase_fields() {
+    let lxd = lookup_container_type("lxd").unwrap();
+    // `lxc info` output with `PID:` and `Status: RUNNING`
+    let options = replay("lxd/uppercase");
+    let found = lookup(&*lxd, "web", &options).unwrap();
+    assert_eq!(found, (4120, String::from("web")));
+    let res = lookup(&*lxd, "db", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+}
+
+#[test]
+fn lookup_container_of_project() {
+    let lxd = lookup_container_type("lxd").unwrap();
+    let options = replay(RECORDINGS);
+    let found = lookup(&*lxd, "staging/web", &options).unwrap();
+    assert_eq!(found, (6822, String::from("web")));
+}
+
+#[test]
+fn lookup_stopped_container() {
+    let lxd = lookup_container_type("lxd").unwrap();
+    let options = replay(RECORDINGS);
+    let res = lookup(&*lxd, "db", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+}
+
+#[test]
+fn lookup_virtual_machine() {
+    let lxd = lookup_container_type("lxd").unwrap();
+    let options = replay(RECORDINGS);
+    let res = lookup(&*lxd, "win", &options);
+    assert!(matches!(res, Err(Error::Unsupported(_))), "{:?}", res);
+}
+
+#[test]
+fn lookup_missing_container() {
+    let lxd = lookup_container_type("lxd").unwrap();
+    let options = replay(RECORDINGS);
+    let res = lookup(&*lxd, "nope", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+#[test]
+fn list_running_containers() {
+    let lxd = lookup_container_type("lxd").unwrap();
+    let options = replay(RECORDINGS);
+    // virtual machines are skipped, ephemeral containers are not
+    assert_eq!(
+        list(&*lxd, &options),
+        vec![(String::from("ci"), 4388), (String::from("web"), 4120)]
+    );
+}
+
+#[test]
+fn list_skips_dead_socket() {
+    let lxd = lookup_container_type("lxd").unwrap();
+    let root = env::temp_dir().join(format!("container-pid-lxd-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("var/snap/lxd/common/lxd")).unwrap();
+    fs::create_dir_all(root.join("var/lib/lxd")).unwrap();
+    // the socket of a removed deb install is left behind next to the snap
+    drop(UnixListener::bind(root.join("var/lib/lxd/unix.socket")).unwrap());
+
+    let listener = UnixListener::bind(root.join("var/snap/lxd/common/lxd/unix.socket")).unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                assert!(
+                    n > 0,
+                    "client closed the connection before the request ended"
+                );
+                request.extend_from_slice(&buf[..n]);
+            }
+            assert!(request.starts_with(b"GET /1.0/instances?recursion=2&all-projects=true "));
+            let body = r#"{"type":"sync","status":"Success","status_code":200,"metadata":[{"name":"web","project":"default","type":"container","status":"Running","state":{"pid":4120}}]}"#;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    let options = LookupOptions {
+        state_root: root,
+        ..host("no-such-host")
+    };
+    assert_eq!(list(&*lxd, &options), vec![(String::from("web"), 4120)]);
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use common::{list, lookup, replay};
+use container_pid::{lookup_container_type, Error};
+
+/// Hand-written `machinectl` output of a host with two containers and a virtual machine.
+const RECORDINGS: &str = "nspawn/machinectl";
+
+#[test]
+fn lookup_machine() {
+    let nspawn = lookup_container_type("nspawn").unwrap();
+    let options = replay(RECORDINGS);
+    let found = lookup(&*nspawn, "web", &options).unwrap();
+    assert_eq!(found, (9120, String::from("web")));
+}
+
+#[test]
+fn lookup_missing_machine() {
+    let nspawn = lookup_container_type("nspawn").unwrap();
+    let options = replay(RECORDINGS);
+    let res = lookup(&*nspawn, "nope", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+#[test]
+fn list_containers() {
+    let nspawn = lookup_container_type("nspawn").unwrap();
+    let options = replay(RECORDINGS);
+    // virtual machines are skipped
+    assert_eq!(
+        list(&*nspawn, &options),
+        vec![(String::from("build"), 9377), (String::from("web"), 9120)]
+    );
+}
+
+#[test]
+fn lookup_virtual_machine() {
+    let nspawn = lookup_container_type("nspawn").unwrap();
+    let options = replay(RECORDINGS);
+    let res = lookup(&*nspawn, "win11", &options);
+    assert!(matches!(res, Err(Error::Unsupported(_))), "{:?}", res);
+}
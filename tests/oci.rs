@@ -0,0 +1,114 @@
+mod common;
+
+use common::{host, list, lookup};
+use container_pid::{lookup_container_type, Error, LookupOptions};
+use std::path::PathBuf;
+
+const HOST: &str = "oci/host";
+
+const DOCKER_WEB: &str = "3fad9eaa93eed1459630ea13ab29974faedbd1adf9c5f5ef1c1d2f5092111e98";
+
+#[test]
+fn lookup_runtimes() {
+    let oci = lookup_container_type("oci").unwrap();
+    let options = host(HOST);
+    oci.check_required_tools(&options).unwrap();
+    // runc, crun, youki and runsc state formats
+    for (id, pid) in &[
+        ("web", 5151),
+        ("db", 5252),
+        ("cache", 5353),
+        ("gvisor", 5757),
+    ] {
+        let (found, name) = lookup(&*oci, id, &options).unwrap();
+        assert_eq!((found, name.as_str()), (*pid, *id));
+    }
+}
+
+#[test]
+fn lookup_not_running() {
+    let oci = lookup_container_type("oci").unwrap();
+    let options = host(HOST);
+    // the recorded start time no longer matches the process
+    let res = lookup(&*oci, "stale", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+    let res = lookup(&*oci, "stopped", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+    let res = lookup(&*oci, "batch", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+    // youki: the state says stopped while the PID is in use
+    let res = lookup(&*oci, "halted", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+    // youki: the process started long after the container was created
+    let res = lookup(&*oci, "recycled", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+    let res = lookup(&*oci, "nope", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+    let res = lookup(&*oci, "..", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+#[test]
+fn list_running() {
+    let oci = lookup_container_type("oci").unwrap();
+    assert_eq!(
+        list(&*oci, &host(HOST)),
+        vec![
+            (String::from("cache"), 5353),
+            (String::from("db"), 5252),
+            (String::from("gvisor"), 5757),
+            (String::from("web"), 5151),
+        ]
+    );
+}
+
+#[test]
+fn no_state_directory() {
+    let oci = lookup_container_type("oci").unwrap();
+    let res = oci.check_required_tools(&host("no-such-host"));
+    assert!(matches!(res, Err(Error::ToolUnavailable(_))), "{:?}", res);
+}
+
+#[test]
+fn lookup_session_state() {
+    let oci = lookup_container_type("oci").unwrap();
+    // the runtime directory of the session is resolved against the state root
+    let options = LookupOptions {
+        runtime_dir: Some(PathBuf::from("/run/user/1000")),
+        ..host("oci/rootless")
+    };
+    let found = lookup(&*oci, "app", &options).unwrap();
+    assert_eq!(found, (5656, String::from("app")));
+}
+
+#[test]
+fn lookup_custom_root() {
+    let oci = lookup_container_type("oci").unwrap();
+    let options = host("oci/custom");
+    let res = lookup(&*oci, "job", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+    // like the default roots, the custom ones are resolved against the state root
+    let options = LookupOptions {
+        oci_roots: vec![PathBuf::from("/var/lib/ci/runc")],
+        ..options
+    };
+    let found = lookup(&*oci, "job", &options).unwrap();
+    assert_eq!(found, (5959, String::from("job")));
+    assert_eq!(list(&*oci, &options), vec![(String::from("job"), 5959)]);
+}
+
+#[test]
+fn skip_managed_roots() {
+    let oci = lookup_container_type("oci").unwrap();
+    let options = host("oci/managed");
+    // the runc root of dockerd belongs to the docker backend
+    let res = lookup(&*oci, DOCKER_WEB, &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+    assert_eq!(list(&*oci, &options), vec![]);
+    let options = LookupOptions {
+        oci_roots: vec![PathBuf::from("/run/docker/runtime-runc/moby")],
+        ..options
+    };
+    let found = lookup(&*oci, DOCKER_WEB, &options).unwrap();
+    assert_eq!(found.0, 4311);
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::fixture;
+use container_pid::{lookup_container_type, Error, LookupOptions};
+
+#[test]
+fn open_own_process() {
+    let options = LookupOptions::default();
+    let process_id = lookup_container_type("process_id").unwrap();
+    let process = process_id
+        .lookup(&std::process::id().to_string(), &options)
+        .unwrap();
+    assert!(process.start_time.is_some());
+
+    let handle = process.open_with_options(&options).unwrap();
+    assert_eq!(handle.pid(), process.pid);
+    assert_eq!(Some(handle.start_time()), process.start_time);
+    handle.validate().unwrap();
+}
+
+#[test]
+fn open_without_start_time() {
+    // the fixture has a cmdline but no stat, as if the process exited right after the lookup
+    let options = LookupOptions {
+        proc_root: fixture("pidfd/proc"),
+        ..LookupOptions::default()
+    };
+    let process_id = lookup_container_type("process_id").unwrap();
+    let process = process_id.lookup("42", &options).unwrap();
+    assert_eq!(process.start_time, None);
+    let res = process.open_with_options(&options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+}
+
+#[test]
+fn open_in_other_namespace() {
+    let options = LookupOptions {
+        proc_root: fixture("command/proc"),
+        ..LookupOptions::default()
+    };
+    let command = lookup_container_type("command").unwrap();
+    let process = command.lookup("supervisord", &options).unwrap();
+    assert!(process.start_time.is_some());
+    let res = process.open_with_options(&options);
+    assert!(matches!(res, Err(Error::Unsupported(_))), "{:?}", res);
+}
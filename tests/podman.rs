@@ -0,0 +1,92 @@
+mod common;
+
+use common::{host, list, lookup, replay};
+use container_pid::{lookup_container_type, Error, LookupOptions};
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::thread;
+
+/// Hand-written `podman ps` and `podman inspect` output of a host with two running containers
+/// and a stopped one.
+const RECORDINGS: &str = "podman/cli";
+
+#[test]
+fn lookup_running_container() {
+    let podman = lookup_container_type("podman").unwrap();
+    let options = replay(RECORDINGS);
+    let found = lookup(&*podman, "web", &options).unwrap();
+    assert_eq!(found, (5120, String::from("web")));
+}
+
+#[test]
+fn lookup_stopped_and_missing_containers() {
+    let podman = lookup_container_type("podman").unwrap();
+    let options = replay(RECORDINGS);
+    let res = lookup(&*podman, "db", &options);
+    assert!(matches!(res, Err(Error::NotRunning(_))), "{:?}", res);
+    let res = lookup(&*podman, "nope", &options);
+    assert!(matches!(res, Err(Error::NotFound(_))), "{:?}", res);
+}
+
+#[test]
+fn list_running_containers() {
+    let podman = lookup_container_type("podman").unwrap();
+    let options = replay(RECORDINGS);
+    let pids: Vec<i32> = list(&*podman, &options)
+        .into_iter()
+        .map(|(_, pid)| pid)
+        .collect();
+    assert_eq!(pids, vec![5377, 5120]);
+}
+
+#[test]
+fn list_skips_dead_socket() {
+    let podman = lookup_container_type("podman").unwrap();
+    let root = env::temp_dir().join(format!("container-pid-podman-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    // a UID without passwd entry, it is not listed again as another user when running as root
+    let session = root.join("run/user/64999/podman");
+    fs::create_dir_all(&session).unwrap();
+    fs::create_dir_all(root.join("run/podman")).unwrap();
+    // the rootful service is gone, but its socket file is left behind
+    drop(UnixListener::bind(root.join("run/podman/podman.sock")).unwrap());
+
+    let listener = UnixListener::bind(session.join("podman.sock")).unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                assert!(
+                    n > 0,
+                    "client closed the connection before the request ended"
+                );
+                request.extend_from_slice(&buf[..n]);
+            }
+            assert!(request.starts_with(b"GET /v3.0.0/libpod/containers/json HTTP/1.1\r\n"));
+            let body = r#"[{"Id":"4b1f0c6d8e2a","Names":["web"],"State":"running","Pid":5120}]"#;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    let options = LookupOptions {
+        state_root: root,
+        runtime_dir: Some(PathBuf::from("/run/user/64999")),
+        ..host("no-such-host")
+    };
+    assert_eq!(
+        list(&*podman, &options),
+        vec![(String::from("4b1f0c6d8e2a"), 5120)]
+    );
+}